@@ -0,0 +1,214 @@
+///
+/// How a sample is acquired, several reads of the sensor reduced to one value per channel.
+///
+/// Each reduced channel is followed by a `spread` channel, `<name>_spread` for channels other
+/// than `value`, telling how much the reads varied. Reads that fail once the sensor's retries
+/// are used up are left out as long as one succeeds, the reduced channels are then marked
+/// suspect.
+///
+use std::time::Duration;
+use crate::sample::{Channel, Status, Value};
+use crate::sensor::Error as SensorError;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reduction {
+    /// Spread is the standard deviation.
+    Mean,
+    /// Spread is the median absolute deviation.
+    Median,
+    /// Mean without the given fraction of lowest and of highest reads, spread is the standard
+    /// deviation of the remaining reads.
+    TrimmedMean(f64),
+    /// Most common value, for levels and flags. Spread is the fraction of reads disagreeing.
+    Majority,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Acquisition {
+    /// Reads per sample.
+    pub samples: u32,
+    /// Time between reads, waited for without holding the GPIO or blocking the runtime.
+    pub spacing: Duration,
+    pub reduction: Reduction,
+}
+
+impl Default for Acquisition {
+    /// One read per sample, published as read.
+    fn default() -> Self {
+        Acquisition { samples: 1, spacing: Duration::from_millis(0), reduction: Reduction::Mean }
+    }
+}
+
+impl Acquisition {
+    /// Reduces the reads of one sample, see the module documentation. A single read is
+    /// returned as read.
+    pub fn reduce(&self, reads: Vec<Result<Vec<Channel>, SensorError>>) -> Result<Vec<Channel>, SensorError> {
+        let attempts = reads.len();
+        let mut ok_reads = Vec::with_capacity(attempts);
+        let mut last_error = None;
+        for read in reads {
+            match read {
+                Ok(channels) => ok_reads.push(channels),
+                Err(err) => last_error = Some(err)
+            }
+        }
+        let reads = ok_reads;
+        let failed = attempts - reads.len();
+        let first = match (reads.as_slice(), last_error) {
+            ([only], None) => return Ok(only.clone()),
+            ([], Some(err)) => return Err(err),
+            ([], None) => return Ok(Vec::new()),
+            (reads, _) => reads[0].clone()
+        };
+        let mut channels = Vec::with_capacity(first.len() * 2);
+        for channel in first {
+            let values: Vec<Value> = reads
+                .iter()
+                .filter_map(|read| read.iter().find(|c| c.name == channel.name).map(|c| c.value))
+                .collect();
+            let (value, spread) = self.reduce_values(&values);
+            let status = match reads.iter().flat_map(|read| read.iter()).find(|c| c.name == channel.name && !c.status.is_ok()) {
+                Some(c) => c.status.clone(),
+                None if failed > 0 => Status::Suspect(format!("{} of {} reads failed", failed, attempts)),
+                None => Status::Ok
+            };
+            let spread_name = spread_name(&channel.name);
+            let spread = match &channel.unit {
+                Some(unit) if self.reduction != Reduction::Majority => Channel::new(&spread_name, spread).with_unit(unit),
+                _ => Channel::new(&spread_name, spread)
+            };
+            channels.push(Channel { value, status, ..channel });
+            channels.push(spread);
+        }
+        Ok(channels)
+    }
+
+    /// Reduced value and spread of a channel's reads.
+    fn reduce_values(&self, values: &[Value]) -> (Value, f64) {
+        let mut numbers: Vec<f64> = values.iter().map(|v| v.as_f64()).collect();
+        numbers.sort_by(f64::total_cmp);
+        match self.reduction {
+            Reduction::Mean => (Value::Float(mean(&numbers)), std_dev(&numbers)),
+            Reduction::Median => {
+                let median = median(&numbers);
+                let mut deviations: Vec<f64> = numbers.iter().map(|n| (n - median).abs()).collect();
+                deviations.sort_by(f64::total_cmp);
+                (Value::Float(median), self::median(&deviations))
+            },
+            Reduction::TrimmedMean(fraction) => {
+                let trim = (numbers.len() as f64 * fraction).floor() as usize;
+                let kept = match numbers.len() > trim * 2 {
+                    true => &numbers[trim..numbers.len() - trim],
+                    false => &numbers[..]
+                };
+                (Value::Float(mean(kept)), std_dev(kept))
+            },
+            Reduction::Majority => {
+                // Ties go to the value read first.
+                let mut counts: Vec<(Value, usize)> = Vec::new();
+                for value in values {
+                    match counts.iter_mut().find(|(v, _)| v == value) {
+                        Some((_, count)) => *count += 1,
+                        None => counts.push((*value, 1))
+                    }
+                }
+                let (value, count) = counts.iter().fold(counts[0], |best, c| if c.1 > best.1 { *c } else { best });
+                (value, (values.len() - count) as f64 / values.len() as f64)
+            },
+        }
+    }
+}
+
+/// Name of the spread channel of channel `name`.
+pub fn spread_name(name: &str) -> String {
+    match name {
+        "value" => "spread".to_string(),
+        name => format!("{}_spread", name)
+    }
+}
+
+fn mean(numbers: &[f64]) -> f64 {
+    numbers.iter().sum::<f64>() / numbers.len() as f64
+}
+
+/// Sample standard deviation, 0 for a single number.
+fn std_dev(numbers: &[f64]) -> f64 {
+    if numbers.len() < 2 {
+        return 0.0;
+    }
+    let mean = mean(numbers);
+    (numbers.iter().map(|n| (n - mean).powi(2)).sum::<f64>() / (numbers.len() - 1) as f64).sqrt()
+}
+
+/// Median of sorted numbers.
+fn median(sorted: &[f64]) -> f64 {
+    let mid = sorted.len() / 2;
+    match sorted.len() % 2 {
+        0 => (sorted[mid - 1] + sorted[mid]) / 2.0,
+        _ => sorted[mid],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dht22_sensor::Error as Dht22Error;
+
+    fn acquisition(samples: u32, reduction: Reduction) -> Acquisition {
+        Acquisition { samples, spacing: Duration::from_millis(0), reduction }
+    }
+
+    fn read(value: f64) -> Result<Vec<Channel>, SensorError> {
+        Ok(vec![Channel::new("value", value).with_unit("V")])
+    }
+
+    fn failed() -> Result<Vec<Channel>, SensorError> {
+        Err(SensorError::from(Dht22Error::Incomplete { bits: 31 }))
+    }
+
+    #[test]
+    fn single_read_is_published_as_read() {
+        let channels = Acquisition::default().reduce(vec![read(1.5)]).unwrap();
+        assert_eq!(channels, vec![Channel::new("value", 1.5).with_unit("V")]);
+        assert!(Acquisition::default().reduce(vec![failed()]).is_err());
+    }
+
+    #[test]
+    fn reduces_with_spread() {
+        let reads = || vec![read(1.0), read(2.0), read(9.0)];
+        let mean = acquisition(3, Reduction::Mean).reduce(reads()).unwrap();
+        assert_eq!(mean[0], Channel::new("value", 4.0).with_unit("V"));
+        assert_eq!(mean[1], Channel::new("spread", 19f64.sqrt()).with_unit("V"));
+        let median = acquisition(3, Reduction::Median).reduce(reads()).unwrap();
+        assert_eq!(median, vec![Channel::new("value", 2.0).with_unit("V"), Channel::new("spread", 1.0).with_unit("V")]);
+        let trimmed = acquisition(3, Reduction::TrimmedMean(0.34)).reduce(reads()).unwrap();
+        assert_eq!(trimmed[0].value, Value::Float(2.0));
+    }
+
+    #[test]
+    fn majority_spread_is_the_fraction_disagreeing() {
+        let level = |high| Ok(vec![Channel::new("value", Value::Bool(high))]);
+        let channels = acquisition(4, Reduction::Majority)
+            .reduce(vec![level(true), level(false), level(true), level(true)])
+            .unwrap();
+        assert_eq!(channels, vec![Channel::new("value", true), Channel::new("spread", 0.25)]);
+    }
+
+    #[test]
+    fn failed_reads_mark_the_sample_suspect() {
+        let channels = acquisition(3, Reduction::Mean).reduce(vec![read(1.0), failed(), read(3.0)]).unwrap();
+        assert_eq!(channels[0].value, Value::Float(2.0));
+        assert_eq!(channels[0].status, Status::Suspect("1 of 3 reads failed".to_string()));
+        assert!(matches!(
+            acquisition(2, Reduction::Mean).reduce(vec![failed(), failed()]),
+            Err(SensorError::Dht22(Dht22Error::Incomplete { bits: 31 }))
+        ));
+    }
+
+
+    #[test]
+    fn reduces_nan_reads_without_panicking() {
+        let channels = acquisition(3, Reduction::Median).reduce(vec![read(f64::NAN), read(1.0), read(2.0)]).unwrap();
+        assert_eq!(channels[0].value, Value::Float(2.0));
+    }
+}
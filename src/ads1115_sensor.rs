@@ -0,0 +1,397 @@
+///
+/// ADS1115 16 bit I2C ADC, read with single-shot conversions.
+/// https://www.ti.com/lit/ds/symlink/ads1115.pdf
+///
+use std::time::Duration;
+use failure::Error as FailureError;
+use crate::gpio::GpioBackend;
+use crate::i2c::{self, I2cBus};
+use crate::sample::{Channel, Status};
+use crate::sensor::{Sensor, Error};
+use crate::sensor_config::SensorConfig;
+use crate::sensor_registry::SensorContext;
+
+const REG_CONVERSION: u8 = 0x00;
+const REG_CONFIG: u8 = 0x01;
+
+/// Start a conversion when written, conversion done when read.
+const CONFIG_OS: u16 = 1 << 15;
+const CONFIG_MODE_SINGLE_SHOT: u16 = 1 << 8;
+/// Comparator disabled, the alert pin stays high impedance.
+const CONFIG_COMP_QUE_DISABLE: u16 = 0b11;
+
+/// Input multiplexer setting, `MUX` in the config register.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Input {
+    /// AIN0 to AIN3 against ground.
+    SingleEnded(u8),
+    /// AIN0 - AIN1.
+    Diff01,
+    /// AIN0 - AIN3.
+    Diff03,
+    /// AIN1 - AIN3.
+    Diff13,
+    /// AIN2 - AIN3.
+    Diff23,
+}
+
+impl Input {
+    /// Differential pairs are numbered in datasheet order, 0-1, 0-3, 1-3 and 2-3.
+    pub fn from_channel(channel: u8, differential: bool) -> Option<Input> {
+        match (channel, differential) {
+            (0..=3, false) => Some(Input::SingleEnded(channel)),
+            (0, true) => Some(Input::Diff01),
+            (1, true) => Some(Input::Diff03),
+            (2, true) => Some(Input::Diff13),
+            (3, true) => Some(Input::Diff23),
+            _ => None
+        }
+    }
+
+    fn bits(self) -> u16 {
+        match self {
+            Input::Diff01 => 0b000,
+            Input::Diff03 => 0b001,
+            Input::Diff13 => 0b010,
+            Input::Diff23 => 0b011,
+            Input::SingleEnded(channel) => 0b100 | u16::from(channel & 0b11),
+        }
+    }
+}
+
+/// Programmable gain amplifier setting, `PGA` in the config register, as full scale range.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Gain {
+    V6_144,
+    V4_096,
+    V2_048,
+    V1_024,
+    V0_512,
+    V0_256,
+}
+
+impl Gain {
+    const ALL: [Gain; 6] = [Gain::V6_144, Gain::V4_096, Gain::V2_048, Gain::V1_024, Gain::V0_512, Gain::V0_256];
+
+    pub fn from_full_scale(volts: f64) -> Option<Gain> {
+        Gain::ALL.iter().cloned().find(|gain| (gain.full_scale() - volts).abs() < 0.0005)
+    }
+
+    pub fn full_scale(self) -> f64 {
+        match self {
+            Gain::V6_144 => 6.144,
+            Gain::V4_096 => 4.096,
+            Gain::V2_048 => 2.048,
+            Gain::V1_024 => 1.024,
+            Gain::V0_512 => 0.512,
+            Gain::V0_256 => 0.256,
+        }
+    }
+
+    fn bits(self) -> u16 {
+        Gain::ALL.iter().position(|gain| *gain == self).unwrap() as u16
+    }
+}
+
+/// Samples per second, `DR` in the config register.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DataRate(u16);
+
+impl DataRate {
+    const RATES: [u16; 8] = [8, 16, 32, 64, 128, 250, 475, 860];
+
+    pub fn from_sps(sps: u16) -> Option<DataRate> {
+        DataRate::RATES.iter().position(|rate| *rate == sps).map(|i| DataRate(i as u16))
+    }
+
+    pub fn sps(self) -> u16 {
+        DataRate::RATES[self.0 as usize]
+    }
+
+    fn bits(self) -> u16 {
+        self.0
+    }
+
+    /// Time of one conversion, the internal oscillator may run up to 10% slow.
+    fn conversion_time(self) -> Duration {
+        Duration::from_micros(1_100_000 / u64::from(self.sps()))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Output {
+    Volts,
+    /// Conversion result, -32768 to 32767 at full scale.
+    Raw,
+}
+
+pub struct Ads1115Sensor {
+    i2c: Box<dyn I2cBus + Send>,
+    address: u8,
+    input: Input,
+    gain: Gain,
+    data_rate: DataRate,
+    output: Output,
+}
+
+impl<G: GpioBackend> Sensor<G> for Ads1115Sensor {
+    /// Checks that the ADC answers, conversions are configured by each `prepare`.
+    fn init(&self, _gpio: &mut G) -> Result<(), Error> {
+        self.read_register(REG_CONFIG)?;
+        Ok(())
+    }
+
+    fn clear(&self, _gpio: &mut G) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Starts a single-shot conversion, the result is ready after the conversion time.
+    fn prepare(&self, _gpio: &mut G) -> Result<Option<Duration>, Error> {
+        let config = self.config();
+        self.i2c.write_register(self.address, REG_CONFIG, &config.to_be_bytes())?;
+        Ok(Some(self.data_rate.conversion_time()))
+    }
+
+    fn read(&self, _gpio: &mut G) -> Result<Vec<Channel>, Error> {
+        let raw = self.conversion()?;
+        let channel = match self.output {
+            Output::Raw => Channel::new("value", i64::from(raw)),
+            Output::Volts => Channel::new("value", f64::from(raw) * self.gain.full_scale() / 32768.0).with_unit("V"),
+        };
+        // The input may be anywhere beyond the full scale range.
+        Ok(vec![match raw {
+            i16::MAX | i16::MIN => channel.with_status(Status::Suspect("Input at full scale".to_string())),
+            _ => channel
+        }])
+    }
+}
+
+impl Ads1115Sensor {
+    pub fn new(
+        i2c: Box<dyn I2cBus + Send>,
+        address: u8,
+        input: Input,
+        gain: Gain,
+        data_rate: DataRate,
+        output: Output
+    ) -> Self {
+        Ads1115Sensor { i2c, address, input, gain, data_rate, output }
+    }
+
+    fn config(&self) -> u16 {
+        CONFIG_OS
+            | self.input.bits() << 12
+            | self.gain.bits() << 9
+            | CONFIG_MODE_SINGLE_SHOT
+            | self.data_rate.bits() << 5
+            | CONFIG_COMP_QUE_DISABLE
+    }
+
+    /// Result of the conversion started by `prepare`, which should have finished by now.
+    fn conversion(&self) -> Result<i16, i2c::Error> {
+        if self.read_register(REG_CONFIG)? & CONFIG_OS == 0 {
+            return Err(i2c::Error::Timeout(self.address));
+        }
+        Ok(self.read_register(REG_CONVERSION)? as i16)
+    }
+
+    fn read_register(&self, register: u8) -> Result<u16, i2c::Error> {
+        let mut buf = [0u8; 2];
+        self.i2c.read_register(self.address, register, &mut buf)?;
+        Ok(u16::from(buf[0]) << 8 | u16::from(buf[1]))
+    }
+}
+
+/// Options are `channel` with `differential`, `full_scale` volts, `data_rate` in samples per
+/// second and `output`, either 'volts' or 'raw'.
+pub fn from_config<G: GpioBackend + Send + 'static>(config: &SensorConfig, _context: &SensorContext<G>)
+    -> Result<Box<dyn Sensor<G> + Send>, FailureError> {
+    Ok(Box::new(configured(config)?))
+}
+
+fn configured(config: &SensorConfig) -> Result<Ads1115Sensor, FailureError> {
+    let channel = config.option("channel", |toml| { toml.as_integer() }, "integer")?;
+    let differential = config.optional("differential", |toml| { toml.as_bool() }, "boolean")?
+        .unwrap_or(false);
+    let input = Some(channel)
+        .filter(|channel| (0..=3).contains(channel))
+        .and_then(|channel| Input::from_channel(channel as u8, differential))
+        .ok_or_else(|| config.invalid("channel", format!("Not a valid ads1115 channel: {}, expected 0 to 3", channel)))?;
+    let full_scale = config.optional("full_scale", |toml| { toml.as_float().or_else(|| toml.as_integer().map(|i| i as f64)) }, "number")?
+        .unwrap_or(2.048);
+    let gain = Gain::from_full_scale(full_scale).ok_or_else(|| config.invalid("full_scale", format!(
+        "Not a valid ads1115 full scale range: {}, expected 6.144, 4.096, 2.048, 1.024, 0.512 or 0.256",
+        full_scale
+    )))?;
+    let sps = config.optional("data_rate", |toml| { toml.as_integer() }, "integer")?
+        .unwrap_or(128);
+    let data_rate = Some(sps)
+        .filter(|sps| (1..=860).contains(sps))
+        .and_then(|sps| DataRate::from_sps(sps as u16))
+        .ok_or_else(|| config.invalid("data_rate", format!(
+            "Not a valid ads1115 data rate: {}, expected 8, 16, 32, 64, 128, 250, 475 or 860",
+            sps
+        )))?;
+    let output = match config.optional("output", |toml| { toml.as_str() }, "string")? {
+        None | Some("volts") => Output::Volts,
+        Some("raw") => Output::Raw,
+        Some(other) => return Err(config.invalid("output", format!("Unknown output '{}', expected 'volts' or 'raw'", other)))
+    };
+    let i2c_config = config.require(config.i2c.as_ref(), "i2c")?;
+    Ok(Ads1115Sensor::new(
+        i2c::open(i2c_config)?,
+        i2c_config.address,
+        input,
+        gain,
+        data_rate,
+        output
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::gpio::simulated::SimulatedGpio;
+    use crate::i2c::mock::MockI2c;
+    use crate::sensor_config::config_with;
+
+    const ADDRESS: u8 = 0x48;
+
+    /// Lets the test look at the mock the sensor owns.
+    struct Shared(Arc<MockI2c>);
+
+    impl I2cBus for Shared {
+        fn write(&self, address: u8, data: &[u8]) -> Result<(), i2c::Error> {
+            self.0.write(address, data)
+        }
+
+        fn read(&self, address: u8, buf: &mut [u8]) -> Result<(), i2c::Error> {
+            self.0.read(address, buf)
+        }
+    }
+
+    fn sensor(input: Input, gain: Gain, sps: u16, output: Output) -> (Ads1115Sensor, Arc<MockI2c>) {
+        let i2c = Arc::new(MockI2c::new());
+        i2c.add_device(ADDRESS);
+        let data_rate = DataRate::from_sps(sps).unwrap();
+        (Ads1115Sensor::new(Box::new(Shared(i2c.clone())), ADDRESS, input, gain, data_rate, output), i2c)
+    }
+
+    /// Converts once with the conversion register answering `raw`.
+    fn convert(adc: &Ads1115Sensor, i2c: &MockI2c, raw: i16) -> Result<Channel, Error> {
+        let mut gpio = SimulatedGpio::new();
+        adc.prepare(&mut gpio)?;
+        i2c.set_register(ADDRESS, REG_CONVERSION, &raw.to_be_bytes());
+        Ok(adc.read(&mut gpio)?.remove(0))
+    }
+
+    #[test]
+    fn assembles_config_word() {
+        // The datasheet's reset default, with a conversion started.
+        assert_eq!(sensor(Input::Diff01, Gain::V2_048, 128, Output::Raw).0.config(), 0x8583);
+        assert_eq!(sensor(Input::SingleEnded(3), Gain::V0_256, 860, Output::Raw).0.config(), 0xfbe3);
+        assert_eq!(sensor(Input::Diff23, Gain::V6_144, 8, Output::Raw).0.config(), 0xb103);
+    }
+
+    #[test]
+    fn numbers_inputs_in_datasheet_order() {
+        let inputs = (0..4).map(|channel| Input::from_channel(channel, true).unwrap().bits()).collect::<Vec<_>>();
+        assert_eq!(inputs, vec![0b000, 0b001, 0b010, 0b011]);
+        assert_eq!(Input::from_channel(2, false).unwrap().bits(), 0b110);
+        assert_eq!(Input::from_channel(4, false), None);
+        assert_eq!(Input::from_channel(4, true), None);
+    }
+
+    #[test]
+    fn maps_gains_and_data_rates() {
+        let gains = [6.144, 4.096, 2.048, 1.024, 0.512, 0.256].iter()
+            .map(|volts| Gain::from_full_scale(*volts).unwrap().bits())
+            .collect::<Vec<_>>();
+        assert_eq!(gains, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(Gain::from_full_scale(2.0), None);
+        assert_eq!(Gain::from_full_scale(5.0), None);
+
+        let rates = [8, 16, 32, 64, 128, 250, 475, 860].iter()
+            .map(|sps| DataRate::from_sps(*sps).unwrap().bits())
+            .collect::<Vec<_>>();
+        assert_eq!(rates, vec![0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(DataRate::from_sps(100), None);
+        assert_eq!(DataRate::from_sps(860).unwrap().sps(), 860);
+        assert_eq!(DataRate::from_sps(8).unwrap().conversion_time(), Duration::from_micros(137_500));
+    }
+
+    #[test]
+    fn starts_conversion_then_reads_result() {
+        let (adc, i2c) = sensor(Input::SingleEnded(0), Gain::V2_048, 128, Output::Volts);
+        let mut gpio = SimulatedGpio::new();
+        assert_eq!(adc.prepare(&mut gpio).unwrap(), Some(Duration::from_micros(8_593)));
+        assert_eq!(i2c.writes(), vec![(ADDRESS, vec![REG_CONFIG, 0xc5, 0x83])]);
+        i2c.set_register(ADDRESS, REG_CONVERSION, &[0x40, 0x00]);
+        assert_eq!(adc.read(&mut gpio).unwrap(), vec![Channel::new("value", 1.024).with_unit("V")]);
+    }
+
+    #[test]
+    fn times_out_while_converting() {
+        let (adc, i2c) = sensor(Input::SingleEnded(0), Gain::V2_048, 128, Output::Raw);
+        let mut gpio = SimulatedGpio::new();
+        adc.prepare(&mut gpio).unwrap();
+        // The ADC clears the OS bit while it is converting.
+        i2c.set_register(ADDRESS, REG_CONFIG, &[0x45, 0x83]);
+        match adc.read(&mut gpio) {
+            Err(Error::I2c(i2c::Error::Timeout(ADDRESS))) => (),
+            other => panic!("Expected a timeout, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn converts_signed_results() {
+        let (raw, i2c) = sensor(Input::Diff01, Gain::V4_096, 128, Output::Raw);
+        assert_eq!(convert(&raw, &i2c, -100).unwrap(), Channel::new("value", -100i64));
+        let (volts, i2c) = sensor(Input::Diff01, Gain::V4_096, 128, Output::Volts);
+        assert_eq!(convert(&volts, &i2c, -8192).unwrap(), Channel::new("value", -1.024).with_unit("V"));
+    }
+
+    #[test]
+    fn suspects_full_scale() {
+        let saturated = Status::Suspect("Input at full scale".to_string());
+        let (raw, i2c) = sensor(Input::Diff01, Gain::V4_096, 128, Output::Raw);
+        assert_eq!(convert(&raw, &i2c, i16::MAX).unwrap(), Channel::new("value", 32767i64).with_status(saturated.clone()));
+        assert_eq!(convert(&raw, &i2c, i16::MIN).unwrap(), Channel::new("value", -32768i64).with_status(saturated.clone()));
+        assert!(convert(&raw, &i2c, i16::MAX - 1).unwrap().status.is_ok());
+        let (volts, i2c) = sensor(Input::Diff01, Gain::V4_096, 128, Output::Volts);
+        assert_eq!(convert(&volts, &i2c, i16::MIN).unwrap().status, saturated);
+    }
+
+    fn configured_with(extra: &str) -> Result<Ads1115Sensor, FailureError> {
+        configured(&config_with("ads1115", &format!("i2c = {{ bus = 'mock', address = 0x48 }}\n{}", extra))?)
+    }
+
+    #[test]
+    fn parses_options() {
+        let adc = configured_with("channel = 3\ndifferential = true\nfull_scale = 0.256\ndata_rate = 860\noutput = 'raw'").unwrap();
+        assert_eq!(adc.input, Input::Diff23);
+        assert_eq!(adc.gain, Gain::V0_256);
+        assert_eq!(adc.data_rate, DataRate::from_sps(860).unwrap());
+        assert_eq!(adc.output, Output::Raw);
+        let adc = configured_with("channel = 1").unwrap();
+        assert_eq!(adc.input, Input::SingleEnded(1));
+        assert_eq!(adc.gain, Gain::V2_048);
+        assert_eq!(adc.data_rate, DataRate::from_sps(128).unwrap());
+        assert_eq!(adc.output, Output::Volts);
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        let error = |extra| configured_with(extra).err().unwrap().to_string();
+        assert!(error("channel = 4").contains("sensors.s1.channel"));
+        // Integers are full scale ranges too, just not valid ones.
+        assert!(error("channel = 0\nfull_scale = 2").contains("Not a valid ads1115 full scale range: 2"));
+        assert!(error("channel = 0\nfull_scale = 2.5").contains("sensors.s1.full_scale"));
+        assert!(error("channel = 0\ndata_rate = 100").contains("sensors.s1.data_rate"));
+        assert!(error("channel = 0\ndata_rate = 65544").contains("sensors.s1.data_rate"));
+        assert!(error("channel = 0\noutput = 'millivolts'").contains("sensors.s1.output"));
+        let unattached = configured(&config_with("ads1115", "channel = 0").unwrap()).err().unwrap().to_string();
+        assert!(unattached.contains("sensors.s1.i2c"));
+    }
+}
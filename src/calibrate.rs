@@ -0,0 +1,366 @@
+///
+/// Interactive calibration, `calibrate <sensor-id>`.
+///
+/// Takes readings with the probe at each reference point in turn, prints their statistics and
+/// writes a calibration through the means back into the sensors TOML. Readings are sampled as
+/// when publishing, so sensors waiting for a conversion or retrying a failed read are read alike. Only the calibration
+/// lines of the sensor are touched, so comments and layout of the file are kept.
+///
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use failure::Error as FailureError;
+use futures::stream::Stream;
+use tokio::runtime::current_thread::Runtime;
+use crate::calibration::{self, Calibration, Curve};
+use crate::gpio::GpioBackend;
+use crate::gpio::claims::ClaimedGpio;
+use crate::gpio::soc::Soc;
+use crate::sensor_config::{self, SensorConfig, SensorsConfig};
+use crate::sensor_registry::{SensorContext, SensorRegistry};
+use crate::sensor_sampler::SensorSampler;
+
+#[derive(Debug)]
+pub enum Error {
+    UnknownSensor(String),
+    /// The sensor's samples have no channel of the name to calibrate.
+    MissingChannel { sensor: String, channel: String },
+    TooFewPoints(usize),
+    /// No readings per reference point were asked for.
+    NoSamples,
+    /// The sensor is not configured with dotted keys under `[sensors]` or a `[sensors.<id>]` table.
+    CannotWrite(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::UnknownSensor(id) => write!(fmt, "No sensor '{}' in the configuration", id),
+            Error::MissingChannel { sensor, channel } =>
+                write!(fmt, "Sensor '{}' has no channel '{}' to calibrate", sensor, channel),
+            Error::TooFewPoints(n) => write!(fmt, "Calibration needs at least 2 reference points, got {}", n),
+            Error::NoSamples => write!(fmt, "Calibration needs at least 1 reading per reference point"),
+            Error::CannotWrite(id) => write!(fmt, "Could not find where sensor '{}' is configured to add its calibration", id),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+}
+
+pub struct Options {
+    /// Readings per reference point.
+    pub samples: u32,
+    /// Time between readings, not zero.
+    pub spacing: Duration,
+    /// Calibrated values of the reference points, dry air and water when empty.
+    pub points: Vec<f64>,
+    /// Print the calibration without writing it.
+    pub dry_run: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Statistics {
+    pub count: usize,
+    pub mean: f64,
+    /// Sample standard deviation.
+    pub std_dev: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Statistics {
+    pub fn of(values: &[f64]) -> Option<Statistics> {
+        if values.is_empty() {
+            return None;
+        }
+        let count = values.len();
+        let mean = values.iter().sum::<f64>() / count as f64;
+        let variance = match count {
+            1 => 0.0,
+            _ => values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (count - 1) as f64
+        };
+        Some(Statistics {
+            count,
+            mean,
+            std_dev: variance.sqrt(),
+            min: values.iter().cloned().fold(f64::INFINITY, f64::min),
+            max: values.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+        })
+    }
+}
+
+impl std::fmt::Display for Statistics {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "n={} mean={:.3} std_dev={:.3} min={:.3} max={:.3}", self.count, self.mean, self.std_dev, self.min, self.max)
+    }
+}
+
+pub fn run<G: GpioBackend + Send + 'static>(
+    config_path: &str,
+    config: &SensorsConfig,
+    sensor_id: &str,
+    options: &Options,
+    gpio: Arc<Mutex<ClaimedGpio<G>>>,
+    soc: Soc
+) -> Result<(), FailureError> {
+    let sensor_config = config.sensors
+        .iter()
+        .find(|sensor| sensor.id == sensor_id)
+        .ok_or_else(|| Error::UnknownSensor(sensor_id.to_string()))?;
+    let (channel, unit) = match &sensor_config.calibration {
+        Some(calibration) => (calibration.channel.clone(), calibration.unit.clone()),
+        None => ("value".to_string(), calibration::DEFAULT_UNIT.to_string())
+    };
+    if options.samples == 0 {
+        return Err(FailureError::from(Error::NoSamples));
+    }
+    let points: Vec<(String, f64)> = match options.points.as_slice() {
+        [] => vec![("dry air".to_string(), 0.0), ("water".to_string(), 100.0)],
+        [_] => return Err(FailureError::from(Error::TooFewPoints(1))),
+        points => points.iter().map(|value| (format!("the {} {} reference", value, unit), *value)).collect()
+    };
+
+    let context = SensorContext::new(gpio, soc);
+    let registry = SensorRegistry::builtin();
+    let mut runtime = Runtime::new()?;
+
+    let mut means = Vec::with_capacity(points.len());
+    let mut spreads = Vec::with_capacity(points.len());
+    let stdin = io::stdin();
+    for (label, value) in &points {
+        print!("Place the probe of '{}' in {} and press enter ", sensor_id, label);
+        io::stdout().flush()?;
+        stdin.lock().read_line(&mut String::new())?;
+        // Set up for each reference point, the sampler clears the sensor once dropped.
+        let sensor = registry.create(sensor_config, &context)?;
+        sensor.init(&mut *context.gpio.lock().unwrap())?;
+        let mut taken = 0;
+        let samples = SensorSampler::with_interval(sensor, context.gpio.clone(), options.spacing, sensor_config.acquisition)
+            .take(u64::from(options.samples))
+            .inspect(|_| {
+                taken += 1;
+                print!("\r{} of {} readings", taken, options.samples);
+                let _ = io::stdout().flush();
+            })
+            .collect();
+        let readings = runtime.block_on(samples)?
+            .iter()
+            .map(|sample| sample.channel(&channel).map(|reading| reading.value.as_f64()))
+            .collect::<Option<Vec<f64>>>()
+            .ok_or_else(|| Error::MissingChannel { sensor: sensor_id.to_string(), channel: channel.clone() })?;
+        let stats = Statistics::of(&readings).unwrap();
+        println!("\r{} ({} {}): {}", label, value, unit, stats);
+        means.push((stats.mean, *value));
+        spreads.push(stats);
+    }
+
+    for (i, pair) in spreads.windows(2).enumerate() {
+        if (pair[0].mean - pair[1].mean).abs() < pair[0].std_dev + pair[1].std_dev {
+            println!(
+                "Warning: readings at {} and {} overlap within one standard deviation",
+                points[i].0,
+                points[i + 1].0
+            );
+        }
+    }
+
+    let curve = match means.as_slice() {
+        [(dry, dry_value), (wet, wet_value)] => Curve::TwoPoint { dry: *dry, wet: *wet, dry_value: *dry_value, wet_value: *wet_value },
+        _ => {
+            let mut points = means.clone();
+            points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            Curve::Piecewise(points)
+        }
+    };
+    let calibration = Calibration { curve, channel, unit };
+    let inline = to_inline_table(&calibration);
+    println!("{}.calibration = {}", sensor_id, inline);
+    if options.dry_run {
+        return Ok(());
+    }
+
+    let toml_str = std::fs::read_to_string(config_path)?;
+    let updated = with_calibration(&toml_str, sensor_id, &inline)?;
+    // Never write a file that reads back differently.
+    let written = sensor_config::from_toml(&updated)?
+        .sensors
+        .into_iter()
+        .find(|sensor| sensor.id == sensor_id)
+        .and_then(|sensor: SensorConfig| sensor.calibration);
+    if written.as_ref() != Some(&calibration) {
+        return Err(FailureError::from(Error::CannotWrite(sensor_id.to_string())));
+    }
+    std::fs::write(config_path, updated)?;
+    println!("Wrote calibration of '{}' to {}", sensor_id, config_path);
+    Ok(())
+}
+
+/// The calibration as a TOML inline table, leaving out defaults.
+pub fn to_inline_table(calibration: &Calibration) -> String {
+    let mut fields = match &calibration.curve {
+        Curve::TwoPoint { dry, wet, dry_value, wet_value } => {
+            let mut fields = vec![
+                "type = 'two_point'".to_string(),
+                format!("dry = {:?}", dry),
+                format!("wet = {:?}", wet)
+            ];
+            if *dry_value != 0.0 || *wet_value != 100.0 {
+                fields.push(format!("dry_value = {:?}", dry_value));
+                fields.push(format!("wet_value = {:?}", wet_value));
+            }
+            fields
+        },
+        Curve::Piecewise(points) => vec![
+            "type = 'piecewise'".to_string(),
+            format!("points = [{}]", points
+                .iter()
+                .map(|(raw, value)| format!("[{:?}, {:?}]", raw, value))
+                .collect::<Vec<String>>()
+                .join(", "))
+        ],
+        Curve::Polynomial(coefficients) => vec![
+            "type = 'polynomial'".to_string(),
+            format!("coefficients = [{}]", coefficients
+                .iter()
+                .map(|c| format!("{:?}", c))
+                .collect::<Vec<String>>()
+                .join(", "))
+        ],
+    };
+    if calibration.channel != "value" {
+        fields.push(format!("channel = '{}'", calibration.channel));
+    }
+    if calibration.unit != calibration::DEFAULT_UNIT {
+        fields.push(format!("unit = '{}'", calibration.unit));
+    }
+    format!("{{ {} }}", fields.join(", "))
+}
+
+/// `toml_str` with any calibration of `sensor_id` replaced by `inline`.
+pub fn with_calibration(toml_str: &str, sensor_id: &str, inline: &str) -> Result<String, Error> {
+    let sensor_table = format!("sensors.{}", sensor_id);
+    let calibration_table = format!("sensors.{}.calibration", sensor_id);
+    let dotted = format!("{}.calibration", sensor_id);
+    let prefix = format!("{}.", sensor_id);
+    let is_key = |key: &str, name: &str| key == name || key.starts_with(&format!("{}.", name));
+
+    let mut lines: Vec<String> = Vec::new();
+    let mut table = String::new();
+    // Line to insert after and whether it is in the sensor's own table.
+    let mut insert_after: Option<(usize, bool)> = None;
+    for line in toml_str.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            table = trimmed.trim_matches(|c| c == '[' || c == ']').trim().to_string();
+            if table == calibration_table {
+                continue;
+            }
+        } else if table == calibration_table {
+            continue;
+        } else if let Some(key) = trimmed.split('=').next().map(str::trim).filter(|_| trimmed.contains('=')) {
+            if table == "sensors" && is_key(key, &dotted) || table == sensor_table && is_key(key, "calibration") {
+                continue;
+            }
+        }
+        lines.push(line.to_string());
+        let in_sensor = table == sensor_table && !trimmed.is_empty() && !trimmed.starts_with('#');
+        let in_sensors = table == "sensors" && trimmed.starts_with(&prefix);
+        if in_sensor || in_sensors {
+            insert_after = Some((lines.len() - 1, in_sensor));
+        }
+    }
+    match insert_after {
+        Some((i, true)) => lines.insert(i + 1, format!("calibration = {}", inline)),
+        Some((i, false)) => lines.insert(i + 1, format!("{}.calibration = {}", sensor_id, inline)),
+        None => return Err(Error::CannotWrite(sensor_id.to_string()))
+    }
+    let mut updated = lines.join("\n");
+    if toml_str.ends_with('\n') {
+        updated.push('\n');
+    }
+    Ok(updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn two_point() -> Calibration {
+        Calibration {
+            curve: Curve::TwoPoint { dry: 820.0, wet: 410.5, dry_value: 0.0, wet_value: 100.0 },
+            channel: "value".to_string(),
+            unit: calibration::DEFAULT_UNIT.to_string()
+        }
+    }
+
+    #[test]
+    fn computes_statistics() {
+        assert_eq!(Statistics::of(&[]), None);
+        assert_eq!(Statistics::of(&[2.0, 4.0, 6.0]), Some(Statistics { count: 3, mean: 4.0, std_dev: 2.0, min: 2.0, max: 6.0 }));
+        assert_eq!(Statistics::of(&[5.0]).unwrap().std_dev, 0.0);
+    }
+
+    #[test]
+    fn writes_inline_tables_without_defaults() {
+        assert_eq!(to_inline_table(&two_point()), "{ type = 'two_point', dry = 820.0, wet = 410.5 }");
+        let piecewise = Calibration {
+            curve: Curve::Piecewise(vec![(410.0, 45.0), (600.0, 20.0), (820.0, 0.0)]),
+            channel: "soil".to_string(),
+            unit: "kPa".to_string()
+        };
+        assert_eq!(
+            to_inline_table(&piecewise),
+            "{ type = 'piecewise', points = [[410.0, 45.0], [600.0, 20.0], [820.0, 0.0]], channel = 'soil', unit = 'kPa' }"
+        );
+    }
+
+    #[test]
+    fn replaces_calibration_of_dotted_keys() {
+        let toml_str = "[sensors]\n\
+            moist1.sensor_type = 'mcp3008'\n\
+            moist1.calibration = { type = 'polynomial', coefficients = [1.0] }\n\
+            moist1.interval = 10\n\
+            moist2.sensor_type = 'mcp3008'\n";
+        let inline = to_inline_table(&two_point());
+        assert_eq!(with_calibration(toml_str, "moist1", &inline).unwrap(), format!("[sensors]\n\
+            moist1.sensor_type = 'mcp3008'\n\
+            moist1.interval = 10\n\
+            moist1.calibration = {}\n\
+            moist2.sensor_type = 'mcp3008'\n", inline));
+    }
+
+    #[test]
+    fn replaces_calibration_of_sensor_tables() {
+        let toml_str = "[sensors.moist1]\n\
+            sensor_type = 'mcp3008'\n\
+            interval = 10\n\
+            \n\
+            [sensors.moist1.calibration]\n\
+            type = 'polynomial'\n\
+            coefficients = [1.0]\n\
+            \n\
+            [sensors.moist2]\n\
+            sensor_type = 'mcp3008'\n\
+            interval = 10\n";
+        let inline = to_inline_table(&two_point());
+        let updated = with_calibration(toml_str, "moist1", &inline).unwrap();
+        assert_eq!(updated, format!("[sensors.moist1]\n\
+            sensor_type = 'mcp3008'\n\
+            interval = 10\n\
+            calibration = {}\n\
+            \n\
+            [sensors.moist2]\n\
+            sensor_type = 'mcp3008'\n\
+            interval = 10\n", inline));
+        let sensors = sensor_config::from_toml(&updated).unwrap().sensors;
+        assert_eq!(sensors[0].calibration, Some(two_point()));
+        assert_eq!(sensors[1].calibration, None);
+    }
+
+    #[test]
+    fn cannot_write_sensor_missing_from_the_file() {
+        let error = with_calibration("[sensors.moist1]\ninterval = 10\n", "moist2", "{}").unwrap_err();
+        assert_eq!(error.to_string(), "Could not find where sensor 'moist2' is configured to add its calibration");
+    }
+}
@@ -0,0 +1,157 @@
+///
+/// Calibration curves mapping raw readings to volumetric water content, or another unit.
+///
+/// A calibrated channel keeps its name and gets the calibrated value and unit, while the raw
+/// reading is kept in a `raw` channel, or `<name>_raw` for channels other than `value`. The
+/// spread of an oversampled channel stays in raw units and is renamed to match, `raw_spread`
+/// or `<name>_raw_spread`.
+///
+use crate::acquisition::spread_name;
+use crate::sample::{Channel, Sample, Status};
+
+pub const DEFAULT_UNIT: &str = "%VWC";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Curve {
+    /// Straight line through a dry and a wet reading.
+    TwoPoint { dry: f64, wet: f64, dry_value: f64, wet_value: f64 },
+    /// Straight lines between `(raw, value)` points sorted by raw reading, extended beyond
+    /// the ends by the first and last segment.
+    Piecewise(Vec<(f64, f64)>),
+    /// Coefficients in ascending order of power, `c0 + c1 * x + c2 * x^2 ...`.
+    Polynomial(Vec<f64>),
+}
+
+impl Curve {
+    /// The calibrated value, and whether `raw` lies within the calibrated range.
+    pub fn apply(&self, raw: f64) -> (f64, bool) {
+        match self {
+            Curve::TwoPoint { dry, wet, dry_value, wet_value } => (
+                interpolate((*dry, *dry_value), (*wet, *wet_value), raw),
+                within(raw, *dry, *wet)
+            ),
+            Curve::Piecewise(points) => {
+                let segment = points
+                    .windows(2)
+                    .find(|segment| raw <= segment[1].0)
+                    .unwrap_or(&points[points.len() - 2..]);
+                (
+                    interpolate(segment[0], segment[1], raw),
+                    within(raw, points[0].0, points[points.len() - 1].0)
+                )
+            },
+            Curve::Polynomial(coefficients) => (
+                coefficients.iter().rev().fold(0.0, |value, c| value * raw + c),
+                true
+            ),
+        }
+    }
+}
+
+fn interpolate((x0, y0): (f64, f64), (x1, y1): (f64, f64), x: f64) -> f64 {
+    y0 + (x - x0) * (y1 - y0) / (x1 - x0)
+}
+
+fn within(x: f64, a: f64, b: f64) -> bool {
+    x >= a.min(b) && x <= a.max(b)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Calibration {
+    pub curve: Curve,
+    /// Channel to calibrate.
+    pub channel: String,
+    pub unit: String,
+}
+
+impl Calibration {
+    /// Calibrates the sample. If it has no channel to calibrate, eg. from a misspelled
+    /// `channel`, its channels are marked suspect instead.
+    pub fn apply(&self, mut sample: Sample) -> Sample {
+        let i = match sample.channels.iter().position(|channel| channel.name == self.channel) {
+            Some(i) => i,
+            None => {
+                let reason = format!("No channel '{}' to calibrate", self.channel);
+                for channel in sample.channels.iter_mut().filter(|channel| channel.status.is_ok()) {
+                    channel.status = Status::Suspect(reason.clone());
+                }
+                return sample;
+            }
+        };
+        let raw = sample.channels[i].clone();
+        let (value, within) = self.curve.apply(raw.value.as_f64());
+        let status = match &raw.status {
+            Status::Ok if !within => Status::Suspect("Raw reading outside calibrated range".to_string()),
+            status => status.clone()
+        };
+        let raw_name = match raw.name.as_str() {
+            "value" => "raw".to_string(),
+            name => format!("{}_raw", name)
+        };
+        let raw_spread_name = spread_name(&raw_name);
+        if let Some(spread) = sample.channels.iter_mut().find(|channel| channel.name == spread_name(&raw.name)) {
+            spread.name = raw_spread_name;
+        }
+        sample.channels[i] = Channel::new(&raw.name, value).with_unit(&self.unit).with_status(status);
+        sample.channels.insert(i + 1, Channel { name: raw_name, ..raw });
+        sample
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    fn two_point(channel: &str) -> Calibration {
+        Calibration {
+            curve: Curve::TwoPoint { dry: 800.0, wet: 400.0, dry_value: 0.0, wet_value: 100.0 },
+            channel: channel.to_string(),
+            unit: DEFAULT_UNIT.to_string()
+        }
+    }
+
+    fn sample(channels: Vec<Channel>) -> Sample {
+        Sample::new(SystemTime::UNIX_EPOCH, channels)
+    }
+
+    #[test]
+    fn applies_curves() {
+        assert_eq!(two_point("value").curve.apply(600.0), (50.0, true));
+        assert_eq!(two_point("value").curve.apply(900.0), (-25.0, false));
+        let piecewise = Curve::Piecewise(vec![(400.0, 45.0), (600.0, 20.0), (800.0, 0.0)]);
+        assert_eq!(piecewise.apply(500.0), (32.5, true));
+        assert_eq!(piecewise.apply(300.0), (57.5, false));
+        assert_eq!(Curve::Polynomial(vec![1.0, 2.0, 3.0]).apply(2.0), (17.0, true));
+    }
+
+    #[test]
+    fn keeps_the_raw_reading() {
+        let calibrated = two_point("value").apply(sample(vec![Channel::new("value", 900u32)]));
+        assert_eq!(calibrated.channels, vec![
+            Channel::new("value", -25.0)
+                .with_unit("%VWC")
+                .with_status(Status::Suspect("Raw reading outside calibrated range".to_string())),
+            Channel::new("raw", 900u32)
+        ]);
+    }
+
+    #[test]
+    fn renames_the_spread_of_the_raw_reading() {
+        let calibrated = two_point("soil").apply(sample(vec![
+            Channel::new("soil", 600.0),
+            Channel::new("soil_spread", 12.0),
+            Channel::new("spread", 3.0)
+        ]));
+        let names: Vec<&str> = calibrated.channels.iter().map(|channel| channel.name.as_str()).collect();
+        assert_eq!(names, vec!["soil", "soil_raw", "soil_raw_spread", "spread"]);
+    }
+
+    #[test]
+    fn marks_samples_without_the_channel_suspect() {
+        let calibrated = two_point("moisture").apply(sample(vec![Channel::new("value", 600.0)]));
+        assert_eq!(calibrated.channels, vec![
+            Channel::new("value", 600.0).with_status(Status::Suspect("No channel 'moisture' to calibrate".to_string()))
+        ]);
+    }
+}
@@ -0,0 +1,362 @@
+///
+/// Capacitive probe read by timing how long it takes to charge.
+///
+/// The probe forms an RC circuit on `val_pin`, charged through a resistor from the supply or
+/// from `pwr_pin`. Each measurement discharges the probe by driving the pin low, then switches
+/// it to input and times until it reads high. Wetter soil gives a higher capacitance and a
+/// longer charge time. Rising edge detection is used where the backend supports it, so a short
+/// high between polls is still caught, otherwise the level is polled.
+///
+/// The probe discharges while the sampler waits between measurements, so the GPIO is only
+/// held for the charge itself.
+///
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use failure::Error as FailureError;
+use crate::gpio::{Edge, Error as GpioError, GpioBackend, Level, Mode, PullUpDown};
+use crate::gpio::claims::ClaimedPin;
+use crate::sample::{Channel, Status};
+use crate::sensor::{Sensor, Error as SensorError};
+use crate::sensor_config::SensorConfig;
+use crate::sensor_registry::SensorContext;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Fewer than half of the measurements finished within the timeout.
+    Timeout { pin: u8, finished: usize, repetitions: u32 },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Timeout { pin, finished, repetitions } =>
+                write!(fmt, "Pin {} charged in {} of {} measurements", pin, finished, repetitions),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+}
+
+/// Mean of `samples` without outliers, values outside 1.5 interquartile ranges of the
+/// quartiles are left out.
+pub fn robust_mean(samples: &[f64]) -> Option<f64> {
+    if samples.is_empty() {
+        return None;
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let q1 = sorted[sorted.len() / 4];
+    let q3 = sorted[sorted.len() * 3 / 4];
+    let fence = 1.5 * (q3 - q1);
+    let kept: Vec<f64> = sorted.into_iter().filter(|s| *s >= q1 - fence && *s <= q3 + fence).collect();
+    Some(kept.iter().sum::<f64>() / kept.len() as f64)
+}
+
+/// Progress of the sample being taken.
+#[derive(Default)]
+struct Measurements {
+    /// Whether the backend detects edges on `val_pin`.
+    edges: bool,
+    taken: u32,
+    /// Charge times in microseconds of the measurements finishing within the timeout.
+    times: Vec<f64>,
+}
+
+pub struct CapacitiveSensor {
+    val_pin: ClaimedPin,
+    pwr_pin: Option<ClaimedPin>,
+    pwr_wait: u64,
+    repetitions: u32,
+    discharge_time: Duration,
+    timeout: Duration,
+    measurements: Mutex<Measurements>,
+}
+
+impl<G: GpioBackend> Sensor<G> for CapacitiveSensor {
+    fn init(&self, gpio: &mut G) -> Result<(), SensorError> {
+        gpio.set_mode(self.val_pin.pin(), Mode::Input)?;
+        gpio.set_pullupdown(&[self.val_pin.pin()], PullUpDown::Off)?;
+        if let Some(pwr_pin) = &self.pwr_pin {
+            gpio.set_mode(pwr_pin.pin(), Mode::Output)?;
+            gpio.set_pullupdown(&[pwr_pin.pin()], PullUpDown::Off)?;
+        }
+        Ok(())
+    }
+
+    fn clear(&self, gpio: &mut G) -> Result<(), SensorError> {
+        gpio.set_mode(self.val_pin.pin(), Mode::Input)?;
+        match &self.pwr_pin {
+            // A shared power pin is left to the last sensor using it.
+            Some(pwr_pin) if pwr_pin.is_sole_owner() => {
+                gpio.clear(pwr_pin.pin())?;
+                gpio.set_mode(pwr_pin.pin(), Mode::Input)?;
+            },
+            _ => ()
+        }
+        Ok(())
+    }
+
+    /// Powers the probe and starts discharging it for the first measurement.
+    fn prepare(&self, gpio: &mut G) -> Result<Option<Duration>, SensorError> {
+        let pin = self.val_pin.pin();
+        *self.measurements.lock().unwrap() = Measurements::default();
+        if let Some(pwr_pin) = &self.pwr_pin {
+            gpio.set(pwr_pin.pin())?;
+        }
+        let edges = match gpio.enable_edge_detect(pin, Edge::Rising) {
+            Ok(()) => true,
+            Err(GpioError::Unsupported { .. }) => false,
+            Err(err) => return Err(self.finish(gpio, false, err))
+        };
+        self.measurements.lock().unwrap().edges = edges;
+        self.discharge(gpio).map_err(|err| self.finish(gpio, edges, err))?;
+        Ok(Some(self.discharge_time.max(Duration::from_millis(self.pwr_wait))))
+    }
+
+    /// Times one charge, then discharges the probe for the next one.
+    fn measure(&self, gpio: &mut G) -> Result<Option<Duration>, SensorError> {
+        let mut measurements = self.measurements.lock().unwrap();
+        if measurements.taken == self.repetitions {
+            return Ok(None);
+        }
+        let edges = measurements.edges;
+        let charged = self.charge(gpio, edges).map_err(|err| self.finish(gpio, edges, err))?;
+        if let Some(time) = charged {
+            measurements.times.push(time.as_nanos() as f64 / 1000.0);
+        }
+        measurements.taken += 1;
+        if measurements.taken == self.repetitions {
+            return Ok(None);
+        }
+        self.discharge(gpio).map_err(|err| self.finish(gpio, edges, err))?;
+        Ok(Some(self.discharge_time))
+    }
+
+    /// Mean charge time in microseconds, suspect when some measurements timed out.
+    fn read(&self, gpio: &mut G) -> Result<Vec<Channel>, SensorError> {
+        let Measurements { edges, times, .. } = std::mem::take(&mut *self.measurements.lock().unwrap());
+        self.release(gpio, edges)?;
+        match robust_mean(&times) {
+            Some(mean) if times.len() * 2 >= self.repetitions as usize => {
+                let channel = Channel::new("value", mean).with_unit("us");
+                Ok(vec![match times.len() < self.repetitions as usize {
+                    true => channel.with_status(Status::Suspect(format!(
+                        "{} of {} measurements timed out",
+                        self.repetitions as usize - times.len(),
+                        self.repetitions
+                    ))),
+                    false => channel
+                }])
+            },
+            _ => Err(SensorError::from(Error::Timeout {
+                pin: self.val_pin.pin(),
+                finished: times.len(),
+                repetitions: self.repetitions
+            }))
+        }
+    }
+}
+
+impl CapacitiveSensor {
+    pub fn new(
+        val_pin: ClaimedPin,
+        pwr_pin: Option<ClaimedPin>,
+        pwr_wait: u64,
+        repetitions: u32,
+        discharge_time: Duration,
+        timeout: Duration
+    ) -> Self {
+        CapacitiveSensor {
+            val_pin,
+            pwr_pin,
+            pwr_wait,
+            repetitions,
+            discharge_time,
+            timeout,
+            measurements: Mutex::new(Measurements::default())
+        }
+    }
+
+    fn discharge<G: GpioBackend>(&self, gpio: &mut G) -> Result<(), GpioError> {
+        gpio.clear(self.val_pin.pin())?;
+        gpio.set_mode(self.val_pin.pin(), Mode::Output)
+    }
+
+    /// Charge time, unless the probe did not charge within the timeout.
+    fn charge<G: GpioBackend>(&self, gpio: &mut G, edges: bool) -> Result<Option<Duration>, GpioError> {
+        let pin = self.val_pin.pin();
+        let started = Instant::now();
+        gpio.set_mode(pin, Mode::Input)?;
+        if edges {
+            // Drop any edge latched from before the discharge, a probe charged by now is
+            // caught by its level.
+            gpio.take_event(pin)?;
+            if gpio.read(pin)? == Level::High {
+                return Ok(Some(started.elapsed()));
+            }
+        }
+        loop {
+            let charged = match edges {
+                true => gpio.take_event(pin)?,
+                false => gpio.read(pin)? == Level::High
+            };
+            let elapsed = started.elapsed();
+            if charged {
+                return Ok(Some(elapsed));
+            }
+            if elapsed > self.timeout {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Leaves the probe unpowered with `val_pin` as input and edge detection disabled.
+    fn release<G: GpioBackend>(&self, gpio: &mut G, edges: bool) -> Result<(), GpioError> {
+        let pin = self.val_pin.pin();
+        let disabled = match edges {
+            true => gpio.disable_edge_detect(pin),
+            false => Ok(())
+        };
+        // The pin is released even if disabling edge detection failed, the first error is returned.
+        let released = gpio.set_mode(pin, Mode::Input);
+        let unpowered = match &self.pwr_pin {
+            Some(pwr_pin) => gpio.clear(pwr_pin.pin()),
+            None => Ok(())
+        };
+        disabled.and(released).and(unpowered)
+    }
+
+    /// Releases the probe after a failed measurement, giving the failure.
+    fn finish<G: GpioBackend>(&self, gpio: &mut G, edges: bool, err: GpioError) -> SensorError {
+        let _ = self.release(gpio, edges);
+        SensorError::from(err)
+    }
+}
+
+/// Options are `repetitions` per sample, and `discharge_time` and `timeout` in milliseconds.
+/// `pwr_pin` is optional.
+pub fn from_config<G: GpioBackend + Send + 'static>(config: &SensorConfig, context: &SensorContext<G>)
+    -> Result<Box<dyn Sensor<G> + Send>, FailureError> {
+    Ok(Box::new(configured(config, context)?))
+}
+
+fn configured<G: GpioBackend>(config: &SensorConfig, context: &SensorContext<G>) -> Result<CapacitiveSensor, FailureError> {
+    let get = |key: &str, min: i64, default: u32| -> Result<u32, FailureError> {
+        match config.optional(key, |toml| { toml.as_integer() }, "unsigned integer")? {
+            Some(value) if (min..=i64::from(u32::MAX)).contains(&value) => Ok(value as u32),
+            Some(value) => Err(config.invalid(key, format!("Must be between {} and {}, got {}", min, u32::MAX, value))),
+            None => Ok(default)
+        }
+    };
+    let repetitions = get("repetitions", 1, 25)?;
+    let discharge_time = Duration::from_millis(u64::from(get("discharge_time", 0, 1)?));
+    let timeout = Duration::from_millis(u64::from(get("timeout", 0, 100)?));
+    let val_pin = context.claim_val_pin(config)?;
+    let pwr_pin = match config.pwr {
+        Some(_) => Some(context.claim_pwr_pin(config)?),
+        None => None
+    };
+    Ok(CapacitiveSensor::new(val_pin, pwr_pin, config.pwr_wait.unwrap_or(0), repetitions, discharge_time, timeout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::claims::PinClaims;
+    use crate::gpio::simulated::{Call, SimulatedGpio};
+    use crate::sensor_config::config_with;
+    use crate::sensor_registry::simulated_context;
+
+    const VAL: u8 = 24;
+
+    fn capacitive(repetitions: u32) -> CapacitiveSensor {
+        let claims = PinClaims::new();
+        CapacitiveSensor::new(
+            claims.claim(VAL, "cap1").unwrap(),
+            None,
+            0,
+            repetitions,
+            Duration::from_millis(0),
+            Duration::from_millis(2)
+        )
+    }
+
+    /// Takes a sample the way the sampler does, without the waits.
+    fn sample(sensor: &CapacitiveSensor, gpio: &mut SimulatedGpio) -> Result<Vec<Channel>, SensorError> {
+        sensor.init(gpio)?;
+        sensor.prepare(gpio)?;
+        let mut measured = 0;
+        while sensor.measure(gpio)?.is_some() {
+            measured += 1;
+        }
+        assert_eq!(measured, sensor.repetitions - 1);
+        sensor.read(gpio)
+    }
+
+    #[test]
+    fn averages_without_outliers() {
+        assert_eq!(robust_mean(&[10.0, 11.0, 12.0, 11.0, 10.0, 12.0, 11.0, 100.0]), Some(11.0));
+        assert_eq!(robust_mean(&[7.0; 5]), Some(7.0));
+        assert_eq!(robust_mean(&[]), None);
+    }
+
+    #[test]
+    fn averages_few_samples_whole() {
+        assert_eq!(robust_mean(&[5.0]), Some(5.0));
+        assert_eq!(robust_mean(&[2.0, 4.0]), Some(3.0));
+        assert_eq!(robust_mean(&[1.0, 2.0, 30.0]), Some(11.0));
+    }
+
+    #[test]
+    fn measures_charged_probe() {
+        let mut gpio = SimulatedGpio::new();
+        gpio.script(VAL, &[(0, Level::High)], None).unwrap();
+        let sensor = capacitive(4);
+        let channels = sample(&sensor, &mut gpio).unwrap();
+        assert_eq!((channels[0].unit.as_deref(), &channels[0].status), (Some("us"), &Status::Ok));
+        assert!(channels[0].value.as_f64() < 2000.0);
+    }
+
+    #[test]
+    fn suspects_sample_with_timed_out_measurements() {
+        let mut gpio = SimulatedGpio::new();
+        let sensor = capacitive(4);
+        *sensor.measurements.lock().unwrap() = Measurements { edges: false, taken: 4, times: vec![10.0, 12.0, 11.0] };
+        assert_eq!(sensor.read(&mut gpio).unwrap(), vec![
+            Channel::new("value", 11.0)
+                .with_unit("us")
+                .with_status(Status::Suspect("1 of 4 measurements timed out".to_string()))
+        ]);
+    }
+
+    #[test]
+    fn times_out_on_probe_not_charging() {
+        let mut gpio = SimulatedGpio::new();
+        let sensor = capacitive(4);
+        match sample(&sensor, &mut gpio) {
+            Err(SensorError::Capacitive(Error::Timeout { pin: VAL, finished: 0, repetitions: 4 })) => (),
+            other => panic!("Expected a timeout, got {:?}", other)
+        }
+        let calls = gpio.take_calls();
+        let disabled = calls.iter().position(|call| *call == Call::DisableEdgeDetect(VAL)).unwrap();
+        assert_eq!(calls[disabled + 1..], [Call::SetMode(VAL, Mode::Input)]);
+    }
+
+    fn configured_with(extra: &str) -> Result<CapacitiveSensor, FailureError> {
+        configured(&config_with("capacitive", extra)?, &simulated_context())
+    }
+
+    #[test]
+    fn parses_options() {
+        let probe = configured_with("val_pin = 24").unwrap();
+        assert_eq!((probe.repetitions, probe.discharge_time, probe.timeout), (25, Duration::from_millis(1), Duration::from_millis(100)));
+        assert!(probe.pwr_pin.is_none());
+        let probe = configured_with("val_pin = 24\npwr_pin = 23\nrepetitions = 5\ndischarge_time = 0\ntimeout = 20").unwrap();
+        assert_eq!((probe.repetitions, probe.discharge_time, probe.timeout), (5, Duration::from_millis(0), Duration::from_millis(20)));
+        assert_eq!(probe.pwr_pin.map(|pin| pin.pin()), Some(23));
+        for extra in &["", "val_pin = 24\nrepetitions = 0", "val_pin = 24\ntimeout = -1", "val_pin = 24\ndischarge_time = 'long'"] {
+            assert!(configured_with(extra).is_err(), "{}", extra);
+        }
+    }
+}
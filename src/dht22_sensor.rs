@@ -0,0 +1,242 @@
+///
+/// DHT22 / AM2302 temperature and humidity sensor on a single data pin.
+/// https://www.sparkfun.com/datasheets/Sensors/Temperature/DHT22.pdf
+///
+/// The host pulls the line low to request a reading and releases it. The sensor answers with
+/// an 80us low and 80us high, then sends 40 bits as a 50us low followed by a high of about
+/// 27us for 0 or 70us for 1. The line is timed by polling, so decoding only compares each high
+/// against the low before it and tolerates slow backends stretching all pulses alike.
+///
+use std::time::{Duration, Instant};
+use failure::Error as FailureError;
+use crate::gpio::{GpioBackend, Level, Mode, PullUpDown};
+use crate::gpio::claims::ClaimedPin;
+use crate::sample::Channel;
+use crate::sensor::{Sensor, Error as SensorError};
+use crate::sensor_config::SensorConfig;
+use crate::sensor_registry::SensorContext;
+
+const START_PULSE: Duration = Duration::from_millis(2);
+/// Response and 40 bits take at most about 5ms.
+const CAPTURE_TIME: Duration = Duration::from_millis(8);
+/// The sensor needs 2 seconds between readings.
+const RETRY_WAIT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Fewer than 40 bits were captured, the sensor did not answer or was missed.
+    Incomplete { bits: usize },
+    Checksum { expected: u8, actual: u8 },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Incomplete { bits } => write!(fmt, "Dht22 sent {} of 40 bits", bits),
+            Error::Checksum { expected, actual } =>
+                write!(fmt, "Dht22 checksum {:#04x} does not match data sum {:#04x}", actual, expected),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+}
+
+/// A stretch of time the data line stayed at `level`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pulse {
+    pub level: Level,
+    pub duration: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reading {
+    /// Degrees Celsius.
+    pub temperature: f64,
+    /// Percent relative humidity.
+    pub humidity: f64,
+}
+
+/// Decodes a reading from the pulses captured after the start pulse.
+/// The last 40 high pulses are taken as the data bits, so leading pulses may be missing or extra.
+pub fn decode(pulses: &[Pulse]) -> Result<Reading, Error> {
+    let bits: Vec<bool> = pulses
+        .windows(2)
+        .filter(|pair| pair[0].level == Level::Low && pair[1].level == Level::High)
+        .map(|pair| pair[1].duration > pair[0].duration)
+        .collect();
+    if bits.len() < 40 {
+        return Err(Error::Incomplete { bits: bits.len() });
+    }
+    let mut bytes = [0u8; 5];
+    for (i, bit) in bits[bits.len() - 40..].iter().enumerate() {
+        bytes[i / 8] = bytes[i / 8] << 1 | *bit as u8;
+    }
+    let sum = bytes[..4].iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    if sum != bytes[4] {
+        return Err(Error::Checksum { expected: sum, actual: bytes[4] });
+    }
+    let humidity = u16::from(bytes[0]) << 8 | u16::from(bytes[1]);
+    // Temperature is sign and magnitude, not two's complement.
+    let temperature = u16::from(bytes[2] & 0x7f) << 8 | u16::from(bytes[3]);
+    let sign = if bytes[2] & 0x80 != 0 { -1.0 } else { 1.0 };
+    Ok(Reading {
+        temperature: sign * f64::from(temperature) / 10.0,
+        humidity: f64::from(humidity) / 10.0,
+    })
+}
+
+pub struct Dht22Sensor {
+    data_pin: ClaimedPin,
+    retries: u32,
+}
+
+impl<G: GpioBackend> Sensor<G> for Dht22Sensor {
+    fn init(&self, gpio: &mut G) -> Result<(), SensorError> {
+        gpio.set_mode(self.data_pin.pin(), Mode::Input)?;
+        gpio.set_pullupdown(&[self.data_pin.pin()], PullUpDown::Up)?;
+        Ok(())
+    }
+
+    fn clear(&self, gpio: &mut G) -> Result<(), SensorError> {
+        gpio.set_mode(self.data_pin.pin(), Mode::Input)?;
+        Ok(())
+    }
+
+    fn read(&self, gpio: &mut G) -> Result<Vec<Channel>, SensorError> {
+        let reading = decode(&self.capture(gpio)?)?;
+        Ok(vec![
+            Channel::new("temperature", reading.temperature).with_unit("°C"),
+            Channel::new("humidity", reading.humidity).with_unit("%RH")
+        ])
+    }
+
+    fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    fn retry_wait(&self) -> Duration {
+        RETRY_WAIT
+    }
+}
+
+impl Dht22Sensor {
+    /// `retries` is how many more times the sampler attempts a failed reading.
+    pub fn new(data_pin: ClaimedPin, retries: u32) -> Self {
+        Dht22Sensor { data_pin, retries }
+    }
+
+    /// Sends the start pulse and records the level changes of the answer.
+    pub fn capture<G: GpioBackend>(&self, gpio: &mut G) -> Result<Vec<Pulse>, SensorError> {
+        let pin = self.data_pin.pin();
+        gpio.clear(pin)?;
+        gpio.set_mode(pin, Mode::Output)?;
+        std::thread::sleep(START_PULSE);
+        gpio.set_mode(pin, Mode::Input)?;
+
+        let mut pulses = Vec::with_capacity(84);
+        let started = Instant::now();
+        let mut level = gpio.read(pin)?;
+        let mut since = started;
+        while started.elapsed() < CAPTURE_TIME {
+            let current = gpio.read(pin)?;
+            if current != level {
+                let now = Instant::now();
+                pulses.push(Pulse { level, duration: now - since });
+                level = current;
+                since = now;
+            }
+        }
+        Ok(pulses)
+    }
+}
+
+/// The data line is `val_pin`, `retries` is optional.
+pub fn from_config<G: GpioBackend + Send + 'static>(config: &SensorConfig, context: &SensorContext<G>)
+    -> Result<Box<dyn Sensor<G> + Send>, FailureError> {
+    Ok(Box::new(configured(config, context)?))
+}
+
+fn configured<G: GpioBackend>(config: &SensorConfig, context: &SensorContext<G>) -> Result<Dht22Sensor, FailureError> {
+    let retries = match config.optional("retries", |toml| { toml.as_integer() }, "unsigned integer")? {
+        Some(retries) if (0..=i64::from(u32::MAX)).contains(&retries) => retries as u32,
+        Some(retries) => return Err(config.invalid("retries", format!("Not a valid number of retries: {}", retries))),
+        None => 2
+    };
+    Ok(Dht22Sensor::new(context.claim_val_pin(config)?, retries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensor_config::config_with;
+    use crate::sensor_registry::simulated_context;
+
+    fn pulse(level: Level, micros: u64) -> Pulse {
+        Pulse { level, duration: Duration::from_micros(micros) }
+    }
+
+    /// Pulses of the answer sending `bytes`, with every duration multiplied by `stretch` as a
+    /// slow backend would record them.
+    fn trace(bytes: &[u8], stretch: u64) -> Vec<Pulse> {
+        let mut pulses = vec![pulse(Level::Low, 80 * stretch), pulse(Level::High, 80 * stretch)];
+        for byte in bytes {
+            for bit in (0..8).rev() {
+                pulses.push(pulse(Level::Low, 50 * stretch));
+                pulses.push(pulse(Level::High, if byte & (1 << bit) != 0 { 70 } else { 27 } * stretch));
+            }
+        }
+        pulses.push(pulse(Level::Low, 50 * stretch));
+        pulses
+    }
+
+    #[test]
+    fn decodes_a_reading() {
+        // 65.2 %RH and 35.1 °C.
+        let reading = decode(&trace(&[0x02, 0x8c, 0x01, 0x5f, 0xee], 1)).unwrap();
+        assert_eq!(reading, Reading { temperature: 35.1, humidity: 65.2 });
+    }
+
+    #[test]
+    fn decodes_negative_temperatures() {
+        // The sign is the top bit, 0x8065 is -10.1 °C.
+        let reading = decode(&trace(&[0x02, 0x8c, 0x80, 0x65, 0x73], 1)).unwrap();
+        assert_eq!(reading, Reading { temperature: -10.1, humidity: 65.2 });
+    }
+
+    #[test]
+    fn tolerates_stretched_and_missing_leading_pulses() {
+        let pulses = trace(&[0x02, 0x8c, 0x01, 0x5f, 0xee], 4);
+        assert_eq!(decode(&pulses[2..]).unwrap(), Reading { temperature: 35.1, humidity: 65.2 });
+    }
+
+    #[test]
+    fn refuses_a_bad_checksum() {
+        assert_eq!(
+            decode(&trace(&[0x02, 0x8c, 0x01, 0x5f, 0xef], 1)),
+            Err(Error::Checksum { expected: 0xee, actual: 0xef })
+        );
+    }
+
+    #[test]
+    fn refuses_a_short_frame() {
+        let pulses = trace(&[0x02, 0x8c, 0x01, 0x5f, 0xee], 1);
+        // The response low and high count as a bit as well.
+        assert_eq!(decode(&pulses[..2 + 2 * 30]), Err(Error::Incomplete { bits: 31 }));
+        assert_eq!(decode(&[]), Err(Error::Incomplete { bits: 0 }));
+    }
+
+    fn configured_with(extra: &str) -> Result<Dht22Sensor, FailureError> {
+        configured(&config_with("dht22", extra)?, &simulated_context())
+    }
+
+    #[test]
+    fn parses_options() {
+        let dht = configured_with("val_pin = 22").unwrap();
+        assert_eq!((dht.data_pin.pin(), dht.retries), (22, 2));
+        assert_eq!(configured_with("val_pin = 22\nretries = 0").unwrap().retries, 0);
+        let error = |extra| configured_with(extra).err().unwrap().to_string();
+        assert!(error("").contains("sensors.s1.val_pin"));
+        assert!(error("val_pin = 22\nretries = -1").contains("sensors.s1.retries"));
+    }
+}
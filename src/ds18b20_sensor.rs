@@ -0,0 +1,275 @@
+///
+/// DS18B20 temperature probes read through the kernel 1-Wire driver (`dtoverlay=w1-gpio`).
+///
+/// Each probe shows up as `<root>/28-<serial>/w1_slave`, reading it runs a conversion:
+///
+/// ```text
+/// 72 01 4b 46 7f ff 0e 10 57 : crc=57 YES
+/// 72 01 4b 46 7f ff 0e 10 57 t=23125
+/// ```
+///
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use failure::Error as FailureError;
+use toml::Value as TomlValue;
+use crate::gpio::GpioBackend;
+use crate::sample::Channel;
+use crate::sensor::{Sensor, Error as SensorError};
+use crate::sensor_config::SensorConfig;
+use crate::sensor_registry::SensorContext;
+
+pub const DEFAULT_ROOT: &str = "/sys/bus/w1/devices";
+/// 1-Wire family code of the DS18B20.
+const FAMILY: &str = "28-";
+/// Register contents at power on, read when a probe browned out before converting.
+const POWER_ON_RESET: i64 = 85000;
+
+#[derive(Debug)]
+pub enum Error {
+    Io { rom: String, cause: io::Error },
+    Crc { rom: String },
+    Parse { rom: String },
+    PowerOnReset { rom: String },
+    NoProbes { root: PathBuf },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Io { rom, cause } => write!(fmt, "Ds18b20 {}: {}", rom, cause),
+            Error::Crc { rom } => write!(fmt, "Ds18b20 {}: crc mismatch", rom),
+            Error::Parse { rom } => write!(fmt, "Ds18b20 {}: no temperature in w1_slave", rom),
+            Error::PowerOnReset { rom } => write!(fmt, "Ds18b20 {}: read power on reset value", rom),
+            Error::NoProbes { root } => write!(fmt, "No ds18b20 probes in {}", root.display()),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+}
+
+/// Temperature in degrees Celsius from the contents of a `w1_slave` file.
+pub fn parse(rom: &str, w1_slave: &str) -> Result<f64, Error> {
+    let mut lines = w1_slave.lines();
+    match lines.next() {
+        Some(line) if line.trim_end().ends_with("YES") => (),
+        Some(line) if line.contains("crc=") => return Err(Error::Crc { rom: rom.to_string() }),
+        _ => return Err(Error::Parse { rom: rom.to_string() }),
+    }
+    let millidegrees = lines.next()
+        .and_then(|line| line.find("t=").map(|i| &line[i + 2..]))
+        .and_then(|t| t.trim().parse::<i64>().ok())
+        .ok_or_else(|| Error::Parse { rom: rom.to_string() })?;
+    if millidegrees == POWER_ON_RESET {
+        return Err(Error::PowerOnReset { rom: rom.to_string() });
+    }
+    Ok(millidegrees as f64 / 1000.0)
+}
+
+/// ROM ids of all DS18B20 probes under `root`, sorted.
+pub fn probes(root: &Path) -> io::Result<Vec<String>> {
+    let mut roms = fs::read_dir(root)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.starts_with(FAMILY))
+        .collect::<Vec<String>>();
+    roms.sort();
+    Ok(roms)
+}
+
+pub struct Ds18b20Sensor {
+    root: PathBuf,
+    /// Probes to read, all probes found under `root` when empty.
+    roms: Vec<String>,
+}
+
+impl<G: GpioBackend> Sensor<G> for Ds18b20Sensor {
+    /// Checks that the configured probes are present.
+    fn init(&self, _gpio: &mut G) -> Result<(), SensorError> {
+        self.roms()?;
+        Ok(())
+    }
+
+    fn clear(&self, _gpio: &mut G) -> Result<(), SensorError> {
+        Ok(())
+    }
+
+    /// One probe gives a single channel, several give one channel each named by ROM id.
+    fn read(&self, _gpio: &mut G) -> Result<Vec<Channel>, SensorError> {
+        let roms = self.roms()?;
+        let temperatures = roms
+            .iter()
+            .map(|rom| self.read_probe(rom))
+            .collect::<Result<Vec<f64>, Error>>()?;
+        Ok(match temperatures.as_slice() {
+            [temperature] => vec![Channel::new("value", *temperature).with_unit("°C")],
+            _ => roms.iter().zip(temperatures).map(|(rom, t)| Channel::new(rom, t).with_unit("°C")).collect()
+        })
+    }
+}
+
+impl Ds18b20Sensor {
+    pub fn new<P: Into<PathBuf>>(root: P, roms: Vec<String>) -> Self {
+        Ds18b20Sensor { root: root.into(), roms }
+    }
+
+    fn roms(&self) -> Result<Vec<String>, Error> {
+        let roms = match self.roms.is_empty() {
+            true => probes(&self.root).map_err(|cause| Error::Io { rom: FAMILY.to_string() + "*", cause })?,
+            false => self.roms.clone()
+        };
+        if roms.is_empty() {
+            return Err(Error::NoProbes { root: self.root.clone() });
+        }
+        for rom in &roms {
+            if !self.root.join(rom).join("w1_slave").exists() {
+                return Err(Error::Io { rom: rom.clone(), cause: io::Error::from(io::ErrorKind::NotFound) });
+            }
+        }
+        Ok(roms)
+    }
+
+    fn read_probe(&self, rom: &str) -> Result<f64, Error> {
+        let w1_slave = fs::read_to_string(self.root.join(rom).join("w1_slave"))
+            .map_err(|cause| Error::Io { rom: rom.to_string(), cause })?;
+        parse(rom, &w1_slave)
+    }
+}
+
+/// Options are `rom`, one ROM id or a list of them, and `w1_root`.
+pub fn from_config<G: GpioBackend + Send + 'static>(config: &SensorConfig, _context: &SensorContext<G>)
+    -> Result<Box<dyn Sensor<G> + Send>, FailureError> {
+    Ok(Box::new(configured(config)?))
+}
+
+fn configured(config: &SensorConfig) -> Result<Ds18b20Sensor, FailureError> {
+    let root = config.optional("w1_root", |toml| { toml.as_str() }, "string")?
+        .unwrap_or(DEFAULT_ROOT);
+    let roms = match config.raw_option("rom") {
+        Some(TomlValue::String(rom)) => vec![rom.clone()],
+        Some(TomlValue::Array(roms)) => roms
+            .iter()
+            .map(|rom| rom.as_str().map(str::to_string))
+            .collect::<Option<Vec<String>>>()
+            .ok_or_else(|| config.invalid("rom", "Is not valid type, expected 'array of strings'".to_string()))?,
+        Some(v) => return Err(config.invalid(
+            "rom",
+            format!("Is not valid type, expected 'string or array' but found '{}'", v.type_str())
+        )),
+        None => Vec::new()
+    };
+    Ok(Ds18b20Sensor::new(root, roms))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensor_config::config_with;
+    use crate::gpio::simulated::SimulatedGpio;
+    use crate::sensor::Error as SensorError;
+
+    const CRC_YES: &str = "72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n";
+
+    /// A 1-Wire device directory under the system temp dir, removed when dropped.
+    struct W1Root(PathBuf);
+
+    impl W1Root {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!("ds18b20-{}-{}", std::process::id(), name));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(&root).unwrap();
+            // The bus master is listed next to the probes.
+            fs::create_dir(root.join("w1_bus_master1")).unwrap();
+            W1Root(root)
+        }
+
+        fn probe(&self, rom: &str, w1_slave: &str) -> &Self {
+            fs::create_dir(self.0.join(rom)).unwrap();
+            fs::write(self.0.join(rom).join("w1_slave"), w1_slave).unwrap();
+            self
+        }
+
+        fn read(&self, roms: &[&str]) -> Result<Vec<Channel>, SensorError> {
+            let sensor = Ds18b20Sensor::new(&self.0, roms.iter().map(|rom| rom.to_string()).collect());
+            let gpio = &mut SimulatedGpio::new();
+            sensor.init(gpio)?;
+            sensor.read(gpio)
+        }
+    }
+
+    impl Drop for W1Root {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn w1_slave(t: &str) -> String {
+        format!("{}72 01 4b 46 7f ff 0e 10 57 {}\n", CRC_YES, t)
+    }
+
+    #[test]
+    fn parses_w1_slave() {
+        assert_eq!(parse("28-1", &w1_slave("t=23125")).unwrap(), 23.125);
+        assert_eq!(parse("28-1", &w1_slave("t=-10125")).unwrap(), -10.125);
+        assert!(matches!(parse("28-1", &w1_slave("t=85000")), Err(Error::PowerOnReset { .. })));
+        assert!(matches!(parse("28-1", "72 01 4b 46 7f ff 0e 10 57 : crc=58 NO\n"), Err(Error::Crc { .. })));
+        assert!(matches!(parse("28-1", CRC_YES), Err(Error::Parse { .. })));
+        assert!(matches!(parse("28-1", ""), Err(Error::Parse { .. })));
+    }
+
+    #[test]
+    fn reads_one_probe() {
+        let root = W1Root::new("one");
+        root.probe("28-0316a2794aff", &w1_slave("t=23125"))
+            .probe("28-0316a2794b00", &w1_slave("t=-500"));
+        let channels = root.read(&["28-0316a2794aff"]).unwrap();
+        assert_eq!(channels, vec![Channel::new("value", 23.125).with_unit("°C")]);
+    }
+
+    #[test]
+    fn reads_every_probe_by_rom() {
+        let root = W1Root::new("every");
+        root.probe("28-0316a2794b00", &w1_slave("t=-500"))
+            .probe("28-0316a2794aff", &w1_slave("t=21000"));
+        let channels = root.read(&[]).unwrap();
+        assert_eq!(channels, vec![
+            Channel::new("28-0316a2794aff", 21.0).with_unit("°C"),
+            Channel::new("28-0316a2794b00", -0.5).with_unit("°C")
+        ]);
+        assert_eq!(root.read(&["28-0316a2794b00", "28-0316a2794aff"]).unwrap()[0].name, "28-0316a2794b00");
+    }
+
+    #[test]
+    fn refuses_bad_reads() {
+        let root = W1Root::new("bad");
+        root.probe("28-000000000001", "72 01 4b 46 7f ff 0e 10 57 : crc=58 NO\n72 01 4b 46 7f ff 0e 10 57 t=23125\n")
+            .probe("28-000000000002", CRC_YES)
+            .probe("28-000000000003", &w1_slave("t=22000"));
+        assert!(matches!(root.read(&["28-000000000001"]), Err(SensorError::Ds18b20(Error::Crc { .. }))));
+        assert!(matches!(root.read(&["28-000000000002"]), Err(SensorError::Ds18b20(Error::Parse { .. }))));
+        // One failing probe fails the sample.
+        assert!(matches!(root.read(&[]), Err(SensorError::Ds18b20(Error::Crc { .. }))));
+        assert!(matches!(root.read(&["28-000000000004"]), Err(SensorError::Ds18b20(Error::Io { .. }))));
+    }
+
+    #[test]
+    fn refuses_missing_probes() {
+        let root = W1Root::new("none");
+        assert!(matches!(root.read(&[]), Err(SensorError::Ds18b20(Error::NoProbes { .. }))));
+    }
+
+    #[test]
+    fn parses_options() {
+        let probe = configured(&config_with("ds18b20", "").unwrap()).unwrap();
+        assert_eq!((probe.root, probe.roms), (PathBuf::from("/sys/bus/w1/devices"), vec![]));
+        let probe = configured(&config_with("ds18b20", "rom = '28-0316a2794aff'\nw1_root = '/tmp/w1'").unwrap()).unwrap();
+        assert_eq!((probe.root, probe.roms), (PathBuf::from("/tmp/w1"), vec!["28-0316a2794aff".to_string()]));
+        let probe = configured(&config_with("ds18b20", "rom = ['28-0316a2794aff', '28-0316a2794b00']").unwrap()).unwrap();
+        assert_eq!(probe.roms.len(), 2);
+        for extra in &["rom = 28", "rom = [28]"] {
+            let error = configured(&config_with("ds18b20", extra).unwrap()).err().unwrap().to_string();
+            assert!(error.contains("sensors.s1.rom"), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+///
+/// Wrapper driving an excitation square wave for a sensor, see `pwm::Excitation`.
+///
+/// The output is started after the wrapped sensor is initialised and stopped before it is
+/// cleared, so it runs for as long as the sensor is sampled.
+///
+use std::sync::Mutex;
+use std::time::Duration;
+use crate::gpio::GpioBackend;
+use crate::gpio::claims::ClaimedPin;
+use crate::gpio::soc::Soc;
+use crate::pwm::{self, Excitation, Output};
+use crate::sample::Channel;
+use crate::sensor::{Sensor, Error as SensorError};
+
+pub struct ExcitedSensor<G: GpioBackend> {
+    sensor: Box<dyn Sensor<G> + Send>,
+    excitation: Excitation,
+    pin: ClaimedPin,
+    soc: Soc,
+    output: Mutex<Option<Output>>,
+}
+
+impl<G: GpioBackend> ExcitedSensor<G> {
+    pub fn new(sensor: Box<dyn Sensor<G> + Send>, excitation: Excitation, pin: ClaimedPin, soc: Soc) -> Self {
+        ExcitedSensor { sensor, excitation, pin, soc, output: Mutex::new(None) }
+    }
+
+    fn stop(&self, gpio: &mut G) -> Result<(), SensorError> {
+        if let Some(mut output) = self.output.lock().unwrap().take() {
+            output.stop(gpio, self.pin.pin())?;
+        }
+        Ok(())
+    }
+}
+
+impl<G: GpioBackend> Sensor<G> for ExcitedSensor<G> {
+    fn init(&self, gpio: &mut G) -> Result<(), SensorError> {
+        self.sensor.init(gpio)?;
+        self.stop(gpio)?;
+        let output = self.excitation.start(gpio, pwm::DEVMEM, self.soc)?;
+        *self.output.lock().unwrap() = Some(output);
+        Ok(())
+    }
+
+    fn clear(&self, gpio: &mut G) -> Result<(), SensorError> {
+        self.stop(gpio)?;
+        self.sensor.clear(gpio)
+    }
+
+    fn read(&self, gpio: &mut G) -> Result<Vec<Channel>, SensorError> {
+        self.sensor.read(gpio)
+    }
+
+    fn prepare(&self, gpio: &mut G) -> Result<Option<Duration>, SensorError> {
+        self.sensor.prepare(gpio)
+    }
+
+    fn measure(&self, gpio: &mut G) -> Result<Option<Duration>, SensorError> {
+        self.sensor.measure(gpio)
+    }
+
+    fn retries(&self) -> u32 {
+        self.sensor.retries()
+    }
+
+    fn retry_wait(&self) -> Duration {
+        self.sensor.retry_wait()
+    }
+}
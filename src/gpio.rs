@@ -6,9 +6,18 @@ use std::fs::{OpenOptions};
 use std::io;
 use memmap::{MmapOptions, MmapMut};
 use register::{FieldValue};
+use register::mmio::ReadWrite;
+use self::soc::Soc;
 
+pub mod cdev;
+pub mod claims;
+pub mod events;
 pub mod registers;
+pub mod restore;
+pub mod simulated;
+pub mod soc;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Mode {
     Input = 0,
     Output = 1,
@@ -24,8 +33,23 @@ impl Mode {
     pub fn as_field_value(self, pin: u8) -> FieldValue<u32, registers::GPFSEL::Register> {
         registers::pin_fsel_field(pin).val(self as u32)
     }
+
+    /// The mode of a 3 bit GPFSEL field value.
+    pub fn from_fsel(fsel: u32) -> Mode {
+        match fsel & 0b111 {
+            0 => Mode::Input,
+            1 => Mode::Output,
+            4 => Mode::Alt0,
+            5 => Mode::Alt1,
+            6 => Mode::Alt2,
+            7 => Mode::Alt3,
+            3 => Mode::Alt4,
+            _ => Mode::Alt5,
+        }
+    }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Level {
     Low = 0,
     High = 1
@@ -57,81 +81,306 @@ impl std::fmt::Display for Level {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum PullUpDown {
     Off = 0,
     Down = 1,
     Up = 2
 }
 
-pub struct Gpio {
-    mmap: MmapMut,
+/// Events that can be detected on an input pin. The synchronous events are sampled with the
+/// system clock, the async ones are detected on the raw input and catch shorter pulses.
+/// `High` and `Low` keep firing for as long as the pin is at that level.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Edge {
+    Rising,
+    Falling,
+    Both,
+    High,
+    Low,
+    AsyncRising,
+    AsyncFalling,
+}
+
+/// State of a pin as far as the backend can tell, fields it can not read are `None`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PinSnapshot {
+    pub pin: u8,
+    pub mode: Option<Mode>,
+    pub level: Option<Level>,
+    pub pull: Option<PullUpDown>,
 }
 
 #[derive(Debug)]
-pub struct Error {
-    pin: u8
+pub enum Error {
+    InvalidPin(u8),
+    Unsupported { pin: u8, operation: &'static str },
+    NotRequested(u8),
+    PinClaimed { pin: u8, owner: String, claimant: String },
+    NotClaimed(u8),
+    Io(io::Error),
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(fmt, "Gpio error with pin ({})", self.pin)
+        match self {
+            Error::InvalidPin(pin) => write!(fmt, "Invalid gpio pin ({}), expected 0 to {}", pin, PIN_COUNT - 1),
+            Error::Unsupported { pin, operation } =>
+                write!(fmt, "Gpio operation '{}' not supported on pin ({})", operation, pin),
+            Error::NotRequested(pin) => write!(fmt, "Gpio pin ({}) has not been requested", pin),
+            Error::PinClaimed { pin, owner, claimant } =>
+                write!(fmt, "Gpio pin ({}) requested by '{}' is already claimed by '{}'", pin, claimant, owner),
+            Error::NotClaimed(pin) => write!(fmt, "Gpio pin ({}) has not been claimed", pin),
+            Error::Io(err) => write!(fmt, "Gpio io error: {}", err),
+        }
     }
 }
 
 impl std::error::Error for Error {
 }
 
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+/// The BCM2835 family has 54 GPIO pins, numbered 0 to 53.
+pub const PIN_COUNT: u8 = 54;
+
 pub fn validate_pin(pin: u8) -> Result<(), Error> {
-    if pin > 54 {
-        return Err(Error { pin })
+    if pin >= PIN_COUNT {
+        return Err(Error::InvalidPin(pin))
     }
     Ok(())
 }
 
-impl Drop for Gpio {
-    fn drop(&mut self) {
+/// Bit mask of `pins`, bit n being pin n, as used by the `*_mask` operations.
+pub fn pin_mask(pins: &[u8]) -> Result<u64, Error> {
+    pins.iter().try_fold(0, |mask, pin| {
+        validate_pin(*pin)?;
+        Ok(mask | 1 << pin)
+    })
+}
+
+fn validate_mask(mask: u64) -> Result<(), Error> {
+    match mask >> PIN_COUNT {
+        0 => Ok(()),
+        invalid => Err(Error::InvalidPin(PIN_COUNT + invalid.trailing_zeros() as u8))
+    }
+}
+
+fn mask_pins(mask: u64) -> impl Iterator<Item = u8> {
+    (0..PIN_COUNT).filter(move |pin| mask & (1 << pin) != 0)
+}
+
+/// Access to GPIO pins. Implemented by the memory mapped `Gpio` as well as alternative
+/// backends, so that sensors do not depend on how the pins are reached.
+pub trait GpioBackend {
+    fn set_mode(&mut self, pin: u8, mode: Mode) -> Result<(), Error>;
+    fn read(&self, pin: u8) -> Result<Level, Error>;
+    fn set(&mut self, pin: u8) -> Result<(), Error>;
+    fn clear(&mut self, pin: u8) -> Result<(), Error>;
+    fn set_pullupdown(&mut self, pins: &[u8], updown: PullUpDown) -> Result<(), Error>;
+
+    /// Drives high all pins in `mask`, see `pin_mask`. Backends with bank registers set all
+    /// pins of a bank in one write, otherwise pins are set one at a time.
+    fn set_mask(&mut self, mask: u64) -> Result<(), Error> {
+        validate_mask(mask)?;
+        mask_pins(mask).try_for_each(|pin| self.set(pin))
+    }
+
+    /// Drives low all pins in `mask`, see `set_mask`.
+    fn clear_mask(&mut self, mask: u64) -> Result<(), Error> {
+        validate_mask(mask)?;
+        mask_pins(mask).try_for_each(|pin| self.clear(pin))
+    }
+
+    /// Levels of the pins in `mask` as a mask of the pins that are high. Backends with bank
+    /// registers read all pins of a bank at the same instant.
+    fn read_mask(&self, mask: u64) -> Result<u64, Error> {
+        validate_mask(mask)?;
+        mask_pins(mask).try_fold(0, |levels, pin| match self.read(pin)? {
+            Level::High => Ok(levels | 1 << pin),
+            Level::Low => Ok(levels)
+        })
+    }
+
+    /// Levels of `pins` read as one snapshot, in the same order as `pins`.
+    fn read_pins(&self, pins: &[u8]) -> Result<Vec<Level>, Error> {
+        let levels = self.read_mask(pin_mask(pins)?)?;
+        Ok(pins
+            .iter()
+            .map(|pin| match levels & (1 << pin) {
+                0 => Level::Low,
+                _ => Level::High
+            })
+            .collect())
+    }
+
+    fn get_mode(&self, pin: u8) -> Result<Mode, Error> {
+        Err(Error::Unsupported { pin, operation: "get_mode" })
+    }
+
+    /// The configured pull of `pin`. Not readable on every SoC.
+    fn get_pullupdown(&self, pin: u8) -> Result<PullUpDown, Error> {
+        Err(Error::Unsupported { pin, operation: "get_pullupdown" })
+    }
+
+    /// Reads mode, level and pull of all pins.
+    fn snapshot(&self) -> Vec<PinSnapshot> {
+        (0..PIN_COUNT)
+            .map(|pin| PinSnapshot {
+                pin,
+                mode: self.get_mode(pin).ok(),
+                level: self.read(pin).ok(),
+                pull: self.get_pullupdown(pin).ok(),
+            })
+            .collect()
+    }
+
+    /// Starts latching `edge` events on `pin`, see `take_event`. Enabling several kinds of
+    /// event on the same pin latches any of them.
+    fn enable_edge_detect(&mut self, pin: u8, _edge: Edge) -> Result<(), Error> {
+        Err(Error::Unsupported { pin, operation: "enable_edge_detect" })
+    }
+
+    /// Stops latching all kinds of event on `pin`.
+    fn disable_edge_detect(&mut self, pin: u8) -> Result<(), Error> {
+        Err(Error::Unsupported { pin, operation: "disable_edge_detect" })
+    }
 
+    /// Returns true if an enabled edge event has occured on `pin` since the last call.
+    fn take_event(&mut self, pin: u8) -> Result<bool, Error> {
+        Err(Error::Unsupported { pin, operation: "take_event" })
     }
 }
 
+pub struct Gpio {
+    mmap: MmapMut,
+    soc: Soc,
+}
+
 impl Gpio {
+    /// Maps the GPIO registers of the SoC found in the device tree.
     pub fn new(gpiomem: &str) -> Result<Gpio, io::Error> {
+        Gpio::with_soc(gpiomem, Soc::detect())
+    }
+
+    pub fn with_soc(gpiomem: &str, soc: Soc) -> Result<Gpio, io::Error> {
         let gpio_file = OpenOptions::new()
             .read(true)
             .write(true)
             .open(gpiomem)?;
 
-        let mmap = unsafe { MmapOptions::new().len(registers::GPIO_MEM_SIZE).map_mut(&gpio_file)? };
+        let len = match soc {
+            Soc::Bcm2835 => registers::GPIO_MEM_SIZE,
+            Soc::Bcm2711 => registers::BCM2711_GPIO_MEM_SIZE,
+        };
+        let mmap = unsafe { MmapOptions::new().len(len).map_mut(&gpio_file)? };
 
         Ok(Gpio {
-            mmap
+            mmap,
+            soc
         })
     }
 
-    pub fn set_mode(&mut self, pin: u8, mode: Mode) -> Result<(), Error> {
+    pub fn soc(&self) -> Soc {
+        self.soc
+    }
+
+    fn set_pullupdown_bcm2711(&mut self, pins: &[u8], updown: PullUpDown) -> Result<(), Error> {
+        let regs = unsafe {
+            self.mmap.as_mut_ptr().add(registers::BCM2711_PUP_PDN_OFFSET) as *mut registers::Bcm2711PullRegisters
+        };
+        // Pull up and down are swapped compared to GPPUD.
+        let value = match updown {
+            PullUpDown::Off => 0b00,
+            PullUpDown::Up => 0b01,
+            PullUpDown::Down => 0b10,
+        };
+        for pin in pins {
+            let field_val = registers::pin_pup_pdn_field(*pin).val(value);
+            match pin / 16 {
+                0 => unsafe { (*regs).GPIO_PUP_PDN_CNTRL_REG0.modify(field_val) },
+                1 => unsafe { (*regs).GPIO_PUP_PDN_CNTRL_REG1.modify(field_val) },
+                2 => unsafe { (*regs).GPIO_PUP_PDN_CNTRL_REG2.modify(field_val) },
+                3 => unsafe { (*regs).GPIO_PUP_PDN_CNTRL_REG3.modify(field_val) },
+                _ => return Err(Error::InvalidPin(*pin))
+            };
+        }
+        Ok(())
+    }
+}
+
+impl GpioBackend for Gpio {
+    fn set_mode(&mut self, pin: u8, mode: Mode) -> Result<(), Error> {
         validate_pin(pin)?;
         let regs = self.mmap.as_mut_ptr() as *mut registers::GpioRegisters;
         let field_val = mode.as_field_value(pin);
 
         match pin / 10  {
+            0 => unsafe { (*regs).GPFSEL0.modify(field_val) },
             1 => unsafe { (*regs).GPFSEL1.modify(field_val) },
             2 => unsafe { (*regs).GPFSEL2.modify(field_val) },
             3 => unsafe { (*regs).GPFSEL3.modify(field_val) },
             4 => unsafe { (*regs).GPFSEL4.modify(field_val) },
             5 => unsafe { (*regs).GPFSEL5.modify(field_val) },
-            _ => panic!("Valid pin > 54 not possible")
+            _ => return Err(Error::InvalidPin(pin))
         };
         Ok(())
     }
 
-    pub fn read(&self, pin: u8) -> Result<Level, Error> {
+    fn get_mode(&self, pin: u8) -> Result<Mode, Error> {
+        validate_pin(pin)?;
+        let regs = self.mmap.as_ptr() as *const registers::GpioRegisters;
+        let field = registers::pin_fsel_field(pin);
+
+        let fsel = match pin / 10 {
+            0 => unsafe { (*regs).GPFSEL0.read(field) },
+            1 => unsafe { (*regs).GPFSEL1.read(field) },
+            2 => unsafe { (*regs).GPFSEL2.read(field) },
+            3 => unsafe { (*regs).GPFSEL3.read(field) },
+            4 => unsafe { (*regs).GPFSEL4.read(field) },
+            5 => unsafe { (*regs).GPFSEL5.read(field) },
+            _ => return Err(Error::InvalidPin(pin))
+        };
+        Ok(Mode::from_fsel(fsel))
+    }
+
+    /// Only readable on BCM2711, the legacy GPPUD registers are write only.
+    fn get_pullupdown(&self, pin: u8) -> Result<PullUpDown, Error> {
+        validate_pin(pin)?;
+        if self.soc != Soc::Bcm2711 {
+            return Err(Error::Unsupported { pin, operation: "get_pullupdown" });
+        }
+        let regs = unsafe {
+            self.mmap.as_ptr().add(registers::BCM2711_PUP_PDN_OFFSET) as *const registers::Bcm2711PullRegisters
+        };
+        let field = registers::pin_pup_pdn_field(pin);
+
+        let pull = match pin / 16 {
+            0 => unsafe { (*regs).GPIO_PUP_PDN_CNTRL_REG0.read(field) },
+            1 => unsafe { (*regs).GPIO_PUP_PDN_CNTRL_REG1.read(field) },
+            2 => unsafe { (*regs).GPIO_PUP_PDN_CNTRL_REG2.read(field) },
+            3 => unsafe { (*regs).GPIO_PUP_PDN_CNTRL_REG3.read(field) },
+            _ => return Err(Error::InvalidPin(pin))
+        };
+        match pull {
+            0b01 => Ok(PullUpDown::Up),
+            0b10 => Ok(PullUpDown::Down),
+            _ => Ok(PullUpDown::Off)
+        }
+    }
+
+    fn read(&self, pin: u8) -> Result<Level, Error> {
         validate_pin(pin)?;
         let regs = self.mmap.as_ptr() as *const registers::GpioRegisters;
 
         let set = match pin / 32 {
             0 => unsafe { (*regs).GPLEV0.is_set(registers::pin_lev_field(pin)) },
             1 => unsafe { (*regs).GPLEV1.is_set(registers::pin_lev_field(pin)) },
-            _ => panic!("Valid pin > 54 not possible")
+            _ => return Err(Error::InvalidPin(pin))
         };
 
         match set {
@@ -140,7 +389,7 @@ impl Gpio {
         }
     }
 
-    pub fn set(&mut self, pin: u8) -> Result<(), Error> {
+    fn set(&mut self, pin: u8) -> Result<(), Error> {
         validate_pin(pin)?;
         let regs = self.mmap.as_mut_ptr() as *mut registers::GpioRegisters;
 
@@ -148,32 +397,72 @@ impl Gpio {
         match pin / 32 {
             0 => unsafe { (*regs).GPSET0.modify(registers::pin_set_value(pin)) },
             1 => unsafe { (*regs).GPSET1.modify(registers::pin_set_value(pin)) },
-            _ => panic!("Valid pin > 54 not possible")
+            _ => return Err(Error::InvalidPin(pin))
         };
 
         Ok(())
     }
 
-    pub fn clear(&mut self, pin: u8) -> Result<(), Error> {
+    fn clear(&mut self, pin: u8) -> Result<(), Error> {
         validate_pin(pin)?;
         let regs = self.mmap.as_mut_ptr() as *mut registers::GpioRegisters;
 
         match pin / 32 {
             0 => unsafe { (*regs).GPCLR0.modify(registers::pin_clear_value(pin)) },
             1 => unsafe { (*regs).GPCLR1.modify(registers::pin_clear_value(pin)) },
-            _ => panic!("Valid pin > 54 not possible")
+            _ => return Err(Error::InvalidPin(pin))
         };
 
         Ok(())
     }
 
+    fn set_mask(&mut self, mask: u64) -> Result<(), Error> {
+        validate_mask(mask)?;
+        let regs = self.mmap.as_mut_ptr() as *mut registers::GpioRegisters;
+        let (bank0, bank1) = (mask as u32, (mask >> 32) as u32);
+
+        if bank0 != 0 {
+            unsafe { (*regs).GPSET0.set(bank0) };
+        }
+        if bank1 != 0 {
+            unsafe { (*regs).GPSET1.set(bank1) };
+        }
+        Ok(())
+    }
+
+    fn clear_mask(&mut self, mask: u64) -> Result<(), Error> {
+        validate_mask(mask)?;
+        let regs = self.mmap.as_mut_ptr() as *mut registers::GpioRegisters;
+        let (bank0, bank1) = (mask as u32, (mask >> 32) as u32);
+
+        if bank0 != 0 {
+            unsafe { (*regs).GPCLR0.set(bank0) };
+        }
+        if bank1 != 0 {
+            unsafe { (*regs).GPCLR1.set(bank1) };
+        }
+        Ok(())
+    }
+
+    fn read_mask(&self, mask: u64) -> Result<u64, Error> {
+        validate_mask(mask)?;
+        let regs = self.mmap.as_ptr() as *const registers::GpioRegisters;
+
+        let bank0 = if mask as u32 != 0 { unsafe { (*regs).GPLEV0.get() } } else { 0 };
+        let bank1 = if (mask >> 32) as u32 != 0 { unsafe { (*regs).GPLEV1.get() } } else { 0 };
+        Ok((bank0 as u64 | (bank1 as u64) << 32) & mask)
+    }
+
     /// Sets actuates the pull-up or pull-down resistors of the supplied pins.
     /// This method is not safe to run simiultaneusly on the same Raspberry PI.
     /// Doing so has undefined behaviour.
-    pub fn set_pullupdown(&mut self, pins: &[u8], updown: PullUpDown) -> Result<(), Error> {
+    fn set_pullupdown(&mut self, pins: &[u8], updown: PullUpDown) -> Result<(), Error> {
         for pin in pins {
             validate_pin(*pin)?;
         }
+        if self.soc == Soc::Bcm2711 {
+            return self.set_pullupdown_bcm2711(pins, updown);
+        }
         let regs = self.mmap.as_mut_ptr() as *mut registers::GpioRegisters;
 
         unsafe { (*regs).GPPUD.modify(registers::GPPUD::PUD.val(updown as u32)); }
@@ -184,14 +473,268 @@ impl Gpio {
             match pin / 32 {
                 0 => unsafe { (*regs).GPPUDCLK0.write(registers::pin_pudclk_value(*pin)) },
                 1 => unsafe { (*regs).GPPUDCLK1.write(registers::pin_pudclk_value(*pin)) },
-                _ => panic!("Valid pin > 54 not possible")
+                _ => return Err(Error::InvalidPin(*pin))
             };
         }
         // Required wait of 150 cycles.
         std::thread::sleep(std::time::Duration::from_millis(1));
         unsafe { (*regs).GPPUDCLK0.set(0) };
+        unsafe { (*regs).GPPUDCLK1.set(0) };
         unsafe { (*regs).GPPUD.modify(registers::GPPUD::PUD::OFF) };
 
         Ok(())
     }
+
+    fn enable_edge_detect(&mut self, pin: u8, edge: Edge) -> Result<(), Error> {
+        validate_pin(pin)?;
+        let regs = self.mmap.as_mut_ptr() as *mut registers::GpioRegisters;
+        let bit = registers::pin_bit(pin);
+        let bank = (pin / 32) as usize;
+
+        unsafe {
+            match edge {
+                Edge::Rising => set_bit([&(*regs).GPREN0, &(*regs).GPREN1][bank], bit),
+                Edge::Falling => set_bit([&(*regs).GPFEN0, &(*regs).GPFEN1][bank], bit),
+                Edge::Both => {
+                    set_bit([&(*regs).GPREN0, &(*regs).GPREN1][bank], bit);
+                    set_bit([&(*regs).GPFEN0, &(*regs).GPFEN1][bank], bit);
+                },
+                Edge::High => set_bit([&(*regs).GPHEN0, &(*regs).GPHEN1][bank], bit),
+                Edge::Low => set_bit([&(*regs).GPLEN0, &(*regs).GPLEN1][bank], bit),
+                Edge::AsyncRising => set_bit([&(*regs).GPAREN0, &(*regs).GPAREN1][bank], bit),
+                Edge::AsyncFalling => set_bit([&(*regs).GPAFEN0, &(*regs).GPAFEN1][bank], bit),
+            }
+        }
+        Ok(())
+    }
+
+    fn disable_edge_detect(&mut self, pin: u8) -> Result<(), Error> {
+        validate_pin(pin)?;
+        let regs = self.mmap.as_mut_ptr() as *mut registers::GpioRegisters;
+        let bit = registers::pin_bit(pin);
+        let bank = (pin / 32) as usize;
+
+        unsafe {
+            clear_bit([&(*regs).GPREN0, &(*regs).GPREN1][bank], bit);
+            clear_bit([&(*regs).GPFEN0, &(*regs).GPFEN1][bank], bit);
+            clear_bit([&(*regs).GPHEN0, &(*regs).GPHEN1][bank], bit);
+            clear_bit([&(*regs).GPLEN0, &(*regs).GPLEN1][bank], bit);
+            clear_bit([&(*regs).GPAREN0, &(*regs).GPAREN1][bank], bit);
+            clear_bit([&(*regs).GPAFEN0, &(*regs).GPAFEN1][bank], bit);
+            // Drop any event latched before detection was disabled.
+            [&(*regs).GPEDS0, &(*regs).GPEDS1][bank].set(bit);
+        }
+        Ok(())
+    }
+
+    fn take_event(&mut self, pin: u8) -> Result<bool, Error> {
+        validate_pin(pin)?;
+        let regs = self.mmap.as_mut_ptr() as *mut registers::GpioRegisters;
+        let bit = registers::pin_bit(pin);
+        let eds = unsafe { [&(*regs).GPEDS0, &(*regs).GPEDS1][(pin / 32) as usize] };
+
+        let detected = eds.get() & bit != 0;
+        if detected {
+            // Status bits are cleared by writing 1 to them, other bits are left untouched.
+            eds.set(bit);
+        }
+        Ok(detected)
+    }
+}
+
+fn set_bit<R: register::RegisterLongName>(reg: &ReadWrite<u32, R>, bit: u32) {
+    reg.set(reg.get() | bit);
+}
+
+fn clear_bit<R: register::RegisterLongName>(reg: &ReadWrite<u32, R>, bit: u32) {
+    reg.set(reg.get() & !bit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GPFSEL0: usize = 0x00;
+    const GPFSEL1: usize = 0x04;
+    const GPFSEL5: usize = 0x14;
+    const GPSET0: usize = 0x1c;
+    const GPSET1: usize = 0x20;
+    const GPCLR0: usize = 0x28;
+    const GPCLR1: usize = 0x2c;
+    const GPLEV0: usize = 0x34;
+    const GPLEV1: usize = 0x38;
+    const GPEDS0: usize = 0x40;
+    const GPREN0: usize = 0x4c;
+    const GPFEN0: usize = 0x58;
+    const GPAREN1: usize = 0x80;
+
+    const GPIO_PUP_PDN_CNTRL_REG0: usize = registers::BCM2711_PUP_PDN_OFFSET;
+    const GPIO_PUP_PDN_CNTRL_REG3: usize = registers::BCM2711_PUP_PDN_OFFSET + 0x0c;
+
+    /// A `Gpio` over plain memory, so registers read back what was last written to them.
+    fn memory_gpio() -> Gpio {
+        memory_gpio_of(Soc::Bcm2835)
+    }
+
+    fn memory_gpio_of(soc: Soc) -> Gpio {
+        let mmap = MmapOptions::new().len(registers::BCM2711_GPIO_MEM_SIZE).map_anon().unwrap();
+        Gpio { mmap, soc }
+    }
+
+    fn register(gpio: &Gpio, offset: usize) -> u32 {
+        let mut word = [0; 4];
+        word.copy_from_slice(&gpio.mmap[offset..offset + 4]);
+        u32::from_ne_bytes(word)
+    }
+
+    fn set_register(gpio: &mut Gpio, offset: usize, value: u32) {
+        gpio.mmap[offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+    }
+
+    #[test]
+    fn validates_pins() {
+        assert!(validate_pin(0).is_ok());
+        assert!(validate_pin(53).is_ok());
+        assert!(matches!(validate_pin(54), Err(Error::InvalidPin(54))));
+    }
+
+    #[test]
+    fn sets_mode_of_every_bank() {
+        let mut gpio = memory_gpio();
+        gpio.set_mode(4, Mode::Output).unwrap();
+        gpio.set_mode(5, Mode::Alt0).unwrap();
+        assert_eq!(register(&gpio, GPFSEL0), 0b100_001 << 12);
+        gpio.set_mode(53, Mode::Alt5).unwrap();
+        assert_eq!(register(&gpio, GPFSEL5), 0b010 << 9);
+    }
+
+    #[test]
+    fn decodes_modes_from_gpfsel() {
+        let mut gpio = memory_gpio();
+        let modes = [Mode::Input, Mode::Output, Mode::Alt0, Mode::Alt1, Mode::Alt2, Mode::Alt3, Mode::Alt4, Mode::Alt5];
+        for (pin, mode) in modes.iter().enumerate() {
+            gpio.set_mode(pin as u8 + 10, *mode).unwrap();
+        }
+        assert_eq!(register(&gpio, GPFSEL1), 0b010_011_111_110_101_100_001_000);
+        for (pin, mode) in modes.iter().enumerate() {
+            assert_eq!(gpio.get_mode(pin as u8 + 10).unwrap(), *mode);
+        }
+
+        set_register(&mut gpio, GPFSEL5, 0b100 << 9);
+        assert_eq!(gpio.get_mode(53).unwrap(), Mode::Alt0);
+        assert!(matches!(gpio.get_mode(54), Err(Error::InvalidPin(54))));
+    }
+
+    #[test]
+    fn snapshots_every_pin() {
+        let mut sim = simulated::SimulatedGpio::new();
+        sim.set_mode(4, Mode::Output).unwrap();
+        sim.set(4).unwrap();
+        sim.set_pullupdown(&[17], PullUpDown::Up).unwrap();
+
+        let snapshot = sim.snapshot();
+        assert_eq!(snapshot.len(), PIN_COUNT as usize);
+        assert_eq!(snapshot[4], PinSnapshot {
+            pin: 4, mode: Some(Mode::Output), level: Some(Level::High), pull: Some(PullUpDown::Off)
+        });
+        assert_eq!(snapshot[17], PinSnapshot {
+            pin: 17, mode: Some(Mode::Input), level: Some(Level::High), pull: Some(PullUpDown::Up)
+        });
+        // The legacy pull registers are write only.
+        assert_eq!(memory_gpio().snapshot()[17].pull, None);
+    }
+
+    #[test]
+    fn refuses_invalid_pins() {
+        let mut gpio = memory_gpio();
+        assert!(matches!(gpio.set_mode(54, Mode::Output), Err(Error::InvalidPin(54))));
+        assert!(matches!(gpio.read(60), Err(Error::InvalidPin(60))));
+        assert!(matches!(gpio.set(54), Err(Error::InvalidPin(54))));
+        assert!(matches!(gpio.clear(255), Err(Error::InvalidPin(255))));
+        assert!(matches!(gpio.set_pullupdown(&[4, 54], PullUpDown::Up), Err(Error::InvalidPin(54))));
+        gpio.set(53).unwrap();
+        assert_eq!(register(&gpio, GPSET1), 1 << 21);
+    }
+
+    #[test]
+    fn enables_edge_detect_per_pin() {
+        let mut gpio = memory_gpio();
+        gpio.enable_edge_detect(3, Edge::Rising).unwrap();
+        gpio.enable_edge_detect(7, Edge::Both).unwrap();
+        gpio.enable_edge_detect(40, Edge::AsyncRising).unwrap();
+        assert_eq!(register(&gpio, GPREN0), 1 << 3 | 1 << 7);
+        assert_eq!(register(&gpio, GPFEN0), 1 << 7);
+        assert_eq!(register(&gpio, GPAREN1), 1 << 8);
+
+        gpio.disable_edge_detect(7).unwrap();
+        assert_eq!(register(&gpio, GPREN0), 1 << 3);
+        assert_eq!(register(&gpio, GPFEN0), 0);
+        assert_eq!(register(&gpio, GPEDS0), 1 << 7);
+    }
+
+    #[test]
+    fn take_event_clears_only_its_latched_bit() {
+        let mut gpio = memory_gpio();
+        set_register(&mut gpio, GPEDS0, 1 << 3 | 1 << 7);
+        assert!(gpio.take_event(3).unwrap());
+        // Plain memory keeps what was written, on the chip writing the bit clears it.
+        assert_eq!(register(&gpio, GPEDS0), 1 << 3);
+
+        set_register(&mut gpio, GPEDS0, 1 << 7);
+        assert!(!gpio.take_event(3).unwrap());
+        assert_eq!(register(&gpio, GPEDS0), 1 << 7);
+    }
+
+    #[test]
+    fn sets_pulls_of_bcm2711() {
+        let mut gpio = memory_gpio_of(Soc::Bcm2711);
+        gpio.set_pullupdown(&[0, 2], PullUpDown::Up).unwrap();
+        gpio.set_pullupdown(&[1, 53], PullUpDown::Down).unwrap();
+        assert_eq!(register(&gpio, GPIO_PUP_PDN_CNTRL_REG0), 0b01_10_01);
+        assert_eq!(register(&gpio, GPIO_PUP_PDN_CNTRL_REG3), 0b10 << 10);
+
+        gpio.set_pullupdown(&[2], PullUpDown::Off).unwrap();
+        assert_eq!(register(&gpio, GPIO_PUP_PDN_CNTRL_REG0), 0b10_01);
+    }
+
+    #[test]
+    fn splits_masks_over_banks() {
+        let mut gpio = memory_gpio();
+        let mask = pin_mask(&[2, 31, 32, 53]).unwrap();
+        assert_eq!(mask, 1 << 2 | 1 << 31 | 1 << 32 | 1 << 53);
+
+        gpio.set_mask(mask).unwrap();
+        assert_eq!(register(&gpio, GPSET0), 1 << 2 | 1 << 31);
+        assert_eq!(register(&gpio, GPSET1), 1 | 1 << 21);
+        gpio.clear_mask(1 << 40).unwrap();
+        assert_eq!(register(&gpio, GPCLR0), 0);
+        assert_eq!(register(&gpio, GPCLR1), 1 << 8);
+
+        set_register(&mut gpio, GPLEV0, 1 << 2 | 1 << 5);
+        set_register(&mut gpio, GPLEV1, 1 << 21);
+        assert_eq!(gpio.read_mask(mask).unwrap(), 1 << 2 | 1 << 53);
+        assert_eq!(gpio.read_pins(&[53, 5, 32]).unwrap(), vec![Level::High, Level::High, Level::Low]);
+    }
+
+    #[test]
+    fn refuses_pins_above_53_in_masks() {
+        let mut gpio = memory_gpio();
+        assert!(matches!(pin_mask(&[3, 54]), Err(Error::InvalidPin(54))));
+        assert!(matches!(gpio.set_mask(1 << 3 | 1 << 60), Err(Error::InvalidPin(60))));
+        assert!(matches!(gpio.clear_mask(1 << 54), Err(Error::InvalidPin(54))));
+        assert!(matches!(gpio.read_mask(1 << 63), Err(Error::InvalidPin(63))));
+        assert_eq!(register(&gpio, GPSET0), 0);
+        assert_eq!(register(&gpio, GPCLR1), 0);
+    }
+
+    #[test]
+    fn falls_back_to_single_pins_without_bank_registers() {
+        let mut sim = simulated::SimulatedGpio::new();
+        sim.set_mode(3, Mode::Output).unwrap();
+        sim.set_mode(40, Mode::Output).unwrap();
+        sim.set_mask(1 << 3 | 1 << 40).unwrap();
+        assert_eq!(sim.read_mask(1 << 3 | 1 << 4 | 1 << 40).unwrap(), 1 << 3 | 1 << 40);
+        sim.clear_mask(1 << 40).unwrap();
+        assert_eq!(sim.read_pins(&[40, 3]).unwrap(), vec![Level::Low, Level::High]);
+    }
 }
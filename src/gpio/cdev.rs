@@ -0,0 +1,700 @@
+///
+/// GPIO backend using the Linux GPIO character device uAPI (`/dev/gpiochipN`).
+///
+/// Lines are requested from the kernel when the mode of a pin is set and held until the
+/// backend is dropped, so other processes can not use them meanwhile. Only `Mode::Input` and
+/// `Mode::Output` can be requested, alternate functions need the memory mapped `Gpio`.
+/// https://www.kernel.org/doc/html/latest/userspace-api/gpio/chardev_v1.html
+///
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use super::{validate_pin, Edge, Error, GpioBackend, Level, Mode, PullUpDown};
+
+const GPIOHANDLES_MAX: usize = 64;
+const CONSUMER_LABEL: &[u8] = b"rpi-moisture-sensor";
+
+pub const GPIOHANDLE_REQUEST_INPUT: u32 = 1 << 0;
+pub const GPIOHANDLE_REQUEST_OUTPUT: u32 = 1 << 1;
+pub const GPIOHANDLE_REQUEST_BIAS_PULL_UP: u32 = 1 << 5;
+pub const GPIOHANDLE_REQUEST_BIAS_PULL_DOWN: u32 = 1 << 6;
+pub const GPIOHANDLE_REQUEST_BIAS_DISABLE: u32 = 1 << 7;
+
+pub const GPIOLINE_FLAG_IS_OUT: u32 = 1 << 1;
+pub const GPIOLINE_FLAG_BIAS_PULL_UP: u32 = 1 << 5;
+pub const GPIOLINE_FLAG_BIAS_PULL_DOWN: u32 = 1 << 6;
+
+pub const GPIOEVENT_REQUEST_RISING_EDGE: u32 = 1 << 0;
+pub const GPIOEVENT_REQUEST_FALLING_EDGE: u32 = 1 << 1;
+pub const GPIOEVENT_REQUEST_BOTH_EDGES: u32 = GPIOEVENT_REQUEST_RISING_EDGE | GPIOEVENT_REQUEST_FALLING_EDGE;
+
+#[repr(C)]
+struct GpioLineInfo {
+    line_offset: u32,
+    flags: u32,
+    name: [u8; 32],
+    consumer: [u8; 32],
+}
+
+#[repr(C)]
+struct GpioHandleRequest {
+    lineoffsets: [u32; GPIOHANDLES_MAX],
+    flags: u32,
+    default_values: [u8; GPIOHANDLES_MAX],
+    consumer_label: [u8; 32],
+    lines: u32,
+    fd: libc::c_int,
+}
+
+#[repr(C)]
+struct GpioHandleData {
+    values: [u8; GPIOHANDLES_MAX],
+}
+
+#[repr(C)]
+struct GpioEventRequest {
+    lineoffset: u32,
+    handleflags: u32,
+    eventflags: u32,
+    consumer_label: [u8; 32],
+    fd: libc::c_int,
+}
+
+#[repr(C)]
+struct GpioEventData {
+    timestamp: u64,
+    id: u32,
+}
+
+const fn iowr(nr: u32, size: usize) -> u32 {
+    (3 << 30) | ((size as u32) << 16) | (0xB4 << 8) | nr
+}
+
+const GPIO_GET_LINEINFO_IOCTL: u32 = iowr(0x02, std::mem::size_of::<GpioLineInfo>());
+const GPIO_GET_LINEHANDLE_IOCTL: u32 = iowr(0x03, std::mem::size_of::<GpioHandleRequest>());
+const GPIO_GET_LINEEVENT_IOCTL: u32 = iowr(0x04, std::mem::size_of::<GpioEventRequest>());
+const GPIOHANDLE_GET_LINE_VALUES_IOCTL: u32 = iowr(0x08, std::mem::size_of::<GpioHandleData>());
+const GPIOHANDLE_SET_LINE_VALUES_IOCTL: u32 = iowr(0x09, std::mem::size_of::<GpioHandleData>());
+
+/// The ioctl layer of a GPIO chip. `Chip` talks to the kernel, tests can supply an in-process
+/// fake instead.
+pub trait LineChip {
+    type Handle;
+
+    /// The `GPIOLINE_FLAG_*` flags of the line at `offset`, requested or not.
+    fn line_flags(&self, offset: u32) -> io::Result<u32>;
+    /// Requests the line at `offset` with `GPIOHANDLE_REQUEST_*` flags.
+    fn request_line(&mut self, offset: u32, flags: u32, default_value: u8) -> io::Result<Self::Handle>;
+    /// Requests the line at `offset` for `GPIOEVENT_REQUEST_*` events.
+    fn request_events(&mut self, offset: u32, handle_flags: u32, event_flags: u32) -> io::Result<Self::Handle>;
+    fn get_value(&self, handle: &Self::Handle) -> io::Result<u8>;
+    fn set_value(&self, handle: &Self::Handle, value: u8) -> io::Result<()>;
+    /// Consumes all pending events of an event handle without blocking and returns how many
+    /// there were.
+    fn take_events(&self, handle: &Self::Handle) -> io::Result<usize>;
+}
+
+pub struct Chip {
+    file: File,
+}
+
+impl Chip {
+    pub fn open(path: &str) -> io::Result<Chip> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)?;
+        Ok(Chip { file })
+    }
+}
+
+fn consumer_label() -> [u8; 32] {
+    let mut label = [0; 32];
+    label[..CONSUMER_LABEL.len()].copy_from_slice(CONSUMER_LABEL);
+    label
+}
+
+fn ioctl<T>(fd: libc::c_int, request: u32, arg: &mut T) -> io::Result<()> {
+    match unsafe { libc::ioctl(fd, request as _, arg as *mut T) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(())
+    }
+}
+
+impl LineChip for Chip {
+    type Handle = File;
+
+    fn line_flags(&self, offset: u32) -> io::Result<u32> {
+        let mut info = GpioLineInfo { line_offset: offset, flags: 0, name: [0; 32], consumer: [0; 32] };
+        ioctl(self.file.as_raw_fd(), GPIO_GET_LINEINFO_IOCTL, &mut info)?;
+        Ok(info.flags)
+    }
+
+    fn request_line(&mut self, offset: u32, flags: u32, default_value: u8) -> io::Result<File> {
+        let mut req = GpioHandleRequest {
+            lineoffsets: [0; GPIOHANDLES_MAX],
+            flags,
+            default_values: [0; GPIOHANDLES_MAX],
+            consumer_label: consumer_label(),
+            lines: 1,
+            fd: -1,
+        };
+        req.lineoffsets[0] = offset;
+        req.default_values[0] = default_value;
+        ioctl(self.file.as_raw_fd(), GPIO_GET_LINEHANDLE_IOCTL, &mut req)?;
+        Ok(unsafe { File::from_raw_fd(req.fd) })
+    }
+
+    fn request_events(&mut self, offset: u32, handle_flags: u32, event_flags: u32) -> io::Result<File> {
+        let mut req = GpioEventRequest {
+            lineoffset: offset,
+            handleflags: handle_flags,
+            eventflags: event_flags,
+            consumer_label: consumer_label(),
+            fd: -1,
+        };
+        ioctl(self.file.as_raw_fd(), GPIO_GET_LINEEVENT_IOCTL, &mut req)?;
+        let file = unsafe { File::from_raw_fd(req.fd) };
+        let fd_flags = unsafe { libc::fcntl(req.fd, libc::F_GETFL) };
+        if fd_flags == -1 || unsafe { libc::fcntl(req.fd, libc::F_SETFL, fd_flags | libc::O_NONBLOCK) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(file)
+    }
+
+    fn get_value(&self, handle: &File) -> io::Result<u8> {
+        let mut data = GpioHandleData { values: [0; GPIOHANDLES_MAX] };
+        ioctl(handle.as_raw_fd(), GPIOHANDLE_GET_LINE_VALUES_IOCTL, &mut data)?;
+        Ok(data.values[0])
+    }
+
+    fn set_value(&self, handle: &File, value: u8) -> io::Result<()> {
+        let mut data = GpioHandleData { values: [0; GPIOHANDLES_MAX] };
+        data.values[0] = value;
+        ioctl(handle.as_raw_fd(), GPIOHANDLE_SET_LINE_VALUES_IOCTL, &mut data)
+    }
+
+    fn take_events(&self, mut handle: &File) -> io::Result<usize> {
+        let event_size = std::mem::size_of::<GpioEventData>();
+        let mut buf = vec![0; event_size * 16];
+        let mut events = 0;
+        loop {
+            match handle.read(&mut buf) {
+                Ok(0) => return Ok(events),
+                Ok(n) => events += n / event_size,
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(events),
+                Err(err) => return Err(err)
+            }
+        }
+    }
+}
+
+/// How a line is configured, whether or not it could be requested that way.
+#[derive(Clone, Copy, Debug)]
+struct Settings {
+    mode: Option<Mode>,
+    /// `None` until a pull is set, the line is then requested without bias flags.
+    pull: Option<PullUpDown>,
+    output: Level,
+    /// `GPIOEVENT_REQUEST_*` flags, 0 without edge detection.
+    events: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings { mode: None, pull: None, output: Level::Low, events: 0 }
+    }
+}
+
+/// A kernel request for a line.
+enum Request {
+    Line { flags: u32, default_value: u8 },
+    Events { handle_flags: u32, event_flags: u32 },
+}
+
+impl Settings {
+    /// The request matching the settings, `None` until a mode is set. Fails for settings the
+    /// character device can not express.
+    fn request(&self, pin: u8) -> Result<Option<Request>, Error> {
+        // Kernels before 5.5 refuse any bias flag, so none is sent unless a pull was set.
+        let bias = match self.pull {
+            None => 0,
+            Some(PullUpDown::Off) => GPIOHANDLE_REQUEST_BIAS_DISABLE,
+            Some(PullUpDown::Down) => GPIOHANDLE_REQUEST_BIAS_PULL_DOWN,
+            Some(PullUpDown::Up) => GPIOHANDLE_REQUEST_BIAS_PULL_UP,
+        };
+        match (self.mode, self.events) {
+            (None, _) => Ok(None),
+            (Some(Mode::Input), 0) =>
+                Ok(Some(Request::Line { flags: GPIOHANDLE_REQUEST_INPUT | bias, default_value: 0 })),
+            (Some(Mode::Input), event_flags) =>
+                Ok(Some(Request::Events { handle_flags: GPIOHANDLE_REQUEST_INPUT | bias, event_flags })),
+            (Some(Mode::Output), _) =>
+                Ok(Some(Request::Line { flags: GPIOHANDLE_REQUEST_OUTPUT | bias, default_value: self.output as u8 })),
+            (Some(_), _) => Err(Error::Unsupported { pin, operation: "alternate function" })
+        }
+    }
+
+    /// Whether the line is requested for events. Edge detection stays configured while the
+    /// line is an output, but no events are watched meanwhile.
+    fn watches_events(&self) -> bool {
+        self.mode == Some(Mode::Input) && self.events != 0
+    }
+}
+
+/// The `GPIOEVENT_REQUEST_*` flags detecting `edge`. The kernel detects edges from interrupts,
+/// which is what the async events of the register interface do as well, so both map to the
+/// same flags. Level events have no counterpart.
+fn event_flags(pin: u8, edge: Edge) -> Result<u32, Error> {
+    match edge {
+        Edge::Rising | Edge::AsyncRising => Ok(GPIOEVENT_REQUEST_RISING_EDGE),
+        Edge::Falling | Edge::AsyncFalling => Ok(GPIOEVENT_REQUEST_FALLING_EDGE),
+        Edge::Both => Ok(GPIOEVENT_REQUEST_BOTH_EDGES),
+        Edge::High | Edge::Low => Err(Error::Unsupported { pin, operation: "level detect" })
+    }
+}
+
+fn request<C: LineChip>(chip: &mut C, pin: u8, request: &Option<Request>) -> io::Result<Option<C::Handle>> {
+    match request {
+        None => Ok(None),
+        Some(Request::Line { flags, default_value }) =>
+            chip.request_line(u32::from(pin), *flags, *default_value).map(Some),
+        Some(Request::Events { handle_flags, event_flags }) =>
+            chip.request_events(u32::from(pin), *handle_flags, *event_flags).map(Some),
+    }
+}
+
+struct Line<H> {
+    settings: Settings,
+    handle: Option<H>,
+}
+
+impl<H> Default for Line<H> {
+    fn default() -> Self {
+        Line { settings: Settings::default(), handle: None }
+    }
+}
+
+pub struct CdevGpio<C: LineChip = Chip> {
+    chip: C,
+    lines: HashMap<u8, Line<C::Handle>>,
+}
+
+impl CdevGpio<Chip> {
+    pub fn open(path: &str) -> io::Result<CdevGpio<Chip>> {
+        Ok(CdevGpio::with_chip(Chip::open(path)?))
+    }
+}
+
+impl<C: LineChip> CdevGpio<C> {
+    pub fn with_chip(chip: C) -> Self {
+        CdevGpio { chip, lines: HashMap::new() }
+    }
+
+    /// Applies `change` to the settings of `pin` and requests the line again accordingly.
+    /// Settings that can not be requested are refused before the current request is released,
+    /// if the kernel refuses the new request the previous one is taken back.
+    fn reconfigure<F: FnOnce(&mut Settings)>(&mut self, pin: u8, change: F) -> Result<(), Error> {
+        validate_pin(pin)?;
+        let line = self.lines.entry(pin).or_default();
+        let mut settings = line.settings;
+        change(&mut settings);
+        let new_request = settings.request(pin)?;
+        // The kernel grants one request per line, so the current one goes first.
+        line.handle = None;
+        match request(&mut self.chip, pin, &new_request) {
+            Ok(handle) => {
+                line.settings = settings;
+                line.handle = handle;
+                Ok(())
+            },
+            Err(err) => {
+                let chip = &mut self.chip;
+                line.handle = line.settings.request(pin)
+                    .ok()
+                    .and_then(|previous| request(chip, pin, &previous).ok())
+                    .and_then(|handle| handle);
+                Err(Error::from(err))
+            }
+        }
+    }
+
+    fn write(&mut self, pin: u8, level: Level) -> Result<(), Error> {
+        validate_pin(pin)?;
+        let line = self.lines.entry(pin).or_default();
+        line.settings.output = level;
+        match (line.settings.mode, &line.handle) {
+            (Some(Mode::Output), Some(handle)) => Ok(self.chip.set_value(handle, level as u8)?),
+            _ => Ok(())
+        }
+    }
+}
+
+impl<C: LineChip> GpioBackend for CdevGpio<C> {
+    fn set_mode(&mut self, pin: u8, mode: Mode) -> Result<(), Error> {
+        self.reconfigure(pin, |settings| settings.mode = Some(mode))
+    }
+
+    fn read(&self, pin: u8) -> Result<Level, Error> {
+        validate_pin(pin)?;
+        let handle = self.lines
+            .get(&pin)
+            .and_then(|line| line.handle.as_ref())
+            .ok_or(Error::NotRequested(pin))?;
+        match self.chip.get_value(handle)? {
+            0 => Ok(Level::Low),
+            _ => Ok(Level::High)
+        }
+    }
+
+    /// Lines requested by other consumers are reported as input or output, the kernel does not
+    /// expose alternate functions.
+    fn get_mode(&self, pin: u8) -> Result<Mode, Error> {
+        validate_pin(pin)?;
+        if let Some(Line { settings: Settings { mode: Some(mode), .. }, .. }) = self.lines.get(&pin) {
+            return Ok(*mode);
+        }
+        match self.chip.line_flags(u32::from(pin))? & GPIOLINE_FLAG_IS_OUT {
+            0 => Ok(Mode::Input),
+            _ => Ok(Mode::Output)
+        }
+    }
+
+    /// Lines without a configured pull report the bias the kernel has for them.
+    fn get_pullupdown(&self, pin: u8) -> Result<PullUpDown, Error> {
+        validate_pin(pin)?;
+        if let Some(Line { settings: Settings { mode: Some(_), pull: Some(pull), .. }, .. }) = self.lines.get(&pin) {
+            return Ok(*pull);
+        }
+        let flags = self.chip.line_flags(u32::from(pin))?;
+        if flags & GPIOLINE_FLAG_BIAS_PULL_UP != 0 {
+            Ok(PullUpDown::Up)
+        } else if flags & GPIOLINE_FLAG_BIAS_PULL_DOWN != 0 {
+            Ok(PullUpDown::Down)
+        } else {
+            Ok(PullUpDown::Off)
+        }
+    }
+
+    fn set(&mut self, pin: u8) -> Result<(), Error> {
+        self.write(pin, Level::High)
+    }
+
+    fn clear(&mut self, pin: u8) -> Result<(), Error> {
+        self.write(pin, Level::Low)
+    }
+
+    fn set_pullupdown(&mut self, pins: &[u8], updown: PullUpDown) -> Result<(), Error> {
+        for pin in pins {
+            validate_pin(*pin)?;
+        }
+        for pin in pins {
+            self.reconfigure(*pin, |settings| settings.pull = Some(updown))?;
+        }
+        Ok(())
+    }
+
+    /// Edges are merged by direction, eg. `AsyncRising` and then `Falling` detect both. Lines
+    /// without a mode are requested as inputs, other modes are refused.
+    fn enable_edge_detect(&mut self, pin: u8, edge: Edge) -> Result<(), Error> {
+        validate_pin(pin)?;
+        let flags = event_flags(pin, edge)?;
+        match self.lines.get(&pin).and_then(|line| line.settings.mode) {
+            None | Some(Mode::Input) => (),
+            Some(_) => return Err(Error::Unsupported { pin, operation: "edge detect on a pin not in input mode" })
+        }
+        self.reconfigure(pin, |settings| {
+            settings.mode = Some(Mode::Input);
+            settings.events |= flags;
+        })
+    }
+
+    fn disable_edge_detect(&mut self, pin: u8) -> Result<(), Error> {
+        self.reconfigure(pin, |settings| settings.events = 0)
+    }
+
+    fn take_event(&mut self, pin: u8) -> Result<bool, Error> {
+        validate_pin(pin)?;
+        match self.lines.get(&pin) {
+            Some(Line { settings, handle: Some(handle) }) if settings.watches_events() =>
+                Ok(self.chip.take_events(handle)? > 0),
+            Some(Line { settings, .. }) if settings.events != 0 => Ok(false),
+            _ => Err(Error::NotRequested(pin))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Debug, Default)]
+    struct FakeLine {
+        /// `GPIOHANDLE_REQUEST_*` flags of the current request, `None` while released.
+        requested: Option<u32>,
+        /// `GPIOEVENT_REQUEST_*` flags if requested for events.
+        events: Option<u32>,
+        value: u8,
+        pending_events: usize,
+        /// `GPIOLINE_FLAG_*` flags reported while not requested, eg. by another consumer.
+        info_flags: u32,
+    }
+
+    type FakeLines = Rc<RefCell<HashMap<u32, FakeLine>>>;
+
+    /// Stands in for the kernel, which grants one request per line and releases it when the
+    /// handle is closed.
+    #[derive(Default)]
+    struct FakeChip {
+        lines: FakeLines,
+        /// Request flags the kernel refuses, eg. bias on kernels older than 5.5.
+        refused_flags: u32,
+    }
+
+    struct FakeHandle {
+        offset: u32,
+        lines: FakeLines,
+    }
+
+    impl Drop for FakeHandle {
+        fn drop(&mut self) {
+            let mut lines = self.lines.borrow_mut();
+            let line = lines.get_mut(&self.offset).unwrap();
+            line.requested = None;
+            line.events = None;
+        }
+    }
+
+    impl FakeChip {
+        fn request(&mut self, offset: u32, flags: u32, events: Option<u32>) -> io::Result<FakeHandle> {
+            let mut lines = self.lines.borrow_mut();
+            let line = lines.entry(offset).or_default();
+            if line.requested.is_some() {
+                return Err(io::Error::from_raw_os_error(libc::EBUSY));
+            }
+            if flags & self.refused_flags != 0 {
+                return Err(io::Error::from_raw_os_error(libc::EINVAL));
+            }
+            line.requested = Some(flags);
+            line.events = events;
+            Ok(FakeHandle { offset, lines: self.lines.clone() })
+        }
+    }
+
+    impl LineChip for FakeChip {
+        type Handle = FakeHandle;
+
+        fn line_flags(&self, offset: u32) -> io::Result<u32> {
+            let lines = self.lines.borrow();
+            let line = match lines.get(&offset) {
+                Some(line) => line,
+                None => return Ok(0)
+            };
+            Ok(match line.requested {
+                Some(flags) => {
+                    let is_out = if flags & GPIOHANDLE_REQUEST_OUTPUT != 0 { GPIOLINE_FLAG_IS_OUT } else { 0 };
+                    // Request and line bias flags share their bits.
+                    is_out | flags & (GPIOLINE_FLAG_BIAS_PULL_UP | GPIOLINE_FLAG_BIAS_PULL_DOWN)
+                },
+                None => line.info_flags
+            })
+        }
+
+        fn request_line(&mut self, offset: u32, flags: u32, default_value: u8) -> io::Result<FakeHandle> {
+            let handle = self.request(offset, flags, None)?;
+            if flags & GPIOHANDLE_REQUEST_OUTPUT != 0 {
+                self.lines.borrow_mut().get_mut(&offset).unwrap().value = default_value;
+            }
+            Ok(handle)
+        }
+
+        fn request_events(&mut self, offset: u32, handle_flags: u32, event_flags: u32) -> io::Result<FakeHandle> {
+            self.request(offset, handle_flags, Some(event_flags))
+        }
+
+        fn get_value(&self, handle: &FakeHandle) -> io::Result<u8> {
+            Ok(self.lines.borrow()[&handle.offset].value)
+        }
+
+        fn set_value(&self, handle: &FakeHandle, value: u8) -> io::Result<()> {
+            self.lines.borrow_mut().get_mut(&handle.offset).unwrap().value = value;
+            Ok(())
+        }
+
+        /// Like the kernel, refuses to read events from a line handle.
+        fn take_events(&self, handle: &FakeHandle) -> io::Result<usize> {
+            let mut lines = self.lines.borrow_mut();
+            let line = lines.get_mut(&handle.offset).unwrap();
+            if line.events.is_none() {
+                return Err(io::Error::from_raw_os_error(libc::EINVAL));
+            }
+            Ok(std::mem::replace(&mut line.pending_events, 0))
+        }
+    }
+
+    fn fake_gpio(refused_flags: u32) -> (CdevGpio<FakeChip>, FakeLines) {
+        let chip = FakeChip { refused_flags, ..FakeChip::default() };
+        let lines = chip.lines.clone();
+        (CdevGpio::with_chip(chip), lines)
+    }
+
+    fn requested(lines: &FakeLines, offset: u32) -> Option<u32> {
+        lines.borrow().get(&offset).and_then(|line| line.requested)
+    }
+
+    const ALL_BIAS: u32 = GPIOHANDLE_REQUEST_BIAS_PULL_UP | GPIOHANDLE_REQUEST_BIAS_PULL_DOWN | GPIOHANDLE_REQUEST_BIAS_DISABLE;
+
+    #[test]
+    fn requests_outputs_at_their_level() {
+        let (mut gpio, lines) = fake_gpio(0);
+        gpio.set(17).unwrap();
+        assert_eq!(requested(&lines, 17), None);
+        gpio.set_mode(17, Mode::Output).unwrap();
+        assert_eq!(requested(&lines, 17), Some(GPIOHANDLE_REQUEST_OUTPUT));
+        assert_eq!(lines.borrow()[&17].value, 1);
+        gpio.clear(17).unwrap();
+        assert_eq!(lines.borrow()[&17].value, 0);
+    }
+
+    #[test]
+    fn requests_bias() {
+        let (mut gpio, lines) = fake_gpio(0);
+        gpio.set_mode(22, Mode::Input).unwrap();
+        gpio.set_pullupdown(&[22], PullUpDown::Up).unwrap();
+        assert_eq!(requested(&lines, 22), Some(GPIOHANDLE_REQUEST_INPUT | GPIOHANDLE_REQUEST_BIAS_PULL_UP));
+        gpio.set_pullupdown(&[22], PullUpDown::Down).unwrap();
+        assert_eq!(requested(&lines, 22), Some(GPIOHANDLE_REQUEST_INPUT | GPIOHANDLE_REQUEST_BIAS_PULL_DOWN));
+        gpio.set_pullupdown(&[22], PullUpDown::Off).unwrap();
+        assert_eq!(requested(&lines, 22), Some(GPIOHANDLE_REQUEST_INPUT | GPIOHANDLE_REQUEST_BIAS_DISABLE));
+    }
+
+    #[test]
+    fn requests_without_bias_unless_pull_set() {
+        // Kernels before 5.5 refuse every bias flag.
+        let (mut gpio, lines) = fake_gpio(ALL_BIAS);
+        gpio.set_mode(5, Mode::Output).unwrap();
+        gpio.set_mode(6, Mode::Input).unwrap();
+        gpio.enable_edge_detect(13, Edge::Rising).unwrap();
+        assert_eq!(requested(&lines, 5), Some(GPIOHANDLE_REQUEST_OUTPUT));
+        assert_eq!(requested(&lines, 6), Some(GPIOHANDLE_REQUEST_INPUT));
+        assert_eq!(requested(&lines, 13), Some(GPIOHANDLE_REQUEST_INPUT));
+        assert!(gpio.set_pullupdown(&[6], PullUpDown::Off).is_err());
+    }
+
+    #[test]
+    fn reads_requested_lines() {
+        let (mut gpio, lines) = fake_gpio(0);
+        assert!(matches!(gpio.read(27), Err(Error::NotRequested(27))));
+        gpio.set_mode(27, Mode::Input).unwrap();
+        assert_eq!(gpio.read(27).unwrap(), Level::Low);
+        lines.borrow_mut().get_mut(&27).unwrap().value = 1;
+        assert_eq!(gpio.read(27).unwrap(), Level::High);
+    }
+
+    #[test]
+    fn refuses_alternate_functions() {
+        let (mut gpio, lines) = fake_gpio(0);
+        gpio.set_mode(18, Mode::Output).unwrap();
+        assert!(gpio.set_mode(18, Mode::Alt5).is_err());
+        assert_eq!(requested(&lines, 18), Some(GPIOHANDLE_REQUEST_OUTPUT));
+        assert_eq!(lines.borrow()[&18].events, None);
+    }
+
+    #[test]
+    fn takes_events() {
+        let (mut gpio, lines) = fake_gpio(0);
+        assert!(matches!(gpio.take_event(23), Err(Error::NotRequested(23))));
+        gpio.enable_edge_detect(23, Edge::Rising).unwrap();
+        assert_eq!(lines.borrow()[&23].events, Some(GPIOEVENT_REQUEST_RISING_EDGE));
+        assert!(!gpio.take_event(23).unwrap());
+        lines.borrow_mut().get_mut(&23).unwrap().pending_events = 2;
+        assert!(gpio.take_event(23).unwrap());
+        assert!(!gpio.take_event(23).unwrap());
+        assert_eq!(gpio.read(23).unwrap(), Level::Low);
+    }
+
+    #[test]
+    fn merges_edges() {
+        let (mut gpio, lines) = fake_gpio(0);
+        gpio.enable_edge_detect(25, Edge::Rising).unwrap();
+        gpio.enable_edge_detect(25, Edge::Falling).unwrap();
+        assert_eq!(lines.borrow()[&25].events, Some(GPIOEVENT_REQUEST_BOTH_EDGES));
+
+        gpio.disable_edge_detect(25).unwrap();
+        assert_eq!(lines.borrow()[&25].events, None);
+        assert!(matches!(gpio.take_event(25), Err(Error::NotRequested(25))));
+    }
+
+    #[test]
+    fn refuses_edges_on_outputs() {
+        let (mut gpio, lines) = fake_gpio(0);
+        gpio.set_mode(16, Mode::Output).unwrap();
+        assert!(matches!(gpio.enable_edge_detect(16, Edge::Falling), Err(Error::Unsupported { pin: 16, .. })));
+        assert_eq!(requested(&lines, 16), Some(GPIOHANDLE_REQUEST_OUTPUT));
+        assert_eq!(lines.borrow()[&16].events, None);
+    }
+
+    #[test]
+    fn watches_no_events_while_output() {
+        let (mut gpio, lines) = fake_gpio(0);
+        gpio.enable_edge_detect(26, Edge::Rising).unwrap();
+        // Driving the pin, eg. to discharge a capacitor, requests a line handle without events.
+        gpio.set_mode(26, Mode::Output).unwrap();
+        assert_eq!(lines.borrow()[&26].events, None);
+        assert!(!gpio.take_event(26).unwrap());
+
+        gpio.set_mode(26, Mode::Input).unwrap();
+        assert_eq!(lines.borrow()[&26].events, Some(GPIOEVENT_REQUEST_RISING_EDGE));
+        lines.borrow_mut().get_mut(&26).unwrap().pending_events = 1;
+        assert!(gpio.take_event(26).unwrap());
+    }
+
+    #[test]
+    fn merges_edges_by_direction() {
+        let (mut gpio, lines) = fake_gpio(0);
+        gpio.enable_edge_detect(25, Edge::AsyncRising).unwrap();
+        gpio.enable_edge_detect(25, Edge::AsyncFalling).unwrap();
+        assert_eq!(lines.borrow()[&25].events, Some(GPIOEVENT_REQUEST_BOTH_EDGES));
+
+        gpio.disable_edge_detect(25).unwrap();
+        assert_eq!(lines.borrow()[&25].events, None);
+        gpio.enable_edge_detect(25, Edge::Rising).unwrap();
+        gpio.enable_edge_detect(25, Edge::AsyncRising).unwrap();
+        assert_eq!(lines.borrow()[&25].events, Some(GPIOEVENT_REQUEST_RISING_EDGE));
+    }
+
+    #[test]
+    fn refuses_level_detect() {
+        let (mut gpio, lines) = fake_gpio(0);
+        assert!(matches!(gpio.enable_edge_detect(27, Edge::High), Err(Error::Unsupported { pin: 27, .. })));
+        assert!(lines.borrow().get(&27).is_none());
+    }
+
+    #[test]
+    fn reads_back_mode_and_pull() {
+        let (mut gpio, lines) = fake_gpio(0);
+        lines.borrow_mut().entry(5).or_default().info_flags = GPIOLINE_FLAG_IS_OUT | GPIOLINE_FLAG_BIAS_PULL_UP;
+        assert_eq!(gpio.get_mode(5).unwrap(), Mode::Output);
+        assert_eq!(gpio.get_pullupdown(5).unwrap(), PullUpDown::Up);
+
+        gpio.set_mode(6, Mode::Input).unwrap();
+        assert_eq!(gpio.get_mode(6).unwrap(), Mode::Input);
+        assert_eq!(gpio.get_pullupdown(6).unwrap(), PullUpDown::Off);
+        gpio.set_pullupdown(&[6], PullUpDown::Down).unwrap();
+        assert_eq!(gpio.get_pullupdown(6).unwrap(), PullUpDown::Down);
+        gpio.set_mode(6, Mode::Output).unwrap();
+        assert_eq!(gpio.get_mode(6).unwrap(), Mode::Output);
+    }
+
+    #[test]
+    fn refused_request_takes_back_previous() {
+        let (mut gpio, lines) = fake_gpio(GPIOHANDLE_REQUEST_BIAS_PULL_UP);
+        gpio.set_mode(24, Mode::Input).unwrap();
+        assert!(gpio.set_pullupdown(&[24], PullUpDown::Up).is_err());
+        assert_eq!(requested(&lines, 24), Some(GPIOHANDLE_REQUEST_INPUT));
+        assert_eq!(gpio.read(24).unwrap(), Level::Low);
+    }
+}
@@ -0,0 +1,270 @@
+///
+/// Registry of which owner, eg. a sensor id, uses which pin.
+///
+/// Pins are claimed exclusively unless every owner claims them as shared, which is meant for
+/// power pins feeding several sensors. A claim is released when its `ClaimedPin` is dropped.
+/// `ClaimedGpio` enforces the claims, it refuses to change a pin no one has claimed.
+///
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use super::{validate_pin, Edge, Error, GpioBackend, Level, Mode, PullUpDown, PIN_COUNT};
+use super::restore::Restore;
+
+#[derive(Debug)]
+struct Claim {
+    owners: Vec<String>,
+    shared: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PinClaims {
+    claims: Arc<Mutex<HashMap<u8, Claim>>>,
+}
+
+impl PinClaims {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Claims `pin` for `owner` alone.
+    pub fn claim(&self, pin: u8, owner: &str) -> Result<ClaimedPin, Error> {
+        self.claim_pin(pin, owner, false)
+    }
+
+    /// Claims `pin` for `owner` together with other owners claiming it as shared.
+    pub fn claim_shared(&self, pin: u8, owner: &str) -> Result<ClaimedPin, Error> {
+        self.claim_pin(pin, owner, true)
+    }
+
+    /// Current owners of `pin`, in claim order.
+    pub fn owners(&self, pin: u8) -> Vec<String> {
+        self.claims
+            .lock()
+            .unwrap()
+            .get(&pin)
+            .map(|claim| claim.owners.clone())
+            .unwrap_or_default()
+    }
+
+    fn claim_pin(&self, pin: u8, owner: &str, shared: bool) -> Result<ClaimedPin, Error> {
+        validate_pin(pin)?;
+        let mut claims = self.claims.lock().unwrap();
+        match claims.get_mut(&pin) {
+            Some(ref mut claim) if claim.shared && shared => claim.owners.push(owner.to_string()),
+            Some(claim) => return Err(Error::PinClaimed {
+                pin,
+                owner: claim.owners[0].clone(),
+                claimant: owner.to_string()
+            }),
+            None => {
+                claims.insert(pin, Claim { owners: vec![owner.to_string()], shared });
+            }
+        }
+        Ok(ClaimedPin { pin, owner: owner.to_string(), shared, claims: self.clone() })
+    }
+
+    fn release(&self, pin: u8, owner: &str) {
+        let mut claims = self.claims.lock().unwrap();
+        let released = match claims.get_mut(&pin) {
+            Some(claim) => {
+                if let Some(i) = claim.owners.iter().position(|o| o == owner) {
+                    claim.owners.remove(i);
+                }
+                claim.owners.is_empty()
+            },
+            None => false
+        };
+        if released {
+            claims.remove(&pin);
+        }
+    }
+}
+
+/// Handle to a claimed pin, proving its owner may use it.
+#[derive(Debug)]
+pub struct ClaimedPin {
+    pin: u8,
+    owner: String,
+    shared: bool,
+    claims: PinClaims,
+}
+
+impl ClaimedPin {
+    pub fn pin(&self) -> u8 {
+        self.pin
+    }
+
+    pub fn owner(&self) -> &str {
+        &self.owner
+    }
+
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
+
+    /// True if no one else holds a claim on the pin, ie. its state may be reset.
+    pub fn is_sole_owner(&self) -> bool {
+        self.claims.owners(self.pin).len() <= 1
+    }
+}
+
+impl Drop for ClaimedPin {
+    fn drop(&mut self) {
+        self.claims.release(self.pin, &self.owner);
+    }
+}
+
+/// Backend refusing to change pins without a live claim in its registry, so a sensor can only
+/// drive the pins it holds a `ClaimedPin` for. Reading is allowed on any pin.
+pub struct ClaimedGpio<G: GpioBackend> {
+    gpio: G,
+    claims: PinClaims,
+}
+
+impl<G: GpioBackend> ClaimedGpio<G> {
+    pub fn new(gpio: G) -> Self {
+        ClaimedGpio { gpio, claims: PinClaims::new() }
+    }
+
+    /// Registry pins must be claimed from before they can be changed.
+    pub fn claims(&self) -> &PinClaims {
+        &self.claims
+    }
+
+    /// The wrapped backend. Changes made through it are not checked.
+    pub fn inner(&mut self) -> &mut G {
+        &mut self.gpio
+    }
+
+    fn check(&self, pin: u8) -> Result<(), Error> {
+        match self.claims.owners(pin).is_empty() {
+            true => Err(Error::NotClaimed(pin)),
+            false => Ok(())
+        }
+    }
+
+    fn check_mask(&self, mask: u64) -> Result<(), Error> {
+        for pin in (0..PIN_COUNT).filter(|pin| mask & (1 << pin) != 0) {
+            self.check(pin)?;
+        }
+        Ok(())
+    }
+}
+
+impl<G: GpioBackend + Restore> Restore for ClaimedGpio<G> {
+    fn restore(&mut self) -> Result<(), Error> {
+        self.gpio.restore()
+    }
+}
+
+impl<G: GpioBackend> GpioBackend for ClaimedGpio<G> {
+    fn set_mode(&mut self, pin: u8, mode: Mode) -> Result<(), Error> {
+        self.check(pin)?;
+        self.gpio.set_mode(pin, mode)
+    }
+
+    fn read(&self, pin: u8) -> Result<Level, Error> {
+        self.gpio.read(pin)
+    }
+
+    fn set(&mut self, pin: u8) -> Result<(), Error> {
+        self.check(pin)?;
+        self.gpio.set(pin)
+    }
+
+    fn clear(&mut self, pin: u8) -> Result<(), Error> {
+        self.check(pin)?;
+        self.gpio.clear(pin)
+    }
+
+    fn set_pullupdown(&mut self, pins: &[u8], updown: PullUpDown) -> Result<(), Error> {
+        for pin in pins {
+            self.check(*pin)?;
+        }
+        self.gpio.set_pullupdown(pins, updown)
+    }
+
+    fn set_mask(&mut self, mask: u64) -> Result<(), Error> {
+        self.check_mask(mask)?;
+        self.gpio.set_mask(mask)
+    }
+
+    fn clear_mask(&mut self, mask: u64) -> Result<(), Error> {
+        self.check_mask(mask)?;
+        self.gpio.clear_mask(mask)
+    }
+
+    fn read_mask(&self, mask: u64) -> Result<u64, Error> {
+        self.gpio.read_mask(mask)
+    }
+
+    fn get_mode(&self, pin: u8) -> Result<Mode, Error> {
+        self.gpio.get_mode(pin)
+    }
+
+    fn get_pullupdown(&self, pin: u8) -> Result<PullUpDown, Error> {
+        self.gpio.get_pullupdown(pin)
+    }
+
+    fn enable_edge_detect(&mut self, pin: u8, edge: Edge) -> Result<(), Error> {
+        self.check(pin)?;
+        self.gpio.enable_edge_detect(pin, edge)
+    }
+
+    fn disable_edge_detect(&mut self, pin: u8) -> Result<(), Error> {
+        self.check(pin)?;
+        self.gpio.disable_edge_detect(pin)
+    }
+
+    fn take_event(&mut self, pin: u8) -> Result<bool, Error> {
+        self.check(pin)?;
+        self.gpio.take_event(pin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::simulated::SimulatedGpio;
+
+    #[test]
+    fn exclusive_claims_name_the_owner() {
+        let claims = PinClaims::new();
+        let _pin = claims.claim(17, "moist1").unwrap();
+        match claims.claim_shared(17, "moist2") {
+            Err(Error::PinClaimed { pin: 17, owner, claimant }) => {
+                assert_eq!(owner, "moist1");
+                assert_eq!(claimant, "moist2");
+            },
+            other => panic!("expected a claimed pin error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn shared_claims_release_with_the_last_owner() {
+        let claims = PinClaims::new();
+        let first = claims.claim_shared(17, "moist1").unwrap();
+        let second = claims.claim_shared(17, "moist2").unwrap();
+        assert!(!first.is_sole_owner());
+        drop(first);
+        assert!(second.is_sole_owner());
+        assert_eq!(claims.owners(17), vec!["moist2".to_string()]);
+        drop(second);
+        assert!(claims.owners(17).is_empty());
+        claims.claim(17, "pulse1").unwrap();
+    }
+
+    #[test]
+    fn refuses_unclaimed_pins() {
+        let mut gpio = ClaimedGpio::new(SimulatedGpio::new());
+        let pin = gpio.claims().claim(17, "moist1").unwrap();
+        gpio.set_mode(17, Mode::Output).unwrap();
+        gpio.set(17).unwrap();
+        assert!(matches!(gpio.set(27), Err(Error::NotClaimed(27))));
+        assert!(matches!(gpio.set_pullupdown(&[17, 27], PullUpDown::Up), Err(Error::NotClaimed(27))));
+        assert!(matches!(gpio.set_mask(1 << 17 | 1 << 27), Err(Error::NotClaimed(27))));
+        assert_eq!(gpio.read(27).unwrap(), Level::Low);
+        drop(pin);
+        assert!(matches!(gpio.clear(17), Err(Error::NotClaimed(17))));
+    }
+}
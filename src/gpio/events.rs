@@ -0,0 +1,116 @@
+///
+/// Edge detection events as a futures `Stream`.
+///
+/// Events are latched by the backend (see `GpioBackend::enable_edge_detect`) and picked up at
+/// a fixed poll interval, so short pulses are not lost between polls. Several events on the
+/// same pin within one interval are reported as one.
+///
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use futures::{Async, Poll};
+use futures::stream::Stream;
+use tokio_timer::Interval;
+use super::{Edge, Error, GpioBackend, Level};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EdgeEvent {
+    pub pin: u8,
+    /// Level of the pin when the event was picked up.
+    pub level: Level,
+    pub timestamp: SystemTime,
+}
+
+pub struct EdgeEvents<G: GpioBackend> {
+    gpio: Arc<Mutex<G>>,
+    pins: Vec<u8>,
+    timer: Interval,
+    pending: VecDeque<EdgeEvent>,
+}
+
+impl<G: GpioBackend> EdgeEvents<G> {
+    /// Enables `edge` detection on `pins` and checks them for events every `poll_interval`.
+    pub fn new(gpio: Arc<Mutex<G>>, pins: &[u8], edge: Edge, poll_interval: Duration) -> Result<Self, Error> {
+        {
+            let mut gp = gpio.lock().unwrap();
+            for pin in pins {
+                gp.enable_edge_detect(*pin, edge)?;
+                // Discard anything latched before we started listening.
+                gp.take_event(*pin)?;
+            }
+        }
+        let timer = Interval::new(Instant::now() + poll_interval, poll_interval);
+        Ok(EdgeEvents { gpio, pins: pins.to_vec(), timer, pending: VecDeque::new() })
+    }
+
+    fn collect_events(&mut self) -> Result<(), Error> {
+        let mut gp = self.gpio.lock().unwrap();
+        let timestamp = SystemTime::now();
+        for pin in &self.pins {
+            if gp.take_event(*pin)? {
+                let level = gp.read(*pin)?;
+                self.pending.push_back(EdgeEvent { pin: *pin, level, timestamp });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<G: GpioBackend> Drop for EdgeEvents<G> {
+    fn drop(&mut self) {
+        if let Ok(mut gp) = self.gpio.lock() {
+            for pin in &self.pins {
+                let _ = gp.disable_edge_detect(*pin);
+            }
+        }
+    }
+}
+
+impl<G: GpioBackend> Stream for EdgeEvents<G> {
+    type Item = EdgeEvent;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(Async::Ready(Some(event)));
+            }
+            match self.timer.poll() {
+                Ok(Async::Ready(Some(_))) => self.collect_events()?,
+                Ok(Async::Ready(None)) => return Ok(Async::Ready(None)),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(err) => {
+                    println!("Error on timer in edge events {}", err);
+                    return Ok(Async::Ready(None));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::simulated::{Call, SimulatedGpio};
+
+    #[test]
+    fn streams_latched_edges() {
+        let mut sim = SimulatedGpio::new();
+        sim.script(17, &[(0, Level::Low), (40, Level::High)], None).unwrap();
+        let gpio = Arc::new(Mutex::new(sim));
+
+        let events = EdgeEvents::new(gpio.clone(), &[17, 18], Edge::Rising, Duration::from_millis(10)).unwrap();
+        let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+        let (event, events) = runtime.block_on(events.into_future()).map_err(|(err, _)| err).unwrap();
+        let event = event.unwrap();
+        assert_eq!((event.pin, event.level), (17, Level::High));
+        drop(events);
+
+        assert_eq!(gpio.lock().unwrap().take_calls(), vec![
+            Call::EnableEdgeDetect(17, Edge::Rising),
+            Call::EnableEdgeDetect(18, Edge::Rising),
+            Call::DisableEdgeDetect(17),
+            Call::DisableEdgeDetect(18),
+        ]);
+    }
+}
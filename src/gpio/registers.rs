@@ -1,4 +1,8 @@
 pub const GPIO_MEM_SIZE: usize = 168;
+/// The BCM2711 pull-up/down control registers follow the legacy block, see
+/// https://datasheets.raspberrypi.com/bcm2711/bcm2711-peripherals.pdf
+pub const BCM2711_PUP_PDN_OFFSET: usize = 0xe4;
+pub const BCM2711_GPIO_MEM_SIZE: usize = BCM2711_PUP_PDN_OFFSET + 16;
 
 use register::{mmio::ReadOnly, mmio::ReadWrite, Field, FieldValue, register_bitfields};
 
@@ -214,13 +218,21 @@ register_bitfields! {
         LEV1 OFFSET(1) NUMBITS(1),
         LEV0 OFFSET(0) NUMBITS(1)
     ],
-    GPEDS [ RESERVED 0 ],
-    GPREN [ RESERVED 0 ],
-    GPFEN [ RESERVED 0 ],
-    GPHEN [ RESERVED 0 ],
-    GPLEN [ RESERVED 0 ],
-    GPAREN [ RESERVED 0 ],
-    GPAFEN [ RESERVED 0 ],
+    // The event detect registers hold one bit per pin, see `pin_bit`.
+    /// GPIO Event Detect Status, write 1 to clear
+    GPEDS [ EDS OFFSET(0) NUMBITS(32) [] ],
+    /// GPIO Rising Edge Detect Enable
+    GPREN [ REN OFFSET(0) NUMBITS(32) [] ],
+    /// GPIO Falling Edge Detect Enable
+    GPFEN [ FEN OFFSET(0) NUMBITS(32) [] ],
+    /// GPIO High Detect Enable
+    GPHEN [ HEN OFFSET(0) NUMBITS(32) [] ],
+    /// GPIO Low Detect Enable
+    GPLEN [ LEN OFFSET(0) NUMBITS(32) [] ],
+    /// GPIO Asynchronous rising Edge Detect Enable
+    GPAREN [ AREN OFFSET(0) NUMBITS(32) [] ],
+    /// GPIO Asynchronous falling Edge Detect Enable
+    GPAFEN [ AFEN OFFSET(0) NUMBITS(32) [] ],
     GPPUD [
         PUD OFFSET(0) NUMBITS(2) [
             OFF = 0b00,
@@ -262,7 +274,90 @@ register_bitfields! {
         PUDCLK1 OFFSET(1) NUMBITS(1),
         PUDCLK0 OFFSET(0) NUMBITS(1)
     ],
-    TEST [ TEST 0 ]
+    TEST [ TEST 0 ],
+    /// BCM2711 GPIO Pull-up / Pull-down control, two bits per pin
+    GPIO_PUP_PDN_CNTRL [
+        PULL15 OFFSET(30) NUMBITS(2) [
+            NONE = 0b00,
+            UP = 0b01,
+            DOWN = 0b10
+        ],
+        PULL14 OFFSET(28) NUMBITS(2) [
+            NONE = 0b00,
+            UP = 0b01,
+            DOWN = 0b10
+        ],
+        PULL13 OFFSET(26) NUMBITS(2) [
+            NONE = 0b00,
+            UP = 0b01,
+            DOWN = 0b10
+        ],
+        PULL12 OFFSET(24) NUMBITS(2) [
+            NONE = 0b00,
+            UP = 0b01,
+            DOWN = 0b10
+        ],
+        PULL11 OFFSET(22) NUMBITS(2) [
+            NONE = 0b00,
+            UP = 0b01,
+            DOWN = 0b10
+        ],
+        PULL10 OFFSET(20) NUMBITS(2) [
+            NONE = 0b00,
+            UP = 0b01,
+            DOWN = 0b10
+        ],
+        PULL9 OFFSET(18) NUMBITS(2) [
+            NONE = 0b00,
+            UP = 0b01,
+            DOWN = 0b10
+        ],
+        PULL8 OFFSET(16) NUMBITS(2) [
+            NONE = 0b00,
+            UP = 0b01,
+            DOWN = 0b10
+        ],
+        PULL7 OFFSET(14) NUMBITS(2) [
+            NONE = 0b00,
+            UP = 0b01,
+            DOWN = 0b10
+        ],
+        PULL6 OFFSET(12) NUMBITS(2) [
+            NONE = 0b00,
+            UP = 0b01,
+            DOWN = 0b10
+        ],
+        PULL5 OFFSET(10) NUMBITS(2) [
+            NONE = 0b00,
+            UP = 0b01,
+            DOWN = 0b10
+        ],
+        PULL4 OFFSET(8) NUMBITS(2) [
+            NONE = 0b00,
+            UP = 0b01,
+            DOWN = 0b10
+        ],
+        PULL3 OFFSET(6) NUMBITS(2) [
+            NONE = 0b00,
+            UP = 0b01,
+            DOWN = 0b10
+        ],
+        PULL2 OFFSET(4) NUMBITS(2) [
+            NONE = 0b00,
+            UP = 0b01,
+            DOWN = 0b10
+        ],
+        PULL1 OFFSET(2) NUMBITS(2) [
+            NONE = 0b00,
+            UP = 0b01,
+            DOWN = 0b10
+        ],
+        PULL0 OFFSET(0) NUMBITS(2) [
+            NONE = 0b00,
+            UP = 0b01,
+            DOWN = 0b10
+        ]
+    ]
 }
 
 #[allow(non_snake_case)]
@@ -312,6 +407,42 @@ pub struct GpioRegisters {
     pub TEST: ReadOnly<u32, TEST::Register>, // 0x08
 }
 
+/// Bit of `pin` in the registers with one bit per pin, ie. GPEDS0/1.
+pub fn pin_bit(pin: u8) -> u32 {
+    1 << (pin % 32)
+}
+
+#[allow(non_snake_case)]
+#[repr(C)]
+pub struct Bcm2711PullRegisters {
+    pub GPIO_PUP_PDN_CNTRL_REG0: ReadWrite<u32, GPIO_PUP_PDN_CNTRL::Register>, // 0xe4
+    pub GPIO_PUP_PDN_CNTRL_REG1: ReadWrite<u32, GPIO_PUP_PDN_CNTRL::Register>, // 0xe8
+    pub GPIO_PUP_PDN_CNTRL_REG2: ReadWrite<u32, GPIO_PUP_PDN_CNTRL::Register>, // 0xec
+    pub GPIO_PUP_PDN_CNTRL_REG3: ReadWrite<u32, GPIO_PUP_PDN_CNTRL::Register>, // 0xf0
+}
+
+pub fn pin_pup_pdn_field(pin: u8) -> Field<u32, GPIO_PUP_PDN_CNTRL::Register> {
+    match pin % 16 {
+        0 => GPIO_PUP_PDN_CNTRL::PULL0,
+        1 => GPIO_PUP_PDN_CNTRL::PULL1,
+        2 => GPIO_PUP_PDN_CNTRL::PULL2,
+        3 => GPIO_PUP_PDN_CNTRL::PULL3,
+        4 => GPIO_PUP_PDN_CNTRL::PULL4,
+        5 => GPIO_PUP_PDN_CNTRL::PULL5,
+        6 => GPIO_PUP_PDN_CNTRL::PULL6,
+        7 => GPIO_PUP_PDN_CNTRL::PULL7,
+        8 => GPIO_PUP_PDN_CNTRL::PULL8,
+        9 => GPIO_PUP_PDN_CNTRL::PULL9,
+        10 => GPIO_PUP_PDN_CNTRL::PULL10,
+        11 => GPIO_PUP_PDN_CNTRL::PULL11,
+        12 => GPIO_PUP_PDN_CNTRL::PULL12,
+        13 => GPIO_PUP_PDN_CNTRL::PULL13,
+        14 => GPIO_PUP_PDN_CNTRL::PULL14,
+        15 => GPIO_PUP_PDN_CNTRL::PULL15,
+        v => panic!("u8 % 16 = {}", v)
+    }
+}
+
 pub fn pin_fsel_field(pin: u8) -> Field<u32, GPFSEL::Register> {
     match pin % 10 {
         0 => GPFSEL::FSEL0,
@@ -0,0 +1,307 @@
+///
+/// Restoring of pins to the state they had before this process touched them.
+///
+/// `RestoringGpio` wraps any backend and remembers the mode, output level and, where
+/// readable, pull of each pin the first time it is changed. `restore` puts them back, which
+/// happens on graceful shutdown, when the wrapper is dropped and, once `restore_on_panic` has
+/// been called, when a thread panics. Pulls can not be read back on BCM2835 and are left as is.
+///
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, TryLockError};
+use super::{Edge, Error, GpioBackend, Level, Mode, PullUpDown, PIN_COUNT};
+
+/// Backend able to put the pins it changed back to their original state.
+pub trait Restore {
+    /// Puts every touched pin back to its original state. All pins are attempted, the first
+    /// error is returned.
+    fn restore(&mut self) -> Result<(), Error>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Original {
+    mode: Option<Mode>,
+    level: Option<Level>,
+    pull: Option<PullUpDown>,
+    edge_detect: bool,
+}
+
+pub struct RestoringGpio<G: GpioBackend> {
+    gpio: G,
+    originals: BTreeMap<u8, Original>,
+}
+
+impl<G: GpioBackend> RestoringGpio<G> {
+    pub fn new(gpio: G) -> Self {
+        RestoringGpio { gpio, originals: BTreeMap::new() }
+    }
+
+    /// The wrapped backend. Changes made through it are not remembered.
+    pub fn inner(&mut self) -> &mut G {
+        &mut self.gpio
+    }
+
+    /// Pins changed since the last restore.
+    pub fn touched_pins(&self) -> Vec<u8> {
+        self.originals.keys().cloned().collect()
+    }
+
+    fn restore_pin(&mut self, pin: u8, original: &Original) -> Result<(), Error> {
+        if original.edge_detect {
+            self.gpio.disable_edge_detect(pin)?;
+        }
+        // Latch the original output level before switching back to output, so the pin is
+        // never driven to the wrong level.
+        match original.level {
+            Some(Level::High) => self.gpio.set(pin)?,
+            Some(Level::Low) => self.gpio.clear(pin)?,
+            None => ()
+        }
+        if let Some(mode) = original.mode {
+            self.gpio.set_mode(pin, mode)?;
+        }
+        if let Some(pull) = original.pull {
+            self.gpio.set_pullupdown(&[pin], pull)?;
+        }
+        Ok(())
+    }
+
+    fn remember(&mut self, pin: u8) {
+        if pin >= PIN_COUNT || self.originals.contains_key(&pin) {
+            return;
+        }
+        let mode = self.gpio.get_mode(pin).ok();
+        let level = match mode {
+            Some(Mode::Output) => self.gpio.read(pin).ok(),
+            _ => None
+        };
+        let pull = self.gpio.get_pullupdown(pin).ok();
+        self.originals.insert(pin, Original { mode, level, pull, edge_detect: false });
+    }
+
+    fn remember_mask(&mut self, mask: u64) {
+        for pin in (0..PIN_COUNT).filter(|pin| mask & (1 << pin) != 0) {
+            self.remember(pin);
+        }
+    }
+}
+
+impl<G: GpioBackend> Restore for RestoringGpio<G> {
+    fn restore(&mut self) -> Result<(), Error> {
+        let originals = std::mem::take(&mut self.originals);
+        let mut result = Ok(());
+        for (pin, original) in originals {
+            let restored = self.restore_pin(pin, &original);
+            if result.is_ok() {
+                result = restored;
+            }
+        }
+        result
+    }
+}
+
+impl<G: GpioBackend> Drop for RestoringGpio<G> {
+    fn drop(&mut self) {
+        if let Err(err) = self.restore() {
+            println!("Error restoring gpio pins: {}", err);
+        }
+    }
+}
+
+impl<G: GpioBackend> GpioBackend for RestoringGpio<G> {
+    fn set_mode(&mut self, pin: u8, mode: Mode) -> Result<(), Error> {
+        self.remember(pin);
+        self.gpio.set_mode(pin, mode)
+    }
+
+    fn read(&self, pin: u8) -> Result<Level, Error> {
+        self.gpio.read(pin)
+    }
+
+    fn set(&mut self, pin: u8) -> Result<(), Error> {
+        self.remember(pin);
+        self.gpio.set(pin)
+    }
+
+    fn clear(&mut self, pin: u8) -> Result<(), Error> {
+        self.remember(pin);
+        self.gpio.clear(pin)
+    }
+
+    fn set_pullupdown(&mut self, pins: &[u8], updown: PullUpDown) -> Result<(), Error> {
+        for pin in pins {
+            self.remember(*pin);
+        }
+        self.gpio.set_pullupdown(pins, updown)
+    }
+
+    fn set_mask(&mut self, mask: u64) -> Result<(), Error> {
+        self.remember_mask(mask);
+        self.gpio.set_mask(mask)
+    }
+
+    fn clear_mask(&mut self, mask: u64) -> Result<(), Error> {
+        self.remember_mask(mask);
+        self.gpio.clear_mask(mask)
+    }
+
+    fn read_mask(&self, mask: u64) -> Result<u64, Error> {
+        self.gpio.read_mask(mask)
+    }
+
+    fn get_mode(&self, pin: u8) -> Result<Mode, Error> {
+        self.gpio.get_mode(pin)
+    }
+
+    fn get_pullupdown(&self, pin: u8) -> Result<PullUpDown, Error> {
+        self.gpio.get_pullupdown(pin)
+    }
+
+    fn enable_edge_detect(&mut self, pin: u8, edge: Edge) -> Result<(), Error> {
+        self.remember(pin);
+        if let Some(original) = self.originals.get_mut(&pin) {
+            original.edge_detect = true;
+        }
+        self.gpio.enable_edge_detect(pin, edge)
+    }
+
+    fn disable_edge_detect(&mut self, pin: u8) -> Result<(), Error> {
+        self.gpio.disable_edge_detect(pin)
+    }
+
+    fn take_event(&mut self, pin: u8) -> Result<bool, Error> {
+        self.gpio.take_event(pin)
+    }
+}
+
+/// Restores `gpio` when any thread panics, before the panic is unwound. The hook can not wait
+/// for the lock, so if it is held, eg. by the panicking thread itself, nothing is restored
+/// here. The pins are then restored by the next `restore`, or when the last clone of `gpio`
+/// is dropped.
+pub fn restore_on_panic<R: Restore + Send + 'static>(gpio: &Arc<Mutex<R>>) {
+    let gpio = Arc::downgrade(gpio);
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        previous(info);
+        let gpio = match gpio.upgrade() {
+            Some(gpio) => gpio,
+            None => return
+        };
+        let restored = match gpio.try_lock() {
+            Ok(mut gp) => gp.restore(),
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner().restore(),
+            Err(TryLockError::WouldBlock) => return
+        };
+        if let Err(err) = restored {
+            println!("Error restoring gpio pins after panic: {}", err);
+        }
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::simulated::{Call, SimulatedGpio};
+
+    /// Delegates to a simulation that stays readable after the wrapper is dropped.
+    struct Shared(Arc<Mutex<SimulatedGpio>>);
+
+    impl GpioBackend for Shared {
+        fn set_mode(&mut self, pin: u8, mode: Mode) -> Result<(), Error> {
+            self.0.lock().unwrap().set_mode(pin, mode)
+        }
+
+        fn read(&self, pin: u8) -> Result<Level, Error> {
+            self.0.lock().unwrap().read(pin)
+        }
+
+        fn set(&mut self, pin: u8) -> Result<(), Error> {
+            self.0.lock().unwrap().set(pin)
+        }
+
+        fn clear(&mut self, pin: u8) -> Result<(), Error> {
+            self.0.lock().unwrap().clear(pin)
+        }
+
+        fn set_pullupdown(&mut self, pins: &[u8], updown: PullUpDown) -> Result<(), Error> {
+            self.0.lock().unwrap().set_pullupdown(pins, updown)
+        }
+
+        fn get_mode(&self, pin: u8) -> Result<Mode, Error> {
+            self.0.lock().unwrap().get_mode(pin)
+        }
+
+        fn get_pullupdown(&self, pin: u8) -> Result<PullUpDown, Error> {
+            self.0.lock().unwrap().get_pullupdown(pin)
+        }
+
+        fn enable_edge_detect(&mut self, pin: u8, edge: Edge) -> Result<(), Error> {
+            self.0.lock().unwrap().enable_edge_detect(pin, edge)
+        }
+
+        fn disable_edge_detect(&mut self, pin: u8) -> Result<(), Error> {
+            self.0.lock().unwrap().disable_edge_detect(pin)
+        }
+    }
+
+    /// Pin 17 starts as a high output, 27 as an input pulled up.
+    fn original_state(gpio: &mut SimulatedGpio) {
+        gpio.set(17).unwrap();
+        gpio.set_mode(17, Mode::Output).unwrap();
+        gpio.set_pullupdown(&[27], PullUpDown::Up).unwrap();
+        gpio.take_calls();
+    }
+
+    fn change_state<G: GpioBackend>(gpio: &mut RestoringGpio<G>) {
+        gpio.set_mode(17, Mode::Input).unwrap();
+        gpio.clear(17).unwrap();
+        gpio.set_mode(27, Mode::Output).unwrap();
+        gpio.set_pullupdown(&[27], PullUpDown::Down).unwrap();
+        gpio.enable_edge_detect(22, Edge::Rising).unwrap();
+    }
+
+    fn assert_original_state(gpio: &SimulatedGpio) {
+        assert_eq!(gpio.get_mode(17).unwrap(), Mode::Output);
+        assert_eq!(gpio.read(17).unwrap(), Level::High);
+        assert_eq!(gpio.get_mode(27).unwrap(), Mode::Input);
+        assert_eq!(gpio.get_pullupdown(27).unwrap(), PullUpDown::Up);
+        assert_eq!(gpio.read(27).unwrap(), Level::High);
+    }
+
+    #[test]
+    fn restores_touched_pins() {
+        let mut gpio = RestoringGpio::new(SimulatedGpio::new());
+        original_state(gpio.inner());
+        change_state(&mut gpio);
+        assert_eq!(gpio.touched_pins(), vec![17, 22, 27]);
+        gpio.inner().take_calls();
+
+        gpio.restore().unwrap();
+        assert_original_state(gpio.inner());
+        let calls = gpio.inner().take_calls();
+        // The level is latched before the pin is driven again.
+        assert_eq!(calls[..2], [Call::Set(17), Call::SetMode(17, Mode::Output)]);
+        assert!(calls.contains(&Call::DisableEdgeDetect(22)));
+        assert!(gpio.touched_pins().is_empty());
+
+        gpio.restore().unwrap();
+        assert!(gpio.inner().take_calls().is_empty());
+    }
+
+    #[test]
+    fn restores_when_dropped() {
+        let sim = Arc::new(Mutex::new(SimulatedGpio::new()));
+        original_state(&mut sim.lock().unwrap());
+        let mut gpio = RestoringGpio::new(Shared(sim.clone()));
+        change_state(&mut gpio);
+        drop(gpio);
+        assert_original_state(&sim.lock().unwrap());
+    }
+
+    #[test]
+    fn does_not_remember_changes_through_inner() {
+        let mut gpio = RestoringGpio::new(SimulatedGpio::new());
+        gpio.inner().set_mode(17, Mode::Output).unwrap();
+        gpio.read(17).unwrap();
+        assert!(gpio.touched_pins().is_empty());
+    }
+}
@@ -0,0 +1,445 @@
+///
+/// Simulated GPIO backend driven by a scenario file.
+///
+/// A scenario describes the input level of each pin over time, eg.
+///
+/// ```toml
+/// [pins.27]
+/// repeat = 20000
+/// levels = [
+///     { at = 0, level = 'high' },
+///     { at = 10000, level = 'low' },
+/// ]
+/// ```
+///
+/// `at` and `repeat` are milliseconds since the simulation was started. Pins without a
+/// scenario read their pull resistor level, or low if no pull is configured. Pins configured
+/// as outputs read back the level last driven on them.
+///
+/// An input can also be wired to an output, eg. `[pins.9] connect = 10` for a SPI loopback
+/// from MOSI to MISO. It reads the output level while the other pin is an output.
+///
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use failure::Error as FailureError;
+use toml::Value;
+use super::{mask_pins, validate_mask, validate_pin, Edge, Error, GpioBackend, Level, Mode, PullUpDown};
+
+#[derive(Debug)]
+pub struct ScenarioError {
+    key: String,
+    cause: String
+}
+
+impl std::fmt::Display for ScenarioError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "Simulation scenario error at [{}]: {}", self.key, self.cause)
+    }
+}
+
+impl std::error::Error for ScenarioError {
+}
+
+/// A call made on the simulated GPIO, recorded in the order it was made.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Call {
+    SetMode(u8, Mode),
+    Set(u8),
+    Clear(u8),
+    SetMask(u64),
+    ClearMask(u64),
+    SetPullUpDown(Vec<u8>, PullUpDown),
+    EnableEdgeDetect(u8, Edge),
+    DisableEdgeDetect(u8),
+}
+
+#[derive(Debug)]
+struct PinScript {
+    levels: Vec<(Duration, Level)>,
+    repeat: Option<Duration>,
+}
+
+impl PinScript {
+    fn level_at(&self, elapsed: Duration) -> Option<Level> {
+        let elapsed = match self.repeat {
+            Some(period) if period > Duration::from_millis(0) => {
+                let period_ms = period.as_millis();
+                Duration::from_millis((elapsed.as_millis() % period_ms) as u64)
+            },
+            _ => elapsed
+        };
+        self.levels
+            .iter()
+            .take_while(|(at, _)| *at <= elapsed)
+            .last()
+            .map(|(_, level)| *level)
+    }
+}
+
+#[derive(Debug)]
+struct PinState {
+    mode: Mode,
+    pull: PullUpDown,
+    output: Level,
+}
+
+impl Default for PinState {
+    fn default() -> Self {
+        PinState { mode: Mode::Input, pull: PullUpDown::Off, output: Level::Low }
+    }
+}
+
+/// Edge detection is simulated by comparing the level at each `take_event` with the level
+/// at the previous one, so pulses shorter than the polling interval are missed.
+#[derive(Debug)]
+struct EdgeState {
+    edges: Vec<Edge>,
+    last: Level,
+}
+
+#[derive(Debug)]
+pub struct SimulatedGpio {
+    start: Instant,
+    scripts: HashMap<u8, PinScript>,
+    connections: HashMap<u8, u8>,
+    pins: HashMap<u8, PinState>,
+    edges: HashMap<u8, EdgeState>,
+    calls: Vec<Call>,
+}
+
+impl SimulatedGpio {
+    pub fn new() -> Self {
+        SimulatedGpio {
+            start: Instant::now(),
+            scripts: HashMap::new(),
+            connections: HashMap::new(),
+            pins: HashMap::new(),
+            edges: HashMap::new(),
+            calls: Vec::new(),
+        }
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, FailureError> {
+        let toml_str = std::fs::read_to_string(path)?;
+        Self::from_toml(&toml_str.parse::<Value>()?)
+    }
+
+    pub fn from_toml(toml: &Value) -> Result<Self, FailureError> {
+        let mut sim = Self::new();
+        let pins = match toml.get("pins") {
+            Some(pins) => pins.as_table().ok_or_else(|| scenario_error("pins", "Is not valid type, expected 'table/object'"))?,
+            None => return Ok(sim)
+        };
+        for (pin_str, script) in pins {
+            let key = format!("pins.{}", pin_str);
+            let pin = pin_str.parse::<u8>()
+                .map_err(|_| scenario_error(&key, "Pin must be a bcm pin number"))?;
+            validate_pin(pin)?;
+            if let Some(source) = script.get("connect") {
+                let source = source.as_integer()
+                    .filter(|source| *source >= 0 && *source < 256)
+                    .ok_or_else(|| scenario_error(&format!("{}.connect", key), "Expected a bcm pin number"))?;
+                sim.connect(pin, source as u8)?;
+            }
+            if script.get("levels").is_some() || script.get("connect").is_none() {
+                sim.scripts.insert(pin, parse_script(&key, script)?);
+            }
+        }
+        Ok(sim)
+    }
+
+    /// Scripts `pin` to read `levels`, given as (milliseconds since start, level). If `repeat`
+    /// is set the script restarts every `repeat` milliseconds.
+    pub fn script(&mut self, pin: u8, levels: &[(u64, Level)], repeat: Option<u64>) -> Result<(), Error> {
+        validate_pin(pin)?;
+        let mut levels: Vec<(Duration, Level)> = levels
+            .iter()
+            .map(|(at, level)| (Duration::from_millis(*at), *level))
+            .collect();
+        levels.sort_by_key(|(at, _)| *at);
+        self.scripts.insert(pin, PinScript { levels, repeat: repeat.map(Duration::from_millis) });
+        Ok(())
+    }
+
+    /// Wires `input` to read the level driven on `output`.
+    pub fn connect(&mut self, input: u8, output: u8) -> Result<(), Error> {
+        validate_pin(input)?;
+        validate_pin(output)?;
+        self.connections.insert(input, output);
+        Ok(())
+    }
+
+    /// Restarts the scenario clock.
+    pub fn restart(&mut self) {
+        self.start = Instant::now();
+    }
+
+    /// All calls made so far, oldest first.
+    pub fn calls(&self) -> &[Call] {
+        &self.calls
+    }
+
+    /// Returns and forgets all calls made so far.
+    pub fn take_calls(&mut self) -> Vec<Call> {
+        std::mem::take(&mut self.calls)
+    }
+}
+
+impl GpioBackend for SimulatedGpio {
+    fn set_mode(&mut self, pin: u8, mode: Mode) -> Result<(), Error> {
+        validate_pin(pin)?;
+        self.calls.push(Call::SetMode(pin, mode));
+        self.pins.entry(pin).or_default().mode = mode;
+        Ok(())
+    }
+
+    fn read(&self, pin: u8) -> Result<Level, Error> {
+        validate_pin(pin)?;
+        let state = self.pins.get(&pin);
+        if let Some(PinState { mode: Mode::Output, output, .. }) = state {
+            return Ok(*output);
+        }
+        let connected = self.connections
+            .get(&pin)
+            .and_then(|source| self.pins.get(source));
+        if let Some(PinState { mode: Mode::Output, output, .. }) = connected {
+            return Ok(*output);
+        }
+        let scripted = self.scripts
+            .get(&pin)
+            .and_then(|script| script.level_at(self.start.elapsed()));
+        Ok(match (scripted, state.map(|s| s.pull)) {
+            (Some(level), _) => level,
+            (None, Some(PullUpDown::Up)) => Level::High,
+            (None, _) => Level::Low
+        })
+    }
+
+    fn get_mode(&self, pin: u8) -> Result<Mode, Error> {
+        validate_pin(pin)?;
+        Ok(self.pins.get(&pin).map(|state| state.mode).unwrap_or(Mode::Input))
+    }
+
+    fn get_pullupdown(&self, pin: u8) -> Result<PullUpDown, Error> {
+        validate_pin(pin)?;
+        Ok(self.pins.get(&pin).map(|state| state.pull).unwrap_or(PullUpDown::Off))
+    }
+
+    fn set(&mut self, pin: u8) -> Result<(), Error> {
+        validate_pin(pin)?;
+        self.calls.push(Call::Set(pin));
+        self.pins.entry(pin).or_default().output = Level::High;
+        Ok(())
+    }
+
+    fn clear(&mut self, pin: u8) -> Result<(), Error> {
+        validate_pin(pin)?;
+        self.calls.push(Call::Clear(pin));
+        self.pins.entry(pin).or_default().output = Level::Low;
+        Ok(())
+    }
+
+    fn set_mask(&mut self, mask: u64) -> Result<(), Error> {
+        validate_mask(mask)?;
+        self.calls.push(Call::SetMask(mask));
+        for pin in mask_pins(mask) {
+            self.pins.entry(pin).or_default().output = Level::High;
+        }
+        Ok(())
+    }
+
+    fn clear_mask(&mut self, mask: u64) -> Result<(), Error> {
+        validate_mask(mask)?;
+        self.calls.push(Call::ClearMask(mask));
+        for pin in mask_pins(mask) {
+            self.pins.entry(pin).or_default().output = Level::Low;
+        }
+        Ok(())
+    }
+
+    fn set_pullupdown(&mut self, pins: &[u8], updown: PullUpDown) -> Result<(), Error> {
+        for pin in pins {
+            validate_pin(*pin)?;
+        }
+        self.calls.push(Call::SetPullUpDown(pins.to_vec(), updown));
+        for pin in pins {
+            self.pins.entry(*pin).or_default().pull = updown;
+        }
+        Ok(())
+    }
+
+    fn enable_edge_detect(&mut self, pin: u8, edge: Edge) -> Result<(), Error> {
+        let level = self.read(pin)?;
+        self.calls.push(Call::EnableEdgeDetect(pin, edge));
+        self.edges
+            .entry(pin)
+            .or_insert_with(|| EdgeState { edges: Vec::new(), last: level })
+            .edges
+            .push(edge);
+        Ok(())
+    }
+
+    fn disable_edge_detect(&mut self, pin: u8) -> Result<(), Error> {
+        validate_pin(pin)?;
+        self.calls.push(Call::DisableEdgeDetect(pin));
+        self.edges.remove(&pin);
+        Ok(())
+    }
+
+    fn take_event(&mut self, pin: u8) -> Result<bool, Error> {
+        let level = self.read(pin)?;
+        let state = match self.edges.get_mut(&pin) {
+            Some(state) => state,
+            None => return Ok(false)
+        };
+        let last = std::mem::replace(&mut state.last, level);
+        Ok(state.edges.iter().any(|edge| match edge {
+            Edge::Rising | Edge::AsyncRising => last == Level::Low && level == Level::High,
+            Edge::Falling | Edge::AsyncFalling => last == Level::High && level == Level::Low,
+            Edge::Both => last != level,
+            Edge::High => level == Level::High,
+            Edge::Low => level == Level::Low,
+        }))
+    }
+}
+
+impl Default for SimulatedGpio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse_script(key: &str, script: &Value) -> Result<PinScript, FailureError> {
+    let repeat = match script.get("repeat") {
+        Some(v) => Some(v.as_integer()
+            .filter(|ms| *ms >= 0)
+            .map(|ms| Duration::from_millis(ms as u64))
+            .ok_or_else(|| scenario_error(&format!("{}.repeat", key), "Is not valid type, expected 'unsigned integer'"))?),
+        None => None
+    };
+    let levels_key = format!("{}.levels", key);
+    let mut levels = script.get("levels")
+        .ok_or_else(|| scenario_error(&levels_key, "Was expected but not found"))?
+        .as_array()
+        .ok_or_else(|| scenario_error(&levels_key, "Is not valid type, expected 'array'"))?
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let entry_key = format!("{}[{}]", levels_key, i);
+            let at = entry.get("at")
+                .and_then(Value::as_integer)
+                .filter(|ms| *ms >= 0)
+                .ok_or_else(|| scenario_error(&format!("{}.at", entry_key), "Expected milliseconds as 'unsigned integer'"))?;
+            let level = entry.get("level")
+                .and_then(parse_level)
+                .ok_or_else(|| scenario_error(&format!("{}.level", entry_key), "Expected 'high', 'low', 1 or 0"))?;
+            Ok((Duration::from_millis(at as u64), level))
+        })
+        .collect::<Result<Vec<(Duration, Level)>, FailureError>>()?;
+    levels.sort_by_key(|(at, _)| *at);
+    Ok(PinScript { levels, repeat })
+}
+
+fn parse_level(value: &Value) -> Option<Level> {
+    match value {
+        Value::String(s) if s.eq_ignore_ascii_case("high") => Some(Level::High),
+        Value::String(s) if s.eq_ignore_ascii_case("low") => Some(Level::Low),
+        Value::Integer(1) => Some(Level::High),
+        Value::Integer(0) => Some(Level::Low),
+        _ => None
+    }
+}
+
+fn scenario_error(key: &str, cause: &str) -> FailureError {
+    FailureError::from(ScenarioError { key: key.to_string(), cause: cause.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::claims::PinClaims;
+    use crate::moist_sensor::MoistSensor;
+
+    fn scenario(toml_str: &str) -> SimulatedGpio {
+        SimulatedGpio::from_toml(&toml_str.parse::<Value>().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn scenario_levels_follow_timeline() {
+        let sim = scenario("
+            [pins.27]
+            levels = [
+                { at = 0, level = 'high' },
+                { at = 100, level = 'low' },
+                { at = 250, level = 1 },
+            ]
+        ");
+        let script = &sim.scripts[&27];
+        let at = |ms| script.level_at(Duration::from_millis(ms));
+        assert_eq!(at(0), Some(Level::High));
+        assert_eq!(at(99), Some(Level::High));
+        assert_eq!(at(100), Some(Level::Low));
+        assert_eq!(at(249), Some(Level::Low));
+        assert_eq!(at(10_000), Some(Level::High));
+    }
+
+    #[test]
+    fn scenario_repeats() {
+        let sim = scenario("
+            [pins.27]
+            repeat = 1000
+            levels = [{ at = 0, level = 'high' }, { at = 500, level = 'low' }]
+        ");
+        let script = &sim.scripts[&27];
+        assert_eq!(script.level_at(Duration::from_millis(1200)), Some(Level::High));
+        assert_eq!(script.level_at(Duration::from_millis(1700)), Some(Level::Low));
+    }
+
+    #[test]
+    fn unscripted_pins_read_pull_or_output() {
+        let mut sim = SimulatedGpio::new();
+        assert_eq!(sim.read(5).unwrap(), Level::Low);
+        sim.set_pullupdown(&[5], PullUpDown::Up).unwrap();
+        assert_eq!(sim.read(5).unwrap(), Level::High);
+        sim.set_mode(6, Mode::Output).unwrap();
+        sim.set(6).unwrap();
+        assert_eq!(sim.read(6).unwrap(), Level::High);
+    }
+
+    #[test]
+    fn rejects_invalid_scenarios() {
+        let parse = |toml_str: &str| SimulatedGpio::from_toml(&toml_str.parse::<Value>().unwrap());
+        assert!(parse("[pins.99]\nlevels = []").is_err());
+        assert!(parse("[pins.27]\nlevels = [{ at = -1, level = 'high' }]").is_err());
+        assert!(parse("[pins.27]\nlevels = [{ at = 0, level = 'up' }]").is_err());
+        assert!(parse("[pins.27]\nrepeat = 10").is_err());
+    }
+
+    #[test]
+    fn moist_sensor_read_calls() {
+        let mut sim = scenario("
+            [pins.27]
+            levels = [{ at = 0, level = 'low' }]
+        ");
+        let claims = PinClaims::new();
+        let sensor = MoistSensor::new(claims.claim(17, "moist1").unwrap(), claims.claim(27, "moist1").unwrap(), 0);
+
+        sensor.init(&mut sim).unwrap();
+        assert_eq!(sim.take_calls(), vec![
+            Call::SetMode(27, Mode::Input),
+            Call::SetMode(17, Mode::Output),
+            Call::SetPullUpDown(vec![27], PullUpDown::Up),
+            Call::SetPullUpDown(vec![17], PullUpDown::Off),
+        ]);
+
+        assert_eq!(sensor.read(&mut sim).unwrap(), 1);
+        assert_eq!(sim.take_calls(), vec![Call::Set(17), Call::Clear(17)]);
+
+        sensor.clear(&mut sim).unwrap();
+        assert_eq!(sim.calls(), &[
+            Call::SetMode(27, Mode::Input),
+            Call::Clear(17),
+            Call::SetMode(17, Mode::Input),
+        ]);
+    }
+}
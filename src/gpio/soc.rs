@@ -0,0 +1,74 @@
+///
+/// Detection of the Broadcom SoC, which decides how pull-up/down resistors are configured.
+///
+use std::str::FromStr;
+
+const DEVICE_TREE_COMPATIBLE: &str = "/proc/device-tree/compatible";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Soc {
+    /// BCM2835, BCM2836 and BCM2837 (Raspberry PI 1 to 3), pulls are clocked in through
+    /// GPPUD/GPPUDCLK.
+    Bcm2835,
+    /// BCM2711 (Raspberry PI 4), pulls are set through GPIO_PUP_PDN_CNTRL_REGx.
+    Bcm2711,
+}
+
+impl Soc {
+    /// Reads the SoC from the device tree, defaulting to `Bcm2835` if it can not be read.
+    pub fn detect() -> Soc {
+        match std::fs::read(DEVICE_TREE_COMPATIBLE) {
+            Ok(compatible) => Soc::from_compatible(&compatible),
+            Err(_) => Soc::Bcm2835
+        }
+    }
+
+    /// Picks the SoC from a device tree `compatible` property, a list of NUL separated strings.
+    pub fn from_compatible(compatible: &[u8]) -> Soc {
+        let is_bcm2711 = compatible
+            .split(|b| *b == 0)
+            .any(|c| c == b"brcm,bcm2711" || c == b"brcm,bcm2838");
+        match is_bcm2711 {
+            true => Soc::Bcm2711,
+            false => Soc::Bcm2835
+        }
+    }
+}
+
+impl FromStr for Soc {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Soc, String> {
+        match s {
+            "bcm2835" | "bcm2836" | "bcm2837" => Ok(Soc::Bcm2835),
+            "bcm2711" => Ok(Soc::Bcm2711),
+            _ => Err(format!("Unknown SoC '{}', expected bcm2835, bcm2836, bcm2837 or bcm2711", s))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_soc_from_compatible() {
+        assert_eq!(Soc::from_compatible(b"raspberrypi,3-model-b\0brcm,bcm2837\0"), Soc::Bcm2835);
+        assert_eq!(Soc::from_compatible(b"raspberrypi,model-zero\0brcm,bcm2835\0"), Soc::Bcm2835);
+        assert_eq!(Soc::from_compatible(b"raspberrypi,4-model-b\0brcm,bcm2711\0"), Soc::Bcm2711);
+        assert_eq!(Soc::from_compatible(b"raspberrypi,4-model-b\0brcm,bcm2838"), Soc::Bcm2711);
+    }
+
+    #[test]
+    fn defaults_unknown_compatible_to_bcm2835() {
+        assert_eq!(Soc::from_compatible(b""), Soc::Bcm2835);
+        assert_eq!(Soc::from_compatible(b"brcm,bcm2711x\0acme,board\0"), Soc::Bcm2835);
+    }
+
+    #[test]
+    fn parses_soc_names() {
+        assert_eq!("bcm2836".parse::<Soc>(), Ok(Soc::Bcm2835));
+        assert_eq!("bcm2711".parse::<Soc>(), Ok(Soc::Bcm2711));
+        assert!("bcm2712".parse::<Soc>().is_err());
+    }
+}
@@ -0,0 +1,87 @@
+///
+/// I2C master support for ADCs and other I2C peripherals.
+///
+use std::io;
+use self::dev::I2cDev;
+use self::mock::MockI2c;
+
+pub mod dev;
+pub mod mock;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum I2cBusConfig {
+    /// An i2c-dev adapter such as `/dev/i2c-1`.
+    Dev { device: String },
+    /// A register map answering at the configured address, for running without hardware.
+    Mock,
+}
+
+/// Bus and 7 bit address of an I2C attached sensor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct I2cConfig {
+    pub bus: I2cBusConfig,
+    pub address: u8,
+}
+
+pub trait I2cBus {
+    fn write(&self, address: u8, data: &[u8]) -> Result<(), Error>;
+
+    fn read(&self, address: u8, buf: &mut [u8]) -> Result<(), Error>;
+
+    /// Writes `tx` then reads into `rx` with a repeated start, without releasing the bus.
+    fn write_read(&self, address: u8, tx: &[u8], rx: &mut [u8]) -> Result<(), Error> {
+        self.write(address, tx)?;
+        self.read(address, rx)
+    }
+
+    /// Writes `data` to register `register`, most devices take the register pointer as the first byte.
+    fn write_register(&self, address: u8, register: u8, data: &[u8]) -> Result<(), Error> {
+        let mut tx = Vec::with_capacity(data.len() + 1);
+        tx.push(register);
+        tx.extend_from_slice(data);
+        self.write(address, &tx)
+    }
+
+    fn read_register(&self, address: u8, register: u8, buf: &mut [u8]) -> Result<(), Error> {
+        self.write_read(address, &[register], buf)
+    }
+}
+
+pub fn open(config: &I2cConfig) -> Result<Box<dyn I2cBus + Send>, Error> {
+    Ok(match &config.bus {
+        I2cBusConfig::Dev { device } => Box::new(I2cDev::open(device)?),
+        I2cBusConfig::Mock => {
+            let mock = MockI2c::new();
+            mock.add_device(config.address);
+            Box::new(mock)
+        }
+    })
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Nothing acknowledged the address.
+    NoDevice(u8),
+    /// The device at the address did not finish in time.
+    Timeout(u8),
+    Io(io::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::NoDevice(address) => write!(fmt, "No i2c device at address {:#04x}", address),
+            Error::Timeout(address) => write!(fmt, "Timeout waiting for i2c device at address {:#04x}", address),
+            Error::Io(err) => write!(fmt, "I2c device error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
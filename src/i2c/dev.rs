@@ -0,0 +1,109 @@
+///
+/// I2C through the Linux i2c-dev driver (`/dev/i2c-N`).
+/// https://www.kernel.org/doc/html/latest/i2c/dev-interface.html
+///
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use super::{Error, I2cBus};
+
+const I2C_RDWR: u32 = 0x0707;
+const I2C_M_RD: u16 = 0x0001;
+const ENXIO: i32 = 6;
+const EREMOTEIO: i32 = 121;
+
+#[repr(C)]
+struct I2cMsg {
+    addr: u16,
+    flags: u16,
+    len: u16,
+    buf: *mut u8,
+}
+
+#[repr(C)]
+struct I2cRdwrIoctlData {
+    msgs: *mut I2cMsg,
+    nmsgs: u32,
+}
+
+pub struct I2cDev {
+    file: File,
+}
+
+impl I2cDev {
+    pub fn open(path: &str) -> Result<I2cDev, Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)?;
+        Ok(I2cDev { file })
+    }
+
+    /// Runs the messages as one combined transaction, with repeated starts between them.
+    fn transfer(&self, address: u8, msgs: &mut [I2cMsg]) -> Result<(), Error> {
+        let mut data = I2cRdwrIoctlData { msgs: msgs.as_mut_ptr(), nmsgs: msgs.len() as u32 };
+        match unsafe { libc::ioctl(self.file.as_raw_fd(), I2C_RDWR as _, &mut data) } {
+            -1 => {
+                let err = io::Error::last_os_error();
+                match err.raw_os_error() {
+                    Some(ENXIO) | Some(EREMOTEIO) => Err(Error::NoDevice(address)),
+                    _ => Err(Error::Io(err))
+                }
+            },
+            _ => Ok(())
+        }
+    }
+}
+
+fn write_msg(address: u8, data: &[u8]) -> I2cMsg {
+    // The kernel does not write through the buffer of a write message.
+    I2cMsg { addr: u16::from(address), flags: 0, len: data.len() as u16, buf: data.as_ptr() as *mut u8 }
+}
+
+fn read_msg(address: u8, buf: &mut [u8]) -> I2cMsg {
+    I2cMsg { addr: u16::from(address), flags: I2C_M_RD, len: buf.len() as u16, buf: buf.as_mut_ptr() }
+}
+
+impl I2cBus for I2cDev {
+    fn write(&self, address: u8, data: &[u8]) -> Result<(), Error> {
+        self.transfer(address, &mut [write_msg(address, data)])
+    }
+
+    fn read(&self, address: u8, buf: &mut [u8]) -> Result<(), Error> {
+        self.transfer(address, &mut [read_msg(address, buf)])
+    }
+
+    fn write_read(&self, address: u8, tx: &[u8], rx: &mut [u8]) -> Result<(), Error> {
+        self.transfer(address, &mut [write_msg(address, tx), read_msg(address, rx)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::{offset_of, size_of};
+
+    #[test]
+    fn messages_match_kernel_layout() {
+        // struct i2c_msg and struct i2c_rdwr_ioctl_data from include/uapi/linux/i2c(-dev).h
+        assert_eq!(offset_of!(I2cMsg, addr), 0);
+        assert_eq!(offset_of!(I2cMsg, flags), 2);
+        assert_eq!(offset_of!(I2cMsg, len), 4);
+        assert_eq!(offset_of!(I2cMsg, buf), size_of::<usize>());
+        assert_eq!(size_of::<I2cMsg>(), 2 * size_of::<usize>());
+        assert_eq!(offset_of!(I2cRdwrIoctlData, nmsgs), size_of::<usize>());
+    }
+
+    #[test]
+    fn assembles_messages() {
+        let tx = [0x01, 0x85, 0x83];
+        let write = write_msg(0x48, &tx);
+        assert_eq!((write.addr, write.flags, write.len), (0x48, 0, 3));
+        assert_eq!(write.buf as *const u8, tx.as_ptr());
+
+        let mut rx = [0u8; 2];
+        let read = read_msg(0x48, &mut rx);
+        assert_eq!((read.addr, read.flags, read.len), (0x48, I2C_M_RD, 2));
+        assert_eq!(read.buf, rx.as_mut_ptr());
+    }
+}
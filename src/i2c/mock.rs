@@ -0,0 +1,120 @@
+///
+/// I2C bus for tests and simulation, backed by a register map per device.
+///
+/// Like most register based devices, the first byte written selects a register and any
+/// further bytes replace its contents. Reads return the selected register, zero padded.
+/// Registers are independent, reads and writes do not continue into the next register.
+///
+use std::collections::HashMap;
+use std::sync::Mutex;
+use super::{Error, I2cBus};
+
+#[derive(Default)]
+struct Device {
+    pointer: u8,
+    registers: HashMap<u8, Vec<u8>>,
+}
+
+#[derive(Default)]
+pub struct MockI2c {
+    devices: Mutex<HashMap<u8, Device>>,
+    writes: Mutex<Vec<(u8, Vec<u8>)>>,
+}
+
+impl MockI2c {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes a device with all registers zero answer at `address`.
+    pub fn add_device(&self, address: u8) {
+        self.devices.lock().unwrap().entry(address).or_default();
+    }
+
+    /// Sets the contents of a register, adding the device if needed.
+    pub fn set_register(&self, address: u8, register: u8, data: &[u8]) {
+        self.devices.lock().unwrap()
+            .entry(address)
+            .or_default()
+            .registers
+            .insert(register, data.to_vec());
+    }
+
+    pub fn register(&self, address: u8, register: u8) -> Option<Vec<u8>> {
+        self.devices.lock().unwrap()
+            .get(&address)
+            .and_then(|device| device.registers.get(&register).cloned())
+    }
+
+    /// Address and bytes of all writes so far, oldest first.
+    pub fn writes(&self) -> Vec<(u8, Vec<u8>)> {
+        self.writes.lock().unwrap().clone()
+    }
+}
+
+impl I2cBus for MockI2c {
+    fn write(&self, address: u8, data: &[u8]) -> Result<(), Error> {
+        let mut devices = self.devices.lock().unwrap();
+        let device = devices.get_mut(&address).ok_or(Error::NoDevice(address))?;
+        self.writes.lock().unwrap().push((address, data.to_vec()));
+        if let Some((register, value)) = data.split_first() {
+            device.pointer = *register;
+            if !value.is_empty() {
+                device.registers.insert(*register, value.to_vec());
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&self, address: u8, buf: &mut [u8]) -> Result<(), Error> {
+        let devices = self.devices.lock().unwrap();
+        let device = devices.get(&address).ok_or(Error::NoDevice(address))?;
+        let value = device.registers.get(&device.pointer);
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = value.and_then(|value| value.get(i).cloned()).unwrap_or(0);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_and_writes_registers() {
+        let i2c = MockI2c::new();
+        i2c.set_register(0x48, 1, &[0x85, 0x83]);
+        let mut buf = [0xff; 3];
+        i2c.read_register(0x48, 1, &mut buf).unwrap();
+        // Reads stop at the end of the register.
+        assert_eq!(buf, [0x85, 0x83, 0]);
+        i2c.write_register(0x48, 2, &[0x12, 0x34]).unwrap();
+        assert_eq!(i2c.register(0x48, 2), Some(vec![0x12, 0x34]));
+        // A write of only the register byte selects it without changing its contents.
+        i2c.write(0x48, &[1]).unwrap();
+        let mut buf = [0; 2];
+        i2c.read(0x48, &mut buf).unwrap();
+        assert_eq!(buf, [0x85, 0x83]);
+        assert_eq!(i2c.register(0x48, 3), None);
+        assert_eq!(i2c.writes(), vec![(0x48, vec![1]), (0x48, vec![2, 0x12, 0x34]), (0x48, vec![1])]);
+    }
+
+    #[test]
+    fn refuses_missing_devices() {
+        let i2c = MockI2c::new();
+        i2c.add_device(0x48);
+        match i2c.write(0x49, &[0]) {
+            Err(Error::NoDevice(0x49)) => (),
+            other => panic!("Expected no device, got {:?}", other)
+        }
+        match i2c.read(0x49, &mut [0]) {
+            Err(Error::NoDevice(0x49)) => (),
+            other => panic!("Expected no device, got {:?}", other)
+        }
+        assert!(i2c.writes().is_empty());
+        let mut buf = [0xff; 2];
+        i2c.read_register(0x48, 0, &mut buf).unwrap();
+        assert_eq!(buf, [0, 0]);
+    }
+}
@@ -1,17 +1,38 @@
 #[macro_use] extern crate serde_json;
-use std::sync::{Arc, Mutex};
-use clap::{Arg, App, SubCommand};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use clap::{Arg, App, ArgMatches, SubCommand};
 use futures::{Future, Stream};
 use tokio::runtime::Runtime;
 use tokio_signal::unix::{Signal, SIGINT, SIGTERM};
+use crate::gpio::GpioBackend;
+use crate::gpio::claims::ClaimedGpio;
+use crate::gpio::restore::{restore_on_panic, Restore, RestoringGpio};
+use crate::gpio::soc::Soc;
+use crate::sensor_config::SensorsConfig;
 
+pub mod acquisition;
+pub mod ads1115_sensor;
+pub mod calibrate;
+pub mod calibration;
+pub mod capacitive_sensor;
+pub mod dht22_sensor;
+pub mod ds18b20_sensor;
+pub mod excited_sensor;
 pub mod gpio;
+pub mod i2c;
+pub mod mcp3008_sensor;
 pub mod moist_sensor;
+pub mod pulse_sensor;
+pub mod pwm;
+pub mod sample;
 pub mod sample_formatter;
 pub mod sensor;
 pub mod sensor_config;
+pub mod sensor_registry;
 pub mod sensor_sampler;
 pub mod sensor_setup;
+pub mod spi;
 pub mod rabbitmq_publisher;
 
 fn main() {
@@ -20,7 +41,7 @@ fn main() {
              .long("config")
              .value_name("PATH")
              .help("Path to config file")
-             .required(true)
+             .required(false)
              .takes_value(true)
          )
         .arg(Arg::with_name("interval")
@@ -35,11 +56,70 @@ fn main() {
         .arg(Arg::with_name("gpio")
              .long("gpio")
              .value_name("PATH")
-             .help("Path GPIO device or simulation file")
+             .help("Path to GPIO memory (/dev/gpiomem), GPIO chip (/dev/gpiochipN) or simulation scenario (.toml)")
              .required(false)
              .takes_value(true)
              .default_value("/dev/gpiomem")
          )
+        .arg(Arg::with_name("soc")
+             .long("soc")
+             .value_name("SOC")
+             .help("SoC of the Raspberry PI, bcm2835, bcm2836, bcm2837 or bcm2711. Read from the device tree by default")
+             .required(false)
+             .takes_value(true)
+             .validator(|soc| soc.parse::<Soc>().map(|_| ()))
+         )
+        .subcommand(SubCommand::with_name("pins")
+            .about("Print mode, level and pull of all GPIO pins")
+        )
+        .subcommand(SubCommand::with_name("calibrate")
+            .about("Take readings at reference points and write a calibration of the sensor to the config")
+            .arg(Arg::with_name("sensor")
+                 .value_name("SENSOR_ID")
+                 .help("Id of the sensor to calibrate")
+                 .required(true)
+                 .index(1)
+             )
+            .arg(Arg::with_name("samples")
+                 .long("samples")
+                 .short("n")
+                 .value_name("COUNT")
+                 .help("Readings per reference point")
+                 .takes_value(true)
+                 .default_value("20")
+                 .validator(|samples| match samples.parse::<u32>() {
+                     Ok(samples) if samples >= 1 => Ok(()),
+                     _ => Err("Samples must be a number of at least 1".to_string())
+                 })
+             )
+            .arg(Arg::with_name("spacing")
+                 .long("spacing")
+                 .value_name("MILLIS")
+                 .help("Milliseconds between readings")
+                 .takes_value(true)
+                 .default_value("500")
+                 .validator(|spacing| match spacing.parse::<u64>() {
+                     Ok(spacing) if spacing >= 1 => Ok(()),
+                     _ => Err("Spacing must be a number of at least 1".to_string())
+                 })
+             )
+            .arg(Arg::with_name("point")
+                 .long("point")
+                 .value_name("VALUE")
+                 .help("Calibrated value of a reference point, repeat for each point. Dry air (0) and water (100) by default")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1)
+                 .validator(|point| match point.parse::<f64>() {
+                     Ok(point) if point.is_finite() => Ok(()),
+                     _ => Err("Reference points must be numbers".to_string())
+                 })
+             )
+            .arg(Arg::with_name("dry-run")
+                 .long("dry-run")
+                 .help("Print the calibration without writing the config")
+             )
+        )
         .subcommand(SubCommand::with_name("rabbitmq")
             .about("Publish sensor values on rabbitmq")
             .arg(Arg::with_name("host")
@@ -61,23 +141,75 @@ fn main() {
         )
         .get_matches();
 
+    let gpio_path = cmd.value_of("gpio").unwrap();
+    // Already validated by clap.
+    let soc = cmd.value_of("soc")
+        .and_then(|soc| soc.parse::<Soc>().ok())
+        .unwrap_or_else(Soc::detect);
+
+    if gpio_path.ends_with(".toml") {
+        let sim = gpio::simulated::SimulatedGpio::from_file(gpio_path)
+            .unwrap_or_else(|err| panic!("Error loading GPIO simulation at {}: {}", gpio_path, err));
+        run(&cmd, sim, soc);
+    } else if gpio_path.starts_with("/dev/gpiochip") {
+        run(&cmd, gpio::cdev::CdevGpio::open(gpio_path).unwrap(), soc);
+    } else {
+        run(&cmd, gpio::Gpio::with_soc(gpio_path, soc).unwrap(), soc);
+    }
+}
+
+fn load_config(cmd: &ArgMatches) -> SensorsConfig {
     let config_path = cmd.value_of("config").expect("Config path is required");
     let toml_str = std::fs::read_to_string(config_path)
-        .expect(&format!("Error reading file at {}", config_path));
+        .unwrap_or_else(|err| panic!("Error reading file at {}: {}", config_path, err));
     let config = sensor_config::from_toml(&toml_str)
-        .expect(&format!("Error parsing configuration at {}", config_path));
+        .unwrap_or_else(|err| panic!("Error parsing configuration at {}: {}", config_path, err));
     println!("Using config: {:?}", config);
+    config
+}
 
-    let gpio_path = cmd.value_of("gpio").unwrap();
-
-    let gp = Arc::new(Mutex::new(gpio::Gpio::new(&gpio_path).unwrap()));
-    let sample_streams = match sensor_setup::setup(&config, gp.clone()) {
-        Ok(sf) => sf,
-        Err(err) => panic!("Config error {:?}", err)
-    };
-
+fn run<G: GpioBackend + Send + 'static>(cmd: &ArgMatches, gpio: G, soc: Soc) {
     match cmd.subcommand() {
+        ("pins", Some(_)) => print_pins(&gpio),
+        ("calibrate", Some(cal_cmd)) => {
+            let config = load_config(cmd);
+            let options = calibrate::Options {
+                samples: cal_cmd.value_of("samples").unwrap().parse().expect("Samples must be a number"),
+                spacing: Duration::from_millis(cal_cmd.value_of("spacing").unwrap().parse().expect("Spacing must be a number")),
+                points: cal_cmd.values_of("point")
+                    .map(|points| points.map(|p| p.parse().expect("Reference points must be numbers")).collect())
+                    .unwrap_or_else(Vec::new),
+                dry_run: cal_cmd.is_present("dry-run")
+            };
+            let gp = Arc::new(Mutex::new(ClaimedGpio::new(RestoringGpio::new(gpio))));
+            restore_on_panic(&gp);
+            let calibrated = calibrate::run(
+                cmd.value_of("config").unwrap(),
+                &config,
+                cal_cmd.value_of("sensor").unwrap(),
+                &options,
+                gp.clone(),
+                soc
+            );
+            if let Err(err) = &calibrated {
+                println!("Calibration failed: {}", err);
+            }
+            let restored = gp.lock().unwrap_or_else(PoisonError::into_inner).restore();
+            if let Err(err) = restored {
+                println!("Error restoring gpio pins: {}", err);
+            }
+            if calibrated.is_err() {
+                std::process::exit(1);
+            }
+        },
         ("rabbitmq", Some(rmq_cmd)) => {
+            let config = load_config(cmd);
+            let gp = Arc::new(Mutex::new(ClaimedGpio::new(RestoringGpio::new(gpio))));
+            restore_on_panic(&gp);
+            let sample_streams = match sensor_setup::setup(&config, gp.clone(), soc) {
+                Ok(sf) => sf,
+                Err(err) => panic!("Config error {:?}", err)
+            };
             let int = Signal::new(SIGINT).flatten_stream().into_future();
             let term = Signal::new(SIGTERM).flatten_stream().into_future();
             let sigf = int.select(term)
@@ -91,10 +223,22 @@ fn main() {
                     sample_streams
                 )
             ).expect("runtime exited with error");
+            let restored = gp.lock().unwrap_or_else(PoisonError::into_inner).restore();
+            if let Err(err) = restored {
+                println!("Error restoring gpio pins: {}", err);
+            }
         },
         (&_, _) => println!("{}", cmd.usage())
     };
+}
+
+fn print_pins<G: GpioBackend>(gpio: &G) {
+    fn or_unknown<T: std::fmt::Debug>(v: Option<T>) -> String {
+        v.map(|v| format!("{:?}", v)).unwrap_or_else(|| "-".to_string())
+    }
 
-    // TODO Bring back teardown
-    //{ sensor.clear(&mut gp.lock().unwrap()).unwrap() };
+    println!("{:>3}  {:<6}  {:<5}  PULL", "PIN", "MODE", "LEVEL");
+    for pin in gpio.snapshot() {
+        println!("{:>3}  {:<6}  {:<5}  {}", pin.pin, or_unknown(pin.mode), or_unknown(pin.level), or_unknown(pin.pull));
+    }
 }
@@ -0,0 +1,212 @@
+///
+/// Analogue moisture probe read through an MCP3008 10 bit ADC.
+/// http://ww1.microchip.com/downloads/en/DeviceDoc/21295d.pdf
+///
+use std::time::Duration;
+use failure::Error as FailureError;
+use crate::gpio::{GpioBackend, Mode, PullUpDown};
+use crate::gpio::claims::ClaimedPin;
+use crate::sample::{Channel, Status};
+use crate::sensor::{Sensor, Error};
+use crate::sensor_config::SensorConfig;
+use crate::sensor_registry::SensorContext;
+use crate::spi::{self, SpiBus, SpiBusConfig};
+
+/// Result at or above the reference voltage.
+const FULL_SCALE: u32 = 1023;
+
+pub struct Mcp3008Sensor<G: GpioBackend> {
+    pwr_pin: ClaimedPin,
+    spi: Box<dyn SpiBus<G> + Send>,
+    /// Pins of a bit-banged bus, empty for hardware SPI.
+    spi_pins: Vec<ClaimedPin>,
+    channel: u8,
+    differential: bool,
+    pwr_wait: u64
+}
+
+impl<G: GpioBackend> Sensor<G> for Mcp3008Sensor<G> {
+    fn init(&self, gpio: &mut G) -> Result<(), Error> {
+        gpio.set_mode(self.pwr_pin.pin(), Mode::Output)?;
+        gpio.set_pullupdown(&[self.pwr_pin.pin()], PullUpDown::Off)?;
+        self.spi.init(gpio)?;
+        Ok(())
+    }
+
+    fn clear(&self, gpio: &mut G) -> Result<(), Error> {
+        // Shared pins are left to the last sensor using them.
+        if self.spi_pins.iter().all(ClaimedPin::is_sole_owner) {
+            self.spi.clear(gpio)?;
+        }
+        if self.pwr_pin.is_sole_owner() {
+            gpio.clear(self.pwr_pin.pin())?;
+            gpio.set_mode(self.pwr_pin.pin(), Mode::Input)?;
+        }
+        Ok(())
+    }
+
+    fn read(&self, gpio: &mut G) -> Result<Vec<Channel>, Error> {
+        gpio.set(self.pwr_pin.pin())?;
+        std::thread::sleep(Duration::from_millis(self.pwr_wait));
+        let res = self.convert(gpio);
+        gpio.clear(self.pwr_pin.pin())?;
+        let value = res?;
+        // The input may be anywhere above the reference voltage.
+        Ok(vec![match value {
+            FULL_SCALE => Channel::new("value", value).with_status(Status::Suspect("Input at full scale".to_string())),
+            _ => Channel::new("value", value)
+        }])
+    }
+}
+
+impl<G: GpioBackend> Mcp3008Sensor<G> {
+    pub fn new(
+        pwr_pin: ClaimedPin,
+        spi: Box<dyn SpiBus<G> + Send>,
+        spi_pins: Vec<ClaimedPin>,
+        channel: u8,
+        differential: bool,
+        pwr_wait: u64
+    ) -> Self {
+        Mcp3008Sensor { pwr_pin, spi, spi_pins, channel, differential, pwr_wait }
+    }
+
+    /// One conversion, the start bit is byte aligned so the result ends the last two bytes.
+    fn convert(&self, gpio: &mut G) -> Result<u32, Error> {
+        let single = if self.differential { 0 } else { 0x80 };
+        let tx = [0x01, single | (self.channel & 0x07) << 4, 0x00];
+        let mut rx = [0u8; 3];
+        self.spi.transfer(gpio, &tx, &mut rx)?;
+        Ok(u32::from(rx[1] & 0x03) << 8 | u32::from(rx[2]))
+    }
+}
+
+/// Options are `channel`, 0 to 7, and `differential` to read channel pairs instead.
+pub fn from_config<G: GpioBackend + Send + 'static>(config: &SensorConfig, context: &SensorContext<G>)
+    -> Result<Box<dyn Sensor<G> + Send>, FailureError> {
+    Ok(Box::new(configured(config, context)?))
+}
+
+fn configured<G: GpioBackend>(config: &SensorConfig, context: &SensorContext<G>) -> Result<Mcp3008Sensor<G>, FailureError> {
+    let channel = config.option("channel", |toml| { toml.as_integer() }, "integer")?;
+    if !(0..=7).contains(&channel) {
+        return Err(config.invalid("channel", format!("Not a valid mcp3008 channel: {}, expected 0 to 7", channel)));
+    }
+    let differential = config.optional("differential", |toml| { toml.as_bool() }, "boolean")?
+        .unwrap_or(false);
+    let pwr_pin = context.claim_pwr_pin(config)?;
+    let spi_config = config.require(config.spi.as_ref(), "spi")?;
+    // Several ADCs may share a bit-banged bus, each with its own chip select.
+    let spi_pins = match spi_config.bus {
+        SpiBusConfig::BitBang(pins) => vec![
+            context.claims.claim_shared(pins.clk, &config.id)?,
+            context.claims.claim_shared(pins.mosi, &config.id)?,
+            context.claims.claim_shared(pins.miso, &config.id)?,
+            context.claims.claim(pins.cs, &config.id)?
+        ],
+        _ => Vec::new()
+    };
+    let spi = spi::open(spi_config)?;
+    Ok(Mcp3008Sensor::new(
+        pwr_pin,
+        spi,
+        spi_pins,
+        channel as u8,
+        differential,
+        config.require(config.pwr_wait, "pwr_wait")?
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use crate::gpio::claims::PinClaims;
+    use crate::gpio::simulated::{Call, SimulatedGpio};
+    use crate::sample::Value;
+    use crate::sensor_config::config_with;
+    use crate::sensor_registry::simulated_context;
+    use crate::spi::mock::MockSpi;
+
+    type Sent = Arc<Mutex<Vec<Vec<u8>>>>;
+
+    /// A sensor on channel `channel` whose ADC answers `response` and records what was sent.
+    fn sensor(channel: u8, differential: bool, response: [u8; 3])
+        -> (Mcp3008Sensor<SimulatedGpio>, Sent) {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let recorded = sent.clone();
+        let spi = MockSpi::with_responder(move |tx| {
+            recorded.lock().unwrap().push(tx.to_vec());
+            response.to_vec()
+        });
+        let pwr_pin = PinClaims::new().claim(23, "adc1").unwrap();
+        (Mcp3008Sensor::new(pwr_pin, Box::new(spi), Vec::new(), channel, differential, 0), sent)
+    }
+
+    #[test]
+    fn sends_start_and_channel_bits() {
+        let mut gpio = SimulatedGpio::new();
+        let (single, sent) = sensor(5, false, [0; 3]);
+        single.read(&mut gpio).unwrap();
+        let (differential, sent_differential) = sensor(2, true, [0; 3]);
+        differential.read(&mut gpio).unwrap();
+        assert_eq!(*sent.lock().unwrap(), vec![vec![0x01, 0xd0, 0x00]]);
+        assert_eq!(*sent_differential.lock().unwrap(), vec![vec![0x01, 0x20, 0x00]]);
+    }
+
+    #[test]
+    fn decodes_ten_bit_result() {
+        let mut gpio = SimulatedGpio::new();
+        // Bits received before the null bit and above the result are undefined.
+        let (adc, _) = sensor(0, false, [0xff, 0xfe, 0x5a]);
+        assert_eq!(adc.read(&mut gpio).unwrap(), Channel::single(0x25au32));
+    }
+
+    #[test]
+    fn suspects_saturated_input() {
+        let mut gpio = SimulatedGpio::new();
+        let (adc, _) = sensor(0, false, [0x00, 0x03, 0xff]);
+        let channel = &adc.read(&mut gpio).unwrap()[0];
+        assert_eq!(channel.value, Value::Integer(1023));
+        assert_eq!(channel.status, Status::Suspect("Input at full scale".to_string()));
+        let (adc, _) = sensor(0, false, [0x00, 0x03, 0xfe]);
+        assert!(adc.read(&mut gpio).unwrap()[0].status.is_ok());
+    }
+
+    #[test]
+    fn powers_probe_around_conversion() {
+        let mut gpio = SimulatedGpio::new();
+        let (adc, _) = sensor(0, false, [0; 3]);
+        adc.init(&mut gpio).unwrap();
+        adc.read(&mut gpio).unwrap();
+        adc.clear(&mut gpio).unwrap();
+        assert_eq!(gpio.calls(), &[
+            Call::SetMode(23, Mode::Output),
+            Call::SetPullUpDown(vec![23], PullUpDown::Off),
+            Call::Set(23),
+            Call::Clear(23),
+            Call::Clear(23),
+            Call::SetMode(23, Mode::Input)
+        ][..]);
+    }
+
+    #[test]
+    fn parses_options() {
+        let context = simulated_context();
+        let configured_with = |extra: &str| configured(
+            &config_with("mcp3008", &format!("pwr_pin = 23\npwr_shared = true\npwr_wait = 1\n{}", extra)).unwrap(),
+            &context
+        );
+        let adc = configured_with("channel = 7\ndifferential = true\nspi = { bus = 'mock' }").unwrap();
+        assert_eq!((adc.channel, adc.differential, adc.spi_pins.len()), (7, true, 0));
+        let spi = "spi = { bus = 'bitbang', clk_pin = 5, mosi_pin = 6, miso_pin = 13, cs_pin = 16 }";
+        let adc = configured_with(&format!("channel = 0\n{}", spi)).unwrap();
+        assert_eq!((adc.channel, adc.differential), (0, false));
+        assert_eq!(adc.spi_pins.iter().map(ClaimedPin::pin).collect::<Vec<u8>>(), vec![5, 6, 13, 16]);
+        let error = |extra: &str| configured_with(extra).err().unwrap().to_string();
+        assert!(error("spi = { bus = 'mock' }").contains("sensors.s1.channel"));
+        assert!(error("channel = 8\nspi = { bus = 'mock' }").contains("sensors.s1.channel"));
+        assert!(error("channel = -1\nspi = { bus = 'mock' }").contains("sensors.s1.channel"));
+        assert!(error("channel = 0").contains("sensors.s1.spi"));
+    }
+}
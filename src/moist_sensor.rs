@@ -1,30 +1,34 @@
 use std::time::Duration;
-use crate::gpio::{Gpio, Mode, Level, PullUpDown, Error}; // as GpioError}
-use crate::sensor::Sensor;
+use failure::Error as FailureError;
+use crate::gpio::{GpioBackend, Mode, Level, PullUpDown, Error}; // as GpioError}
+use crate::gpio::claims::ClaimedPin;
+use crate::sample::Channel;
+use crate::sensor::{Sensor, Error as SensorError};
+use crate::sensor_config::SensorConfig;
+use crate::sensor_registry::SensorContext;
 
-#[derive(Clone, Copy)]
 pub struct MoistSensor {
-    pwr_pin: u8,
-    val_pin: u8,
+    pwr_pin: ClaimedPin,
+    val_pin: ClaimedPin,
     pwr_wait: u64
 }
 
-impl Sensor for MoistSensor {
-    fn init(&self, gpio: &mut Gpio) -> Result<(), Error> {
-        self.init(gpio)
+impl<G: GpioBackend> Sensor<G> for MoistSensor {
+    fn init(&self, gpio: &mut G) -> Result<(), SensorError> {
+        Ok(self.init(gpio)?)
     }
 
-    fn read(&self, gpio: &mut Gpio) -> Result<u32, Error> {
-        self.read(gpio)
+    fn read(&self, gpio: &mut G) -> Result<Vec<Channel>, SensorError> {
+        Ok(Channel::single(self.read(gpio)?))
     }
 
-    fn clear(&self, gpio: &mut Gpio) -> Result<(), Error> {
-        self.clear(gpio)
+    fn clear(&self, gpio: &mut G) -> Result<(), SensorError> {
+        Ok(self.clear(gpio)?)
     }
 }
 
 impl MoistSensor {
-    pub fn new(pwr_pin: u8, val_pin: u8, pwr_wait: u64) -> MoistSensor {
+    pub fn new(pwr_pin: ClaimedPin, val_pin: ClaimedPin, pwr_wait: u64) -> MoistSensor {
         MoistSensor {
             pwr_pin,
             val_pin,
@@ -32,29 +36,39 @@ impl MoistSensor {
         }
     }
 
-    pub fn init(&self, gpio: &mut Gpio) -> Result<(), Error> {
-        gpio.set_mode(self.val_pin, Mode::Input).unwrap();
-        gpio.set_mode(self.pwr_pin, Mode::Output).unwrap();
-        gpio.set_pullupdown(&[self.val_pin], PullUpDown::Up).unwrap();
-        gpio.set_pullupdown(&[self.pwr_pin], PullUpDown::Off).unwrap();
+    pub fn init<G: GpioBackend>(&self, gpio: &mut G) -> Result<(), Error> {
+        gpio.set_mode(self.val_pin.pin(), Mode::Input)?;
+        gpio.set_mode(self.pwr_pin.pin(), Mode::Output)?;
+        gpio.set_pullupdown(&[self.val_pin.pin()], PullUpDown::Up)?;
+        gpio.set_pullupdown(&[self.pwr_pin.pin()], PullUpDown::Off)?;
         Ok(())
     }
 
-    pub fn clear(&self, gpio: &mut Gpio) -> Result<(), Error> {
-        let _ = gpio.clear(self.pwr_pin)?;
-        let _ = gpio.set_mode(self.val_pin, Mode::Input)?;
-        let _ = gpio.set_mode(self.pwr_pin, Mode::Input)?;
+    pub fn clear<G: GpioBackend>(&self, gpio: &mut G) -> Result<(), Error> {
+        gpio.set_mode(self.val_pin.pin(), Mode::Input)?;
+        // A shared power pin is left to the last sensor using it.
+        if self.pwr_pin.is_sole_owner() {
+            gpio.clear(self.pwr_pin.pin())?;
+            gpio.set_mode(self.pwr_pin.pin(), Mode::Input)?;
+        }
         Ok(())
     }
 
-    pub fn read(&self, gpio: &mut Gpio) -> Result<u32, Error> {
-        gpio.set(self.pwr_pin)?;
+    pub fn read<G: GpioBackend>(&self, gpio: &mut G) -> Result<u32, Error> {
+        gpio.set(self.pwr_pin.pin())?;
         std::thread::sleep(Duration::from_millis(self.pwr_wait));
-        let res = match gpio.read(self.val_pin)? {
+        let res = match gpio.read(self.val_pin.pin())? {
             Level::High => 0,
             Level::Low => 1
         };
-        gpio.clear(self.pwr_pin)?;
+        gpio.clear(self.pwr_pin.pin())?;
         Ok(res)
     }
 }
+
+pub fn from_config<G: GpioBackend + Send + 'static>(config: &SensorConfig, context: &SensorContext<G>)
+    -> Result<Box<dyn Sensor<G> + Send>, FailureError> {
+    let pwr_pin = context.claim_pwr_pin(config)?;
+    let val_pin = context.claim_val_pin(config)?;
+    Ok(Box::new(MoistSensor::new(pwr_pin, val_pin, config.require(config.pwr_wait, "pwr_wait")?)))
+}
@@ -0,0 +1,240 @@
+///
+/// Pulse counter for tipping-bucket rain gauges and flow meters.
+///
+/// Unlike one-shot sensors, pulses arrive between samples. They are picked up as edge events
+/// (see `gpio::events`) latched by the backend, so pulses are not lost while other sensors hold
+/// the GPIO. An edge within the debounce time of the last counted one is taken as reed switch
+/// bounce. Each read reports the pulses since the previous read and their rate per second.
+///
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use failure::Error as FailureError;
+use futures::future::Future;
+use futures::stream::Stream;
+use crate::gpio::{Edge, Error as GpioError, GpioBackend, Mode, PullUpDown};
+use crate::gpio::claims::ClaimedPin;
+use crate::gpio::events::EdgeEvents;
+use crate::sample::Channel;
+use crate::sensor::{Sensor, Error as SensorError};
+use crate::sensor_config::SensorConfig;
+use crate::sensor_registry::SensorContext;
+
+#[derive(Default)]
+struct Counter {
+    count: u32,
+    last_counted: Option<SystemTime>,
+}
+
+impl Counter {
+    fn edge(&mut self, at: SystemTime, debounce: Duration) {
+        let bounce = match self.last_counted.map(|last| at.duration_since(last)) {
+            Some(Ok(since)) => since < debounce,
+            // The clock went back, the edge is as good as any.
+            Some(Err(_)) | None => false
+        };
+        if !bounce {
+            self.count = self.count.saturating_add(1);
+            self.last_counted = Some(at);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PulseSettings {
+    /// Level change counted as a pulse, `Rising`, `Falling` or `Both`.
+    pub edge: Edge,
+    pub pull: PullUpDown,
+    pub debounce: Duration,
+    pub poll_interval: Duration,
+}
+
+pub struct PulseSensor {
+    pin: ClaimedPin,
+    settings: PulseSettings,
+    counter: Arc<Mutex<Counter>>,
+    /// Start of the pulses counted into the next read.
+    since: Mutex<Instant>,
+}
+
+impl<G: GpioBackend> Sensor<G> for PulseSensor {
+    fn init(&self, gpio: &mut G) -> Result<(), SensorError> {
+        gpio.set_mode(self.pin.pin(), Mode::Input)?;
+        gpio.set_pullupdown(&[self.pin.pin()], self.settings.pull)?;
+        *self.since.lock().unwrap() = Instant::now();
+        Ok(())
+    }
+
+    fn clear(&self, gpio: &mut G) -> Result<(), SensorError> {
+        gpio.set_pullupdown(&[self.pin.pin()], PullUpDown::Off)?;
+        Ok(())
+    }
+
+    /// Pulses since the previous read, and their rate per second.
+    fn read(&self, _gpio: &mut G) -> Result<Vec<Channel>, SensorError> {
+        let count = std::mem::replace(&mut self.counter.lock().unwrap().count, 0);
+        let now = Instant::now();
+        let elapsed = now - std::mem::replace(&mut *self.since.lock().unwrap(), now);
+        let seconds = elapsed.as_secs_f64();
+        Ok(vec![
+            Channel::new("count", count),
+            Channel::new("rate", if seconds > 0.0 { f64::from(count) / seconds } else { 0.0 }).with_unit("1/s")
+        ])
+    }
+}
+
+impl PulseSensor {
+    pub fn new(pin: ClaimedPin, settings: PulseSettings) -> Self {
+        PulseSensor {
+            pin,
+            settings,
+            counter: Arc::new(Mutex::new(Counter::default())),
+            since: Mutex::new(Instant::now())
+        }
+    }
+
+    /// Counts pulses into this sensor's reads, see `PulseCounting::start`.
+    pub fn counting(&self) -> PulseCounting {
+        PulseCounting { pin: self.pin.pin(), settings: self.settings, counter: self.counter.clone() }
+    }
+}
+
+/// Counting of the pulses read by a `PulseSensor`.
+pub struct PulseCounting {
+    pin: u8,
+    settings: PulseSettings,
+    counter: Arc<Mutex<Counter>>,
+}
+
+impl PulseCounting {
+    /// Counts pulses while the returned stream is polled, from after the sensor is
+    /// initialised. The stream gives no items, edge detection is disabled when it is dropped.
+    pub fn start<G: GpioBackend>(self, gpio: Arc<Mutex<G>>)
+        -> Result<impl Stream<Item = (), Error = GpioError>, GpioError> {
+        let events = EdgeEvents::new(gpio, &[self.pin], self.settings.edge, self.settings.poll_interval)?;
+        let counter = self.counter;
+        let debounce = self.settings.debounce;
+        Ok(events
+            .map(move |event| counter.lock().unwrap().edge(event.timestamp, debounce))
+            .filter(|_| false))
+    }
+}
+
+/// Pulses are counted on `val_pin`. Options are `edge`, `pull`, and `debounce` and
+/// `poll_interval` in milliseconds.
+pub fn from_config<G: GpioBackend + Send + 'static>(config: &SensorConfig, context: &SensorContext<G>)
+    -> Result<Box<dyn Sensor<G> + Send>, FailureError> {
+    let sensor = configured(config, context)?;
+    let counting = sensor.counting();
+    let gpio = context.gpio.clone();
+    // Started on first poll, once the pin is set up by `init`.
+    context.run_alongside(Box::new(futures::future::lazy(move || counting.start(gpio))
+        .flatten_stream()
+        .map(|_| Vec::new())
+        .map_err(FailureError::from)));
+    Ok(Box::new(sensor))
+}
+
+fn configured<G: GpioBackend>(config: &SensorConfig, context: &SensorContext<G>) -> Result<PulseSensor, FailureError> {
+    let edge = match config.optional("edge", |toml| { toml.as_str() }, "string")? {
+        None | Some("falling") => Edge::Falling,
+        Some("rising") => Edge::Rising,
+        Some("both") => Edge::Both,
+        Some(other) => return Err(config.invalid("edge", format!("Unknown edge '{}', expected 'falling', 'rising' or 'both'", other)))
+    };
+    let pull = match config.optional("pull", |toml| { toml.as_str() }, "string")? {
+        None | Some("up") => PullUpDown::Up,
+        Some("down") => PullUpDown::Down,
+        Some("off") => PullUpDown::Off,
+        Some(other) => return Err(config.invalid("pull", format!("Unknown pull '{}', expected 'up', 'down' or 'off'", other)))
+    };
+    let millis = |key: &str, min: i64, default: u64| -> Result<Duration, FailureError> {
+        match config.optional(key, |toml| { toml.as_integer() }, "unsigned integer")? {
+            Some(value) if value >= min => Ok(Duration::from_millis(value as u64)),
+            Some(value) => Err(config.invalid(key, format!("Must be at least {}, got {}", min, value))),
+            None => Ok(Duration::from_millis(default))
+        }
+    };
+    let settings = PulseSettings {
+        edge,
+        pull,
+        debounce: millis("debounce", 0, 10)?,
+        poll_interval: millis("poll_interval", 1, 5)?
+    };
+    Ok(PulseSensor::new(context.claim_val_pin(config)?, settings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_timer::Timeout;
+    use crate::gpio::Level;
+    use crate::gpio::claims::PinClaims;
+    use crate::gpio::simulated::{Call, SimulatedGpio};
+    use crate::sample::Value;
+    use crate::sensor_config::config_with;
+    use crate::sensor_registry::simulated_context;
+
+    fn at(ms: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn counts_edges_apart_from_bounce() {
+        let mut counter = Counter::default();
+        for ms in &[1000, 1002, 1009, 1010, 1500, 1501, 900, 2000] {
+            counter.edge(at(*ms), Duration::from_millis(10));
+        }
+        assert_eq!(counter.count, 5);
+    }
+
+    #[test]
+    fn counts_latched_pulses_between_reads() {
+        let mut sim = SimulatedGpio::new();
+        // A tip 20 ms in, with a 1 ms bounce.
+        sim.script(25, &[(0, Level::High), (20, Level::Low), (21, Level::High), (22, Level::Low)], None).unwrap();
+        let gpio = Arc::new(Mutex::new(sim));
+        let claims = PinClaims::new();
+        let settings = PulseSettings {
+            edge: Edge::Falling,
+            pull: PullUpDown::Up,
+            debounce: Duration::from_millis(10),
+            poll_interval: Duration::from_millis(1)
+        };
+        let sensor = PulseSensor::new(claims.claim(25, "rain1").unwrap(), settings);
+        sensor.init(&mut *gpio.lock().unwrap()).unwrap();
+        let counting = sensor.counting().start(gpio.clone()).unwrap().for_each(|_| Ok(()));
+
+        // The stream never ends, it is dropped once the timeout elapses.
+        let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+        let counted = runtime.block_on(Timeout::new(counting, Duration::from_millis(60)));
+        assert!(counted.unwrap_err().is_elapsed());
+
+        let mut gp = gpio.lock().unwrap();
+        let channels = sensor.read(&mut *gp).unwrap();
+        assert_eq!((&channels[0].name, channels[0].value), (&"count".to_string(), Value::Integer(1)));
+        // One pulse in the 60 ms or so since init.
+        assert_eq!((&channels[1].name, channels[1].unit.as_deref()), (&"rate".to_string(), Some("1/s")));
+        assert!(channels[1].value.as_f64() > 1.0 && channels[1].value.as_f64() < 1.0 / 0.06);
+        let channels = sensor.read(&mut *gp).unwrap();
+        assert_eq!((channels[0].value, channels[1].value), (Value::Integer(0), Value::Float(0.0)));
+        assert_eq!(gp.take_calls().last(), Some(&Call::DisableEdgeDetect(25)));
+    }
+
+    #[test]
+    fn parses_options() {
+        let configured_with = |extra: &str| configured(
+            &config_with("pulse_counter", &format!("val_pin = 25\n{}", extra)).unwrap(),
+            &simulated_context()
+        );
+        let settings = configured_with("").unwrap().settings;
+        assert_eq!((settings.edge, settings.pull), (Edge::Falling, PullUpDown::Up));
+        assert_eq!((settings.debounce, settings.poll_interval), (Duration::from_millis(10), Duration::from_millis(5)));
+        let settings = configured_with("edge = 'both'\npull = 'off'\ndebounce = 0\npoll_interval = 1").unwrap().settings;
+        assert_eq!((settings.edge, settings.pull), (Edge::Both, PullUpDown::Off));
+        assert_eq!((settings.debounce, settings.poll_interval), (Duration::from_millis(0), Duration::from_millis(1)));
+        for (extra, key) in &[("edge = 'high'", "edge"), ("pull = 'sideways'", "pull"), ("debounce = -1", "debounce"), ("poll_interval = 0", "poll_interval")] {
+            let error = configured_with(extra).err().unwrap().to_string();
+            assert!(error.contains(&format!("sensors.s1.{}", key)), "{}", error);
+        }
+    }
+}
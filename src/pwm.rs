@@ -0,0 +1,429 @@
+///
+/// Hardware PWM and general purpose clock (GPCLK) outputs.
+///
+/// Both peripherals live outside the GPIO register block and are mapped from `/dev/mem`,
+/// which needs root. The output pin is switched to the alternate function that routes the
+/// peripheral to it. Note that the PWM peripheral is also used for analogue audio.
+///
+use std::fs::OpenOptions;
+use std::io;
+use std::time::Duration;
+use memmap::{MmapOptions, MmapMut};
+use register::FieldValue;
+use register::mmio::ReadWrite;
+use crate::gpio::{self, GpioBackend, Mode};
+use crate::gpio::soc::Soc;
+
+pub mod registers;
+
+use self::registers::{CM_CTL, CM_DIV, CM_PASSWD, CTL};
+
+const DEVICE_TREE_RANGES: &str = "/proc/device-tree/soc/ranges";
+/// Physical memory, the PWM and clock manager registers are not in `/dev/gpiomem`.
+pub const DEVMEM: &str = "/dev/mem";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PwmChannel {
+    Pwm0,
+    Pwm1,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClockOutput {
+    Gpclk0,
+    Gpclk1,
+    Gpclk2,
+}
+
+/// The PWM channel and alternate function available on `pin`.
+pub fn pwm_channel(pin: u8) -> Option<(PwmChannel, Mode)> {
+    match pin {
+        12 => Some((PwmChannel::Pwm0, Mode::Alt0)),
+        13 => Some((PwmChannel::Pwm1, Mode::Alt0)),
+        18 => Some((PwmChannel::Pwm0, Mode::Alt5)),
+        19 => Some((PwmChannel::Pwm1, Mode::Alt5)),
+        _ => None
+    }
+}
+
+/// Square wave output on a PWM or GPCLK pin, eg. to excite a capacitive probe.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Excitation {
+    pub pin: u8,
+    pub frequency: f64,
+    /// Fraction of each period the output is high. GPCLK pins only output 0.5.
+    pub duty: f64,
+}
+
+impl Excitation {
+    /// Maps the peripheral routed to the pin and starts the output, see `Pwm::start` and
+    /// `GpClock::start`.
+    pub fn start<G: GpioBackend>(&self, gpio: &mut G, devmem: &str, soc: Soc) -> Result<Output, Error> {
+        if pwm_channel(self.pin).is_some() {
+            let mut pwm = Pwm::new(devmem, soc)?;
+            pwm.start(gpio, self.pin, self.frequency, self.duty)?;
+            Ok(Output::Pwm(pwm))
+        } else {
+            let mut clock = GpClock::new(devmem, soc)?;
+            clock.start(gpio, self.pin, self.frequency)?;
+            Ok(Output::Clock(clock))
+        }
+    }
+}
+
+/// A started `Excitation`, the peripheral stays mapped until the output is stopped.
+pub enum Output {
+    Pwm(Pwm),
+    Clock(GpClock),
+}
+
+impl Output {
+    pub fn stop<G: GpioBackend>(&mut self, gpio: &mut G, pin: u8) -> Result<(), Error> {
+        match self {
+            Output::Pwm(pwm) => pwm.stop(gpio, pin),
+            Output::Clock(clock) => clock.stop(gpio, pin),
+        }
+    }
+}
+
+/// The general purpose clock and alternate function available on `pin`.
+pub fn clock_output(pin: u8) -> Option<(ClockOutput, Mode)> {
+    match pin {
+        4 => Some((ClockOutput::Gpclk0, Mode::Alt0)),
+        5 => Some((ClockOutput::Gpclk1, Mode::Alt0)),
+        6 => Some((ClockOutput::Gpclk2, Mode::Alt0)),
+        20 => Some((ClockOutput::Gpclk0, Mode::Alt5)),
+        21 => Some((ClockOutput::Gpclk1, Mode::Alt5)),
+        _ => None
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Gpio(gpio::Error),
+    NotCapable { pin: u8, function: &'static str },
+    Frequency(f64),
+    DutyCycle(f64),
+    Io(io::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Gpio(err) => write!(fmt, "{}", err),
+            Error::NotCapable { pin, function } => write!(fmt, "Pin ({}) can not output {}", pin, function),
+            Error::Frequency(freq) => write!(fmt, "Frequency {} Hz out of range", freq),
+            Error::DutyCycle(duty) => write!(fmt, "Duty cycle {} not within 0.0 to 1.0", duty),
+            Error::Io(err) => write!(fmt, "Pwm io error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+}
+
+impl From<gpio::Error> for Error {
+    fn from(err: gpio::Error) -> Self {
+        Error::Gpio(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+/// Physical address of the peripherals as seen by the ARM core, from the device tree if
+/// available.
+pub fn peripheral_base(soc: Soc) -> usize {
+    let ranges = std::fs::read(DEVICE_TREE_RANGES).unwrap_or_else(|_| Vec::new());
+    let cell = |i: usize| ranges
+        .get(i * 4..i * 4 + 4)
+        .map(|b| (b[0] as usize) << 24 | (b[1] as usize) << 16 | (b[2] as usize) << 8 | b[3] as usize);
+    // The parent address is one cell on BCM2835-7 and two cells, high first, on BCM2711.
+    match (cell(1), cell(2)) {
+        (Some(0), Some(base)) if base != 0 => base,
+        (Some(base), _) if base != 0 => base,
+        _ => match soc {
+            Soc::Bcm2835 => 0x3f00_0000,
+            Soc::Bcm2711 => 0xfe00_0000,
+        }
+    }
+}
+
+/// Frequencies of the oscillator and PLLD clock sources.
+fn clock_sources(soc: Soc) -> (f64, f64) {
+    match soc {
+        Soc::Bcm2835 => (19_200_000.0, 500_000_000.0),
+        Soc::Bcm2711 => (54_000_000.0, 750_000_000.0),
+    }
+}
+
+fn map_peripheral(devmem: &str, offset: usize, len: usize, soc: Soc) -> io::Result<MmapMut> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(devmem)?;
+    unsafe {
+        MmapOptions::new()
+            .offset((peripheral_base(soc) + offset) as u64)
+            .len(len)
+            .map_mut(&file)
+    }
+}
+
+fn stop_clock(ctl: &ReadWrite<u32, CM_CTL::Register>) {
+    ctl.modify(CM_CTL::PASSWD.val(CM_PASSWD) + CM_CTL::ENAB::CLEAR);
+    for _ in 0..100 {
+        if !ctl.is_set(CM_CTL::BUSY) {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    ctl.modify(CM_CTL::PASSWD.val(CM_PASSWD) + CM_CTL::KILL::SET);
+}
+
+/// Stops the clock, programs the divisor and starts it again. A divisor must not be changed
+/// while the clock is busy.
+fn start_clock(ctl: &ReadWrite<u32, CM_CTL::Register>, div: &ReadWrite<u32, CM_DIV::Register>, src: FieldValue<u32, CM_CTL::Register>, divi: u32, divf: u32) {
+    stop_clock(ctl);
+    let mash = if divf == 0 { CM_CTL::MASH::INTEGER } else { CM_CTL::MASH::STAGE1 };
+    div.write(CM_DIV::PASSWD.val(CM_PASSWD) + CM_DIV::DIVI.val(divi) + CM_DIV::DIVF.val(divf));
+    ctl.write(CM_CTL::PASSWD.val(CM_PASSWD) + mash + src);
+    ctl.write(CM_CTL::PASSWD.val(CM_PASSWD) + mash + src + CM_CTL::ENAB::SET);
+}
+
+/// The two hardware PWM channels. Both channels share one PWM clock, which is run at half
+/// the oscillator frequency, and set their frequency with the channel range.
+pub struct Pwm {
+    pwm: MmapMut,
+    cm: MmapMut,
+    soc: Soc,
+    clock_started: bool,
+}
+
+impl Pwm {
+    pub fn new(devmem: &str, soc: Soc) -> io::Result<Pwm> {
+        Ok(Pwm {
+            pwm: map_peripheral(devmem, registers::PWM_OFFSET, registers::PWM_MEM_SIZE, soc)?,
+            cm: map_peripheral(devmem, registers::CLOCK_MANAGER_OFFSET, registers::CLOCK_MANAGER_MEM_SIZE, soc)?,
+            soc,
+            clock_started: false,
+        })
+    }
+
+    fn clock_hz(&self) -> f64 {
+        clock_sources(self.soc).0 / 2.0
+    }
+
+    /// Outputs a `frequency` Hz square wave with `duty` in 0.0 to 1.0 on `pin`. Returns the
+    /// actual frequency, which is rounded to a whole number of PWM clock cycles.
+    pub fn start<G: GpioBackend>(&mut self, gpio: &mut G, pin: u8, frequency: f64, duty: f64) -> Result<f64, Error> {
+        let (channel, mode) = pwm_channel(pin).ok_or(Error::NotCapable { pin, function: "pwm" })?;
+        if !(0.0..=1.0).contains(&duty) {
+            return Err(Error::DutyCycle(duty));
+        }
+        let range = (self.clock_hz() / frequency).round();
+        if !(2.0..=u32::MAX as f64).contains(&range) {
+            return Err(Error::Frequency(frequency));
+        }
+        let range = range as u32;
+        let data = (range as f64 * duty).round() as u32;
+
+        if !self.clock_started {
+            let cm = self.cm.as_mut_ptr() as *mut registers::ClockManagerRegisters;
+            unsafe { start_clock(&(*cm).CM_PWMCTL, &(*cm).CM_PWMDIV, CM_CTL::SRC::OSCILLATOR, 2, 0) };
+            self.clock_started = true;
+        }
+
+        let regs = self.pwm.as_mut_ptr() as *mut registers::PwmRegisters;
+        unsafe {
+            match channel {
+                PwmChannel::Pwm0 => {
+                    (*regs).CTL.modify(CTL::PWEN1::CLEAR);
+                    (*regs).RNG1.set(range);
+                    (*regs).DAT1.set(data);
+                    (*regs).CTL.modify(CTL::MODE1::CLEAR + CTL::USEF1::CLEAR + CTL::MSEN1::SET + CTL::PWEN1::SET);
+                },
+                PwmChannel::Pwm1 => {
+                    (*regs).CTL.modify(CTL::PWEN2::CLEAR);
+                    (*regs).RNG2.set(range);
+                    (*regs).DAT2.set(data);
+                    (*regs).CTL.modify(CTL::MODE2::CLEAR + CTL::USEF2::CLEAR + CTL::MSEN2::SET + CTL::PWEN2::SET);
+                },
+            }
+        }
+        gpio.set_mode(pin, mode)?;
+        Ok(self.clock_hz() / range as f64)
+    }
+
+    /// Changes the duty cycle of a started channel without changing its frequency.
+    pub fn set_duty(&mut self, pin: u8, duty: f64) -> Result<(), Error> {
+        let (channel, _) = pwm_channel(pin).ok_or(Error::NotCapable { pin, function: "pwm" })?;
+        if !(0.0..=1.0).contains(&duty) {
+            return Err(Error::DutyCycle(duty));
+        }
+        let regs = self.pwm.as_mut_ptr() as *mut registers::PwmRegisters;
+        unsafe {
+            match channel {
+                PwmChannel::Pwm0 => (*regs).DAT1.set(((*regs).RNG1.get() as f64 * duty).round() as u32),
+                PwmChannel::Pwm1 => (*regs).DAT2.set(((*regs).RNG2.get() as f64 * duty).round() as u32),
+            }
+        }
+        Ok(())
+    }
+
+    /// Disables the channel of `pin` and returns the pin to input.
+    pub fn stop<G: GpioBackend>(&mut self, gpio: &mut G, pin: u8) -> Result<(), Error> {
+        let (channel, _) = pwm_channel(pin).ok_or(Error::NotCapable { pin, function: "pwm" })?;
+        gpio.set_mode(pin, Mode::Input)?;
+        let regs = self.pwm.as_mut_ptr() as *mut registers::PwmRegisters;
+        match channel {
+            PwmChannel::Pwm0 => unsafe { (*regs).CTL.modify(CTL::PWEN1::CLEAR) },
+            PwmChannel::Pwm1 => unsafe { (*regs).CTL.modify(CTL::PWEN2::CLEAR) },
+        }
+        Ok(())
+    }
+}
+
+/// The general purpose clocks, outputting a 50% duty cycle clock derived from PLLD.
+pub struct GpClock {
+    cm: MmapMut,
+    soc: Soc,
+}
+
+impl GpClock {
+    pub fn new(devmem: &str, soc: Soc) -> io::Result<GpClock> {
+        Ok(GpClock {
+            cm: map_peripheral(devmem, registers::CLOCK_MANAGER_OFFSET, registers::CLOCK_MANAGER_MEM_SIZE, soc)?,
+            soc,
+        })
+    }
+
+    fn registers(&mut self, output: ClockOutput) -> (&ReadWrite<u32, CM_CTL::Register>, &ReadWrite<u32, CM_DIV::Register>) {
+        let cm = self.cm.as_mut_ptr() as *mut registers::ClockManagerRegisters;
+        unsafe {
+            match output {
+                ClockOutput::Gpclk0 => (&(*cm).CM_GP0CTL, &(*cm).CM_GP0DIV),
+                ClockOutput::Gpclk1 => (&(*cm).CM_GP1CTL, &(*cm).CM_GP1DIV),
+                ClockOutput::Gpclk2 => (&(*cm).CM_GP2CTL, &(*cm).CM_GP2DIV),
+            }
+        }
+    }
+
+    /// Outputs a `frequency` Hz clock on `pin`. Fractional divisors are smoothed with MASH,
+    /// which gives the requested average frequency with some jitter. Returns the actual
+    /// average frequency.
+    pub fn start<G: GpioBackend>(&mut self, gpio: &mut G, pin: u8, frequency: f64) -> Result<f64, Error> {
+        let (output, mode) = clock_output(pin).ok_or(Error::NotCapable { pin, function: "gpclk" })?;
+        let plld = clock_sources(self.soc).1;
+        let divisor = plld / frequency;
+        if !(2.0..4096.0).contains(&divisor) {
+            return Err(Error::Frequency(frequency));
+        }
+        let divi = divisor.trunc() as u32;
+        let divf = ((divisor.fract() * 4096.0).round() as u32).min(4095);
+
+        let (ctl, div) = self.registers(output);
+        start_clock(ctl, div, CM_CTL::SRC::PLLD, divi, divf);
+        gpio.set_mode(pin, mode)?;
+        Ok(plld / (divi as f64 + divf as f64 / 4096.0))
+    }
+
+    /// Stops the clock of `pin` and returns the pin to input.
+    pub fn stop<G: GpioBackend>(&mut self, gpio: &mut G, pin: u8) -> Result<(), Error> {
+        let (output, _) = clock_output(pin).ok_or(Error::NotCapable { pin, function: "gpclk" })?;
+        gpio.set_mode(pin, Mode::Input)?;
+        let (ctl, _) = self.registers(output);
+        stop_clock(ctl);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::simulated::{Call, SimulatedGpio};
+
+    const CTL: usize = 0x00;
+    const RNG1: usize = 0x10;
+    const DAT1: usize = 0x14;
+    const RNG2: usize = 0x20;
+    const DAT2: usize = 0x24;
+    const CM_GP1CTL: usize = 0x78;
+    const CM_GP1DIV: usize = 0x7c;
+    const CM_PWMCTL: usize = 0xa0;
+    const CM_PWMDIV: usize = 0xa4;
+
+    fn memory(len: usize) -> MmapMut {
+        MmapOptions::new().len(len).map_anon().unwrap()
+    }
+
+    fn register(mmap: &MmapMut, offset: usize) -> u32 {
+        let mut word = [0; 4];
+        word.copy_from_slice(&mmap[offset..offset + 4]);
+        u32::from_ne_bytes(word)
+    }
+
+    /// A `Pwm` over plain memory, so registers read back what was last written to them.
+    fn memory_pwm(soc: Soc) -> Pwm {
+        Pwm {
+            pwm: memory(registers::PWM_MEM_SIZE),
+            cm: memory(registers::CLOCK_MANAGER_MEM_SIZE),
+            soc,
+            clock_started: false
+        }
+    }
+
+    #[test]
+    fn programs_pwm_channels() {
+        let mut gpio = SimulatedGpio::new();
+        let mut pwm = memory_pwm(Soc::Bcm2835);
+        // The PWM clock runs at 9.6 MHz, half the oscillator.
+        assert_eq!(pwm.start(&mut gpio, 18, 100_000.0, 0.25).unwrap(), 100_000.0);
+        assert_eq!(register(&pwm.cm, CM_PWMDIV), CM_PASSWD << 24 | 2 << 12);
+        assert_eq!(register(&pwm.cm, CM_PWMCTL), CM_PASSWD << 24 | 1 << 4 | 1);
+        assert_eq!((register(&pwm.pwm, RNG1), register(&pwm.pwm, DAT1)), (96, 24));
+        assert_eq!(register(&pwm.pwm, CTL), 1 << 7 | 1);
+
+        pwm.start(&mut gpio, 13, 1_000.0, 0.5).unwrap();
+        assert_eq!((register(&pwm.pwm, RNG2), register(&pwm.pwm, DAT2)), (9_600, 4_800));
+        assert_eq!(register(&pwm.pwm, CTL), 1 << 15 | 1 << 8 | 1 << 7 | 1);
+        pwm.set_duty(13, 0.75).unwrap();
+        assert_eq!(register(&pwm.pwm, DAT2), 7_200);
+
+        pwm.stop(&mut gpio, 18).unwrap();
+        assert_eq!(register(&pwm.pwm, CTL), 1 << 15 | 1 << 8 | 1 << 7);
+        assert_eq!(gpio.take_calls(), vec![
+            Call::SetMode(18, Mode::Alt5),
+            Call::SetMode(13, Mode::Alt0),
+            Call::SetMode(18, Mode::Input),
+        ]);
+    }
+
+    #[test]
+    fn refuses_pwm_out_of_range() {
+        let mut gpio = SimulatedGpio::new();
+        let mut pwm = memory_pwm(Soc::Bcm2711);
+        assert!(matches!(pwm.start(&mut gpio, 17, 1_000.0, 0.5), Err(Error::NotCapable { pin: 17, .. })));
+        assert!(matches!(pwm.start(&mut gpio, 18, 1_000.0, 1.5), Err(Error::DutyCycle(_))));
+        assert!(matches!(pwm.start(&mut gpio, 18, 20_000_000.0, 0.5), Err(Error::Frequency(_))));
+        assert!(gpio.calls().is_empty());
+    }
+
+    #[test]
+    fn programs_clock_divisors() {
+        let mut gpio = SimulatedGpio::new();
+        let mut clock = GpClock { cm: memory(registers::CLOCK_MANAGER_MEM_SIZE), soc: Soc::Bcm2835 };
+        // 500 MHz PLLD divided by 3.125, a fractional divisor smoothed with MASH.
+        assert_eq!(clock.start(&mut gpio, 5, 160_000_000.0).unwrap(), 160_000_000.0);
+        assert_eq!(register(&clock.cm, CM_GP1DIV), CM_PASSWD << 24 | 3 << 12 | 512);
+        assert_eq!(register(&clock.cm, CM_GP1CTL), CM_PASSWD << 24 | 1 << 9 | 1 << 4 | 6);
+        assert!(matches!(clock.start(&mut gpio, 5, 100.0), Err(Error::Frequency(_))));
+
+        clock.stop(&mut gpio, 5).unwrap();
+        assert_eq!(register(&clock.cm, CM_GP1CTL) & 1 << 4, 0);
+        assert_eq!(gpio.take_calls(), vec![Call::SetMode(5, Mode::Alt0), Call::SetMode(5, Mode::Input)]);
+    }
+}
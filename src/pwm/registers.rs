@@ -0,0 +1,122 @@
+/// Offsets from the peripheral base address.
+pub const PWM_OFFSET: usize = 0x20c000;
+pub const PWM_MEM_SIZE: usize = 40;
+pub const CLOCK_MANAGER_OFFSET: usize = 0x101000;
+pub const CLOCK_MANAGER_MEM_SIZE: usize = 168;
+
+/// Password that must be written along with every clock manager register write.
+pub const CM_PASSWD: u32 = 0x5a;
+
+use register::{mmio::ReadOnly, mmio::ReadWrite, register_bitfields};
+
+// Descriptions taken from
+// https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf
+// with clock manager errata from https://elinux.org/BCM2835_datasheet_errata
+register_bitfields! {
+    u32,
+
+    /// Reserved
+    RESERVED [ RESERVED 0 ],
+    /// PWM Control
+    CTL [
+        MSEN2 OFFSET(15) NUMBITS(1),
+        USEF2 OFFSET(13) NUMBITS(1),
+        POLA2 OFFSET(12) NUMBITS(1),
+        SBIT2 OFFSET(11) NUMBITS(1),
+        RPTL2 OFFSET(10) NUMBITS(1),
+        MODE2 OFFSET(9) NUMBITS(1),
+        PWEN2 OFFSET(8) NUMBITS(1),
+        MSEN1 OFFSET(7) NUMBITS(1),
+        CLRF1 OFFSET(6) NUMBITS(1),
+        USEF1 OFFSET(5) NUMBITS(1),
+        POLA1 OFFSET(4) NUMBITS(1),
+        SBIT1 OFFSET(3) NUMBITS(1),
+        RPTL1 OFFSET(2) NUMBITS(1),
+        MODE1 OFFSET(1) NUMBITS(1),
+        PWEN1 OFFSET(0) NUMBITS(1)
+    ],
+    /// PWM Status
+    STA [
+        STA2 OFFSET(10) NUMBITS(1),
+        STA1 OFFSET(9) NUMBITS(1),
+        BERR OFFSET(8) NUMBITS(1),
+        GAPO2 OFFSET(5) NUMBITS(1),
+        GAPO1 OFFSET(4) NUMBITS(1),
+        RERR1 OFFSET(3) NUMBITS(1),
+        WERR1 OFFSET(2) NUMBITS(1),
+        EMPT1 OFFSET(1) NUMBITS(1),
+        FULL1 OFFSET(0) NUMBITS(1)
+    ],
+    /// PWM DMA Configuration
+    DMAC [
+        ENAB OFFSET(31) NUMBITS(1),
+        PANIC OFFSET(8) NUMBITS(8),
+        DREQ OFFSET(0) NUMBITS(8)
+    ],
+    /// PWM Channel Range, period of the output in PWM clock cycles
+    RNG [ RNG OFFSET(0) NUMBITS(32) [] ],
+    /// PWM Channel Data, clock cycles the output is high in M/S mode
+    DAT [ DAT OFFSET(0) NUMBITS(32) [] ],
+    /// PWM FIFO Input
+    FIF [ FIF OFFSET(0) NUMBITS(32) [] ],
+    /// Clock Manager Control
+    CM_CTL [
+        PASSWD OFFSET(24) NUMBITS(8) [],
+        MASH OFFSET(9) NUMBITS(2) [
+            INTEGER = 0,
+            STAGE1 = 1,
+            STAGE2 = 2,
+            STAGE3 = 3
+        ],
+        FLIP OFFSET(8) NUMBITS(1) [],
+        BUSY OFFSET(7) NUMBITS(1) [],
+        KILL OFFSET(5) NUMBITS(1) [],
+        ENAB OFFSET(4) NUMBITS(1) [],
+        SRC OFFSET(0) NUMBITS(4) [
+            GND = 0,
+            OSCILLATOR = 1,
+            TESTDEBUG0 = 2,
+            TESTDEBUG1 = 3,
+            PLLA = 4,
+            PLLC = 5,
+            PLLD = 6,
+            HDMI = 7
+        ]
+    ],
+    /// Clock Manager Divisor
+    CM_DIV [
+        PASSWD OFFSET(24) NUMBITS(8),
+        DIVI OFFSET(12) NUMBITS(12),
+        DIVF OFFSET(0) NUMBITS(12)
+    ]
+}
+
+#[allow(non_snake_case)]
+#[repr(C)]
+pub struct PwmRegisters {
+    pub CTL: ReadWrite<u32, CTL::Register>, // 0x00
+    pub STA: ReadWrite<u32, STA::Register>, // 0x04
+    pub DMAC: ReadWrite<u32, DMAC::Register>, // 0x08
+    pub RESERVED00: ReadOnly<u32, RESERVED::Register>, // 0x0c
+    pub RNG1: ReadWrite<u32, RNG::Register>, // 0x10
+    pub DAT1: ReadWrite<u32, DAT::Register>, // 0x14
+    pub FIF1: ReadWrite<u32, FIF::Register>, // 0x18
+    pub RESERVED01: ReadOnly<u32, RESERVED::Register>, // 0x1c
+    pub RNG2: ReadWrite<u32, RNG::Register>, // 0x20
+    pub DAT2: ReadWrite<u32, DAT::Register>, // 0x24
+}
+
+#[allow(non_snake_case)]
+#[repr(C)]
+pub struct ClockManagerRegisters {
+    pub RESERVED00: [ReadOnly<u32, RESERVED::Register>; 28], // 0x00
+    pub CM_GP0CTL: ReadWrite<u32, CM_CTL::Register>, // 0x70
+    pub CM_GP0DIV: ReadWrite<u32, CM_DIV::Register>, // 0x74
+    pub CM_GP1CTL: ReadWrite<u32, CM_CTL::Register>, // 0x78
+    pub CM_GP1DIV: ReadWrite<u32, CM_DIV::Register>, // 0x7c
+    pub CM_GP2CTL: ReadWrite<u32, CM_CTL::Register>, // 0x80
+    pub CM_GP2DIV: ReadWrite<u32, CM_DIV::Register>, // 0x84
+    pub RESERVED01: [ReadOnly<u32, RESERVED::Register>; 6], // 0x88
+    pub CM_PWMCTL: ReadWrite<u32, CM_CTL::Register>, // 0xa0
+    pub CM_PWMDIV: ReadWrite<u32, CM_DIV::Register>, // 0xa4
+}
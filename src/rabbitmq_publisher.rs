@@ -11,8 +11,8 @@ pub fn run<F>(
         teardown: Shared<F>,
         addr: &str,
         exchange: &str,
-        sample_stream: Box<Stream<Item = Vec<u8>, Error = Error> + Send>
-    ) -> Box<Future<Item = (), Error = Error> + Send>
+        sample_stream: Box<dyn Stream<Item = Vec<u8>, Error = Error> + Send>
+    ) -> Box<dyn Future<Item = (), Error = Error> + Send>
         where F: Future<Item = Option<i32>, Error = std::io::Error> + Send + 'static {
     let exchange = exchange.to_string();
     let teardown = teardown.clone();
@@ -0,0 +1,136 @@
+///
+/// Samples read from sensors, a set of named channels each with a value, unit and status.
+///
+use std::time::SystemTime;
+use serde::{Serialize, Serializer};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+    /// Counts, levels and other raw readings.
+    Integer(i64),
+    /// Converted readings such as volts.
+    Float(f64),
+    Bool(bool),
+}
+
+impl Value {
+    /// The value as a number, true is 1.
+    pub fn as_f64(self) -> f64 {
+        match self {
+            Value::Integer(i) => i as f64,
+            Value::Float(f) => f,
+            Value::Bool(b) => if b { 1.0 } else { 0.0 },
+        }
+    }
+}
+
+impl From<u32> for Value {
+    fn from(value: u32) -> Self {
+        Value::Integer(i64::from(value))
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Integer(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Integer(i) => serializer.serialize_i64(*i),
+            Value::Float(f) => serializer.serialize_f64(*f),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+        }
+    }
+}
+
+/// How far a channel's value can be trusted.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Status {
+    Ok,
+    /// Read, but possibly wrong, eg. a saturated ADC or a partly failed measurement.
+    Suspect(String),
+    /// Not a meaningful reading, the value is only kept for diagnosis.
+    Invalid(String),
+}
+
+impl Status {
+    pub fn is_ok(&self) -> bool {
+        *self == Status::Ok
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Suspect(_) => "suspect",
+            Status::Invalid(_) => "invalid",
+        }
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            Status::Ok => None,
+            Status::Suspect(reason) | Status::Invalid(reason) => Some(reason),
+        }
+    }
+}
+
+/// One named value of a sample, sensors measuring several quantities give one each.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Channel {
+    pub name: String,
+    pub value: Value,
+    pub unit: Option<String>,
+    pub status: Status,
+}
+
+impl Channel {
+    pub fn new<V: Into<Value>>(name: &str, value: V) -> Self {
+        Channel { name: name.to_string(), value: value.into(), unit: None, status: Status::Ok }
+    }
+
+    /// The channel of a sensor measuring one quantity.
+    pub fn single<V: Into<Value>>(value: V) -> Vec<Channel> {
+        vec![Channel::new("value", value)]
+    }
+
+    pub fn with_unit(mut self, unit: &str) -> Self {
+        self.unit = Some(unit.to_string());
+        self
+    }
+
+    pub fn with_status(mut self, status: Status) -> Self {
+        self.status = status;
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub timestamp: SystemTime,
+    pub channels: Vec<Channel>,
+}
+
+impl Sample {
+    pub fn new(timestamp: SystemTime, channels: Vec<Channel>) -> Self {
+        Sample { timestamp, channels }
+    }
+
+    pub fn channel(&self, name: &str) -> Option<&Channel> {
+        self.channels.iter().find(|channel| channel.name == name)
+    }
+}
@@ -1,4 +1,6 @@
 use std::time::SystemTime;
+use serde_json::{Map, Value as JsonValue};
+use crate::sample::{Channel, Sample};
 
 pub struct SampleFormatter {
     sensor_id: String,
@@ -10,13 +12,132 @@ impl SampleFormatter {
         Self { sensor_id, sample_type }
     }
 
-    pub fn format(&self, (timestamp, sample): &(SystemTime, u32)) -> Vec<u8> {
+    /// A single channel is published as `value`, with `unit`, `status` and `reason` when set.
+    /// Several channels are published as objects by channel name, `values` and when any channel
+    /// sets them `units`, `status` and `reasons`. Channels with status ok are left out of
+    /// `status`, so a plain moisture reading keeps its original shape.
+    pub fn format(&self, sample: &Sample) -> Vec<u8> {
+        let mut json = json!({
+            "sensor_type": self.sample_type,
+            "sensor_id": self.sensor_id,
+            "timestamp": sample.timestamp.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis() as u64
+        });
+        match sample.channels.as_slice() {
+            [channel] => {
+                json["value"] = json!(channel.value);
+                if let Some(unit) = &channel.unit {
+                    json["unit"] = json!(unit);
+                }
+                if !channel.status.is_ok() {
+                    json["status"] = json!(channel.status.name());
+                    json["reason"] = json!(channel.status.reason());
+                }
+            },
+            channels => {
+                json["values"] = by_name(channels, |c| Some(json!(c.value)));
+                let units = by_name(channels, |c| c.unit.as_ref().map(|unit| json!(unit)));
+                let status = by_name(channels, |c| Some(json!(c.status.name())).filter(|_| !c.status.is_ok()));
+                let reasons = by_name(channels, |c| c.status.reason().map(|reason| json!(reason)));
+                for (key, map) in [("units", units), ("status", status), ("reasons", reasons)] {
+                    if map.as_object().is_some_and(|map| !map.is_empty()) {
+                        json[key] = map;
+                    }
+                }
+            }
+        }
+        serde_json::to_vec(&json).unwrap()
+    }
+
+    /// A sample that could not be read, published with status `invalid` and the error as
+    /// `reason`.
+    pub fn format_error<E: std::fmt::Display>(&self, timestamp: SystemTime, err: &E) -> Vec<u8> {
         let json = json!({
             "sensor_type": self.sample_type,
             "sensor_id": self.sensor_id,
             "timestamp": timestamp.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis() as u64,
-            "value": sample
+            "status": "invalid",
+            "reason": err.to_string()
         });
         serde_json::to_vec(&json).unwrap()
     }
 }
+
+fn by_name<F>(channels: &[Channel], f: F) -> JsonValue
+    where F: Fn(&Channel) -> Option<JsonValue> {
+    channels
+        .iter()
+        .filter_map(|channel| f(channel).map(|value| (channel.name.clone(), value)))
+        .collect::<Map<String, JsonValue>>()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::sample::Status;
+
+    fn formatted(json: Vec<u8>) -> JsonValue {
+        serde_json::from_slice(&json).unwrap()
+    }
+
+    #[test]
+    fn formats_single_channel() {
+        let formatter = SampleFormatter::new("adc1".to_string(), "mcp3008".to_string());
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_millis(1_546_300_800_000);
+        let channel = Channel::new("value", 1023u32).with_status(Status::Suspect("Saturated".to_string()));
+        assert_eq!(formatted(formatter.format(&Sample::new(timestamp, vec![channel]))), json!({
+            "sensor_type": "mcp3008",
+            "sensor_id": "adc1",
+            "timestamp": 1_546_300_800_000u64,
+            "value": 1023,
+            "status": "suspect",
+            "reason": "Saturated"
+        }));
+    }
+
+    #[test]
+    fn keeps_shape_of_plain_value() {
+        let formatter = SampleFormatter::new("moist1".to_string(), "moist_sensor".to_string());
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_millis(1_546_300_800_000);
+        assert_eq!(formatted(formatter.format(&Sample::new(timestamp, Channel::single(1u32)))), json!({
+            "sensor_type": "moist_sensor",
+            "sensor_id": "moist1",
+            "timestamp": 1_546_300_800_000u64,
+            "value": 1
+        }));
+    }
+
+    #[test]
+    fn formats_channels_by_name() {
+        let formatter = SampleFormatter::new("air1".to_string(), "dht22".to_string());
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_millis(1_546_300_800_000);
+        let channels = vec![
+            Channel::new("temperature", 21.5).with_unit("°C"),
+            Channel::new("humidity", 55.0).with_unit("%RH").with_status(Status::Invalid("Out of range".to_string())),
+            Channel::new("heater", false)
+        ];
+        assert_eq!(formatted(formatter.format(&Sample::new(timestamp, channels))), json!({
+            "sensor_type": "dht22",
+            "sensor_id": "air1",
+            "timestamp": 1_546_300_800_000u64,
+            "values": {"temperature": 21.5, "humidity": 55.0, "heater": false},
+            "units": {"temperature": "°C", "humidity": "%RH"},
+            "status": {"humidity": "invalid"},
+            "reasons": {"humidity": "Out of range"}
+        }));
+    }
+
+    #[test]
+    fn formats_error() {
+        let formatter = SampleFormatter::new("air1".to_string(), "dht22".to_string());
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_millis(1_546_300_800_000);
+        assert_eq!(formatted(formatter.format_error(timestamp, &"Dht22 sent 31 of 40 bits")), json!({
+            "sensor_type": "dht22",
+            "sensor_id": "air1",
+            "timestamp": 1_546_300_800_000u64,
+            "status": "invalid",
+            "reason": "Dht22 sent 31 of 40 bits"
+        }));
+    }
+}
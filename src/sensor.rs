@@ -1,8 +1,139 @@
-use crate::gpio::Gpio;
-use crate::gpio::Error;
+use std::time::Duration;
+use crate::capacitive_sensor;
+use crate::dht22_sensor;
+use crate::ds18b20_sensor;
+use crate::gpio::{self, GpioBackend};
+use crate::i2c;
+use crate::pwm;
+use crate::sample::Channel;
+use crate::spi;
 
-pub trait Sensor {
-    fn init(&self, gpio: &mut Gpio) -> Result<(), Error>;
-    fn clear(&self, gpio: &mut Gpio) -> Result<(), Error>;
-    fn read(&self, gpio: &mut Gpio) -> Result<u32, Error>;
+#[derive(Debug)]
+pub enum Error {
+    Gpio(gpio::Error),
+    Pwm(pwm::Error),
+    Spi(spi::Error),
+    I2c(i2c::Error),
+    Dht22(dht22_sensor::Error),
+    Capacitive(capacitive_sensor::Error),
+    Ds18b20(ds18b20_sensor::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Gpio(err) => write!(fmt, "{}", err),
+            Error::Pwm(err) => write!(fmt, "{}", err),
+            Error::Spi(err) => write!(fmt, "{}", err),
+            Error::I2c(err) => write!(fmt, "{}", err),
+            Error::Dht22(err) => write!(fmt, "{}", err),
+            Error::Capacitive(err) => write!(fmt, "{}", err),
+            Error::Ds18b20(err) => write!(fmt, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+}
+
+impl From<gpio::Error> for Error {
+    fn from(err: gpio::Error) -> Self {
+        Error::Gpio(err)
+    }
+}
+
+impl From<pwm::Error> for Error {
+    fn from(err: pwm::Error) -> Self {
+        Error::Pwm(err)
+    }
+}
+
+impl From<spi::Error> for Error {
+    fn from(err: spi::Error) -> Self {
+        Error::Spi(err)
+    }
+}
+
+impl From<i2c::Error> for Error {
+    fn from(err: i2c::Error) -> Self {
+        Error::I2c(err)
+    }
+}
+
+impl From<dht22_sensor::Error> for Error {
+    fn from(err: dht22_sensor::Error) -> Self {
+        Error::Dht22(err)
+    }
+}
+
+impl From<capacitive_sensor::Error> for Error {
+    fn from(err: capacitive_sensor::Error) -> Self {
+        Error::Capacitive(err)
+    }
+}
+
+impl From<ds18b20_sensor::Error> for Error {
+    fn from(err: ds18b20_sensor::Error) -> Self {
+        Error::Ds18b20(err)
+    }
+}
+
+pub trait Sensor<G: GpioBackend> {
+    fn init(&self, gpio: &mut G) -> Result<(), Error>;
+    fn clear(&self, gpio: &mut G) -> Result<(), Error>;
+    fn read(&self, gpio: &mut G) -> Result<Vec<Channel>, Error>;
+
+    /// Starts a read that takes time on the sensor's side, like an ADC conversion. The
+    /// sampler waits for the returned time, without holding the GPIO, before calling `read`.
+    /// Nothing to wait for by default.
+    fn prepare(&self, _gpio: &mut G) -> Result<Option<Duration>, Error> {
+        Ok(None)
+    }
+
+    /// Takes one of several measurements making up a sample, for sensors averaging them.
+    /// Called after `prepare` until nothing more is to be measured, the sampler waits for the
+    /// returned time without holding the GPIO between calls. Nothing to measure by default.
+    fn measure(&self, _gpio: &mut G) -> Result<Option<Duration>, Error> {
+        Ok(None)
+    }
+
+    /// How many more times the sampler attempts a failed read, none by default.
+    fn retries(&self) -> u32 {
+        0
+    }
+
+    /// Time the sampler waits before attempting a failed read again, without holding the GPIO.
+    fn retry_wait(&self) -> Duration {
+        Duration::from_millis(0)
+    }
+}
+
+impl<G: GpioBackend, S: Sensor<G> + ?Sized> Sensor<G> for Box<S> {
+    fn init(&self, gpio: &mut G) -> Result<(), Error> {
+        (**self).init(gpio)
+    }
+
+    fn clear(&self, gpio: &mut G) -> Result<(), Error> {
+        (**self).clear(gpio)
+    }
+
+    fn read(&self, gpio: &mut G) -> Result<Vec<Channel>, Error> {
+        (**self).read(gpio)
+    }
+
+    fn prepare(&self, gpio: &mut G) -> Result<Option<Duration>, Error> {
+        (**self).prepare(gpio)
+    }
+
+    fn measure(&self, gpio: &mut G) -> Result<Option<Duration>, Error> {
+        (**self).measure(gpio)
+    }
+
+    fn retries(&self) -> u32 {
+        (**self).retries()
+    }
+
+    fn retry_wait(&self) -> Duration {
+        (**self).retry_wait()
+    }
 }
@@ -1,5 +1,12 @@
+use std::time::Duration;
 use toml::Value;
 use failure::Error as FailureError;
+use crate::acquisition::{Acquisition, Reduction};
+use crate::calibration::{self, Calibration, Curve};
+use crate::i2c::{I2cBusConfig, I2cConfig};
+use crate::pwm::{self, Excitation};
+use crate::spi::{SpiBusConfig, SpiConfig, SpiMode};
+use crate::spi::bitbang::SpiPins;
 
 #[derive(Debug)]
 pub struct Error {
@@ -20,10 +27,24 @@ impl std::error::Error for Error {
 pub struct SensorConfig {
     pub id: String,
     pub sensor_type: String,
-    pub pwr: i64,
-    pub val: i64,
-    pub pwr_wait: u64,
-    pub interval: u64
+    pub pwr: Option<i64>,
+    pub val: Option<i64>,
+    pub pwr_wait: Option<u64>,
+    /// The power pin may be shared with other sensors declaring it shared.
+    pub pwr_shared: bool,
+    /// Square wave driven while the sensor is sampled, from the `excitation` table.
+    pub excitation: Option<Excitation>,
+    /// Bus for SPI attached sensors, from the `spi` table.
+    pub spi: Option<SpiConfig>,
+    /// Bus and address for I2C attached sensors, from the `i2c` table.
+    pub i2c: Option<I2cConfig>,
+    /// Curve applied to the readings, from the `calibration` table.
+    pub calibration: Option<Calibration>,
+    /// Reads per sample and how they are reduced, from the `acquisition` table.
+    pub acquisition: Acquisition,
+    pub interval: u64,
+    /// The whole sensor table, for options specific to the sensor type.
+    options: Value
 }
 
 #[derive(Debug)]
@@ -33,7 +54,7 @@ pub struct SensorsConfig {
 
 pub fn from_toml(toml_str: &str) -> Result<SensorsConfig, FailureError> {
     let value = toml_str.parse::<Value>()?;
-    Ok(SensorsConfig::from_toml(&value)?)
+    SensorsConfig::from_toml(&value)
 }
 
 impl SensorsConfig {
@@ -48,45 +69,297 @@ impl SensorsConfig {
                 SensorsConfig { sensors }
             })
     }
-
-    pub fn validate(&self) -> Result<(), FailureError> {
-        match self.sensors.as_slice() {
-            [sensor] => Ok(sensor.validate()?),
-            _ => Err(FailureError::from(Error { key: "sensors".to_string(), cause: "Only one sensor supported.".to_string() }))
-        }
-    }
 }
 
 impl SensorConfig {
     pub fn from_toml(id: &str, conf: &Value) -> Result<SensorConfig, FailureError> {
         let parent_key = &format!("sensors.{}", id);
         let sensor_type = get_key_as(conf, "sensor_type", |toml| { toml.as_str() }, parent_key, "string")?;
-        let pwr = get_key_as(conf, "pwr_pin", |toml| { toml.as_integer() }, parent_key, "integer")?;
-        let val = get_key_as(conf, "val_pin", |toml| { toml.as_integer() }, parent_key, "integer")?;
-        let pwr_wait = get_key_as(conf, "pwr_wait", |toml| { toml.as_integer().map(|i| i as u64) }, parent_key, "unsigned integer")?;
+        let pwr = get_optional_key_as(conf, "pwr_pin", |toml| { toml.as_integer() }, parent_key, "integer")?;
+        let val = get_optional_key_as(conf, "val_pin", |toml| { toml.as_integer() }, parent_key, "integer")?;
+        let pwr_wait = get_optional_key_as(conf, "pwr_wait", |toml| { toml.as_integer().map(|i| i as u64) }, parent_key, "unsigned integer")?;
         let interval = get_key_as(conf, "interval", |toml| { toml.as_integer().map(|i| i as u64) }, parent_key, "unsigned integer")?;
+        let pwr_shared = get_optional_key_as(conf, "pwr_shared", |toml| { toml.as_bool() }, parent_key, "boolean")?
+            .unwrap_or(false);
+        let excitation = match conf.get("excitation") {
+            Some(excitation) => Some(excitation_from_toml(excitation, &format!("{}.excitation", parent_key))?),
+            None => None
+        };
+        let spi = match conf.get("spi") {
+            Some(spi) => Some(spi_from_toml(spi, &format!("{}.spi", parent_key))?),
+            None => None
+        };
+        let i2c = match conf.get("i2c") {
+            Some(i2c) => Some(i2c_from_toml(i2c, &format!("{}.i2c", parent_key))?),
+            None => None
+        };
+        let acquisition = match conf.get("acquisition") {
+            Some(acquisition) => acquisition_from_toml(acquisition, &format!("{}.acquisition", parent_key))?,
+            None => Acquisition::default()
+        };
+        let calibration = match conf.get("calibration") {
+            Some(calibration) => Some(calibration_from_toml(calibration, &format!("{}.calibration", parent_key))?),
+            None => None
+        };
 
         Ok(SensorConfig {
             id: id.to_string(),
             sensor_type: sensor_type.to_string(),
-            pwr: pwr,
-            val: val,
+            pwr,
+            val,
             pwr_wait,
-            interval
+            pwr_shared,
+            excitation,
+            spi,
+            i2c,
+            calibration,
+            acquisition,
+            interval,
+            options: conf.clone()
         })
     }
 
+    /// Unwraps an optional setting the sensor type needs, `key` names it in the error.
+    pub fn require<T>(&self, value: Option<T>, key: &str) -> Result<T, FailureError> {
+        value.ok_or_else(|| self.invalid(key, "Was expected but not found".to_string()))
+    }
+
+    /// A type specific option, errors name the key within the sensor's table.
+    pub fn option<'a, F, V>(&'a self, key: &str, f: F, expected_type: &str) -> Result<V, FailureError>
+        where F: FnOnce(&'a Value) -> Option<V> {
+        get_key_as(&self.options, key, f, &format!("sensors.{}", self.id), expected_type)
+    }
+
+    pub fn optional<'a, F, V>(&'a self, key: &str, f: F, expected_type: &str) -> Result<Option<V>, FailureError>
+        where F: FnOnce(&'a Value) -> Option<V> {
+        get_optional_key_as(&self.options, key, f, &format!("sensors.{}", self.id), expected_type)
+    }
+
+    /// An option of a type the accessors above can't express, eg. a string or an array.
+    pub fn raw_option(&self, key: &str) -> Option<&Value> {
+        self.options.get(key)
+    }
+
+    /// Error for an option of the sensor's table with an unusable value.
+    pub fn invalid(&self, key: &str, cause: String) -> FailureError {
+        FailureError::from(Error {
+            key: format!("sensors.{}.{}", self.id, key),
+            cause
+        })
+    }
+
+    /// Unwraps a pin setting the sensor type needs and checks it is free for sensors.
+    pub fn require_pin(&self, value: Option<i64>, key: &str) -> Result<u8, FailureError> {
+        validate_pin(self.require(value, key)?, &format!("sensors.{}.{}", self.id, key))
+    }
+
     pub fn validate(&self) -> Result<(), FailureError> {
-        validate_pin(self.pwr)?;
-        validate_pin(self.val)?;
+        if self.pwr.is_some() {
+            self.require_pin(self.pwr, "pwr_pin")?;
+        }
+        if self.val.is_some() {
+            self.require_pin(self.val, "val_pin")?;
+        }
         Ok(())
     }
 }
 
-fn validate_pin(pin: i64) -> Result<(), FailureError> {
+/// `pin` is a PWM pin, 13, 18 or 19, or a GPCLK pin, 4, 5, 6, 20 or 21. `duty` defaults to
+/// 0.5, the only duty cycle a GPCLK pin outputs.
+fn excitation_from_toml(conf: &Value, parent_key: &str) -> Result<Excitation, FailureError> {
+    let invalid = |key: &str, cause: String| FailureError::from(Error {
+        key: format!("{}.{}", parent_key, key),
+        cause
+    });
+    let pin = get_key_as(conf, "pin", |toml| { toml.as_integer() }, parent_key, "integer")?;
+    let pin = validate_pin(pin, &format!("{}.pin", parent_key))?;
+    let pwm_pin = pwm::pwm_channel(pin).is_some();
+    if !pwm_pin && pwm::clock_output(pin).is_none() {
+        return Err(invalid("pin", format!("Pin {} has no PWM or GPCLK output, expected 4, 5, 6, 13, 18, 19, 20 or 21", pin)));
+    }
+    let frequency = get_key_as(conf, "frequency", |toml| { toml.as_float().or_else(|| toml.as_integer().map(|i| i as f64)) }, parent_key, "number")?;
+    if !frequency.is_finite() || frequency <= 0.0 {
+        return Err(invalid("frequency", format!("Not a valid frequency: {} Hz", frequency)));
+    }
+    let duty = get_optional_key_as(conf, "duty", |toml| { toml.as_float() }, parent_key, "float")?;
+    match duty {
+        Some(duty) if !(0.0..=1.0).contains(&duty) =>
+            return Err(invalid("duty", format!("Not a valid duty cycle: {}, expected 0.0 to 1.0", duty))),
+        Some(duty) if !pwm_pin && duty != 0.5 =>
+            return Err(invalid("duty", format!("GPCLK pin {} only outputs a duty cycle of 0.5", pin))),
+        _ => ()
+    }
+    Ok(Excitation { pin, frequency, duty: duty.unwrap_or(0.5) })
+}
+
+fn spi_from_toml(conf: &Value, parent_key: &str) -> Result<SpiConfig, FailureError> {
+    let bus_type = get_key_as(conf, "bus", |toml| { toml.as_str() }, parent_key, "string")?;
+    let pin = |key| {
+        let pin = get_key_as(conf, key, |toml| { toml.as_integer() }, parent_key, "integer")?;
+        validate_pin(pin, &format!("{}.{}", parent_key, key))
+    };
+    let bus = match bus_type {
+        "spidev" => SpiBusConfig::Spidev {
+            device: get_key_as(conf, "device", |toml| { toml.as_str() }, parent_key, "string")?.to_string()
+        },
+        "bitbang" => SpiBusConfig::BitBang(SpiPins {
+            clk: pin("clk_pin")?,
+            mosi: pin("mosi_pin")?,
+            miso: pin("miso_pin")?,
+            cs: pin("cs_pin")?
+        }),
+        "mock" => SpiBusConfig::Mock,
+        other => return Err(FailureError::from(Error {
+            key: format!("{}.bus", parent_key),
+            cause: format!("Unknown spi bus '{}', expected 'spidev', 'bitbang' or 'mock'", other)
+        }))
+    };
+    let mode = match get_optional_key_as(conf, "mode", |toml| { toml.as_integer() }, parent_key, "integer")? {
+        Some(n) => SpiMode::from_number(n as u8).filter(|_| (0..=3).contains(&n)).ok_or_else(|| Error {
+            key: format!("{}.mode", parent_key),
+            cause: format!("Not a valid spi mode: {}, expected 0 to 3", n)
+        })?,
+        None => SpiMode::Mode0
+    };
+    let clock_hz = match get_optional_key_as(conf, "clock_hz", |toml| { toml.as_integer() }, parent_key, "unsigned integer")? {
+        Some(hz) if (1..=i64::from(u32::MAX)).contains(&hz) => hz as u32,
+        Some(hz) => return Err(FailureError::from(Error {
+            key: format!("{}.clock_hz", parent_key),
+            cause: format!("Not a valid clock rate: {} Hz, expected 1 to {}", hz, u32::MAX)
+        })),
+        None => 1_000_000
+    };
+    Ok(SpiConfig { bus, mode, clock_hz })
+}
+
+/// `bus` is an adapter number, a device path or 'mock', and defaults to `/dev/i2c-1`,
+/// the bus on the Raspberry PI header. `address` is the 7 bit device address.
+fn i2c_from_toml(conf: &Value, parent_key: &str) -> Result<I2cConfig, FailureError> {
+    let bus = match conf.get("bus") {
+        Some(Value::Integer(n)) => I2cBusConfig::Dev { device: format!("/dev/i2c-{}", n) },
+        Some(Value::String(ref s)) if s == "mock" => I2cBusConfig::Mock,
+        Some(Value::String(s)) => I2cBusConfig::Dev { device: s.to_string() },
+        Some(v) => return Err(FailureError::from(Error {
+            key: format!("{}.bus", parent_key),
+            cause: format!("Is not valid type, expected 'integer or string' but found '{}'", v.type_str())
+        })),
+        None => I2cBusConfig::Dev { device: "/dev/i2c-1".to_string() }
+    };
+    match get_key_as(conf, "address", |toml| { toml.as_integer() }, parent_key, "integer")? {
+        address if (0x03..=0x77).contains(&address) => Ok(I2cConfig { bus, address: address as u8 }),
+        address => Err(FailureError::from(Error {
+            key: format!("{}.address", parent_key),
+            cause: format!("Not a valid 7 bit i2c address: {:#x}", address)
+        }))
+    }
+}
+
+/// `reduce` is 'mean', 'median', 'trimmed_mean' with the fraction `trim` left out at each end,
+/// or 'majority'.
+fn acquisition_from_toml(conf: &Value, parent_key: &str) -> Result<Acquisition, FailureError> {
+    let invalid = |key: &str, cause: String| FailureError::from(Error {
+        key: format!("{}.{}", parent_key, key),
+        cause
+    });
+    let samples = get_key_as(conf, "samples", |toml| { toml.as_integer() }, parent_key, "integer")?;
+    if samples < 1 {
+        return Err(invalid("samples", "Must be at least 1".to_string()));
+    }
+    let spacing = match get_optional_key_as(conf, "spacing", |toml| { toml.as_integer() }, parent_key, "unsigned integer")? {
+        Some(spacing) if spacing < 0 => return Err(invalid("spacing", format!("Must be at least 0, got {}", spacing))),
+        Some(spacing) => spacing as u64,
+        None => 0
+    };
+    let reduction = match get_optional_key_as(conf, "reduce", |toml| { toml.as_str() }, parent_key, "string")? {
+        None | Some("mean") => Reduction::Mean,
+        Some("median") => Reduction::Median,
+        Some("trimmed_mean") => {
+            let trim = get_optional_key_as(conf, "trim", |toml| { toml.as_float() }, parent_key, "float")?
+                .unwrap_or(0.1);
+            if !(0.0..0.5).contains(&trim) {
+                return Err(invalid("trim", format!("Not a valid fraction to trim: {}, expected 0 up to 0.5", trim)));
+            }
+            Reduction::TrimmedMean(trim)
+        },
+        Some("majority") => Reduction::Majority,
+        Some(other) => return Err(invalid(
+            "reduce",
+            format!("Unknown reduction '{}', expected 'mean', 'median', 'trimmed_mean' or 'majority'", other)
+        ))
+    };
+    Ok(Acquisition { samples: samples as u32, spacing: Duration::from_millis(spacing), reduction })
+}
+
+/// `type` is 'two_point' with `dry` and `wet` readings, 'piecewise' with `points` as
+/// `[raw, value]` pairs or 'polynomial' with `coefficients` in ascending order of power.
+fn calibration_from_toml(conf: &Value, parent_key: &str) -> Result<Calibration, FailureError> {
+    let invalid = |key: &str, cause: String| FailureError::from(Error {
+        key: format!("{}.{}", parent_key, key),
+        cause
+    });
+    // Rules out nan and inf, which the curves can not be built from.
+    let number = |value: &Value| value.as_float()
+        .or_else(|| value.as_integer().map(|i| i as f64))
+        .filter(|number| number.is_finite());
+    let curve_type = get_key_as(conf, "type", |toml| { toml.as_str() }, parent_key, "string")?;
+    let curve = match curve_type {
+        "two_point" => {
+            let dry = get_key_as(conf, "dry", number, parent_key, "finite number")?;
+            let wet = get_key_as(conf, "wet", number, parent_key, "finite number")?;
+            if dry == wet {
+                return Err(invalid("wet", "Must differ from the dry reading".to_string()));
+            }
+            Curve::TwoPoint {
+                dry,
+                wet,
+                dry_value: get_optional_key_as(conf, "dry_value", number, parent_key, "finite number")?.unwrap_or(0.0),
+                wet_value: get_optional_key_as(conf, "wet_value", number, parent_key, "finite number")?.unwrap_or(100.0)
+            }
+        },
+        "piecewise" => {
+            let mut points = get_key_as(conf, "points", |toml| { toml.as_array() }, parent_key, "array")?
+                .iter()
+                .map(|point| match point.as_array().map(Vec::as_slice) {
+                    Some([raw, value]) => number(raw).and_then(|raw| number(value).map(|value| (raw, value))),
+                    _ => None
+                })
+                .collect::<Option<Vec<(f64, f64)>>>()
+                .ok_or_else(|| invalid("points", "Is not valid type, expected 'array of [raw, value] pairs of finite numbers'".to_string()))?;
+            points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            if points.len() < 2 || points.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+                return Err(invalid("points", "Expected at least two points with distinct raw readings".to_string()));
+            }
+            Curve::Piecewise(points)
+        },
+        "polynomial" => {
+            let coefficients = get_key_as(conf, "coefficients", |toml| { toml.as_array() }, parent_key, "array")?
+                .iter()
+                .map(number)
+                .collect::<Option<Vec<f64>>>()
+                .ok_or_else(|| invalid("coefficients", "Is not valid type, expected 'array of finite numbers'".to_string()))?;
+            if coefficients.is_empty() {
+                return Err(invalid("coefficients", "Expected at least one coefficient".to_string()));
+            }
+            Curve::Polynomial(coefficients)
+        },
+        other => return Err(invalid(
+            "type",
+            format!("Unknown calibration '{}', expected 'two_point', 'piecewise' or 'polynomial'", other)
+        ))
+    };
+    let channel = get_optional_key_as(conf, "channel", |toml| { toml.as_str() }, parent_key, "string")?
+        .unwrap_or("value")
+        .to_string();
+    let unit = get_optional_key_as(conf, "unit", |toml| { toml.as_str() }, parent_key, "string")?
+        .unwrap_or(calibration::DEFAULT_UNIT)
+        .to_string();
+    Ok(Calibration { curve, channel, unit })
+}
+
+fn validate_pin(pin: i64, key: &str) -> Result<u8, FailureError> {
     match pin {
-        4 | 5 | 6 | 13 | 16 | 17 | 18 | 19 | 20 | 21 | 22 | 23 | 24 | 25 | 26 | 27 => Ok(()),
-        _ => Err(FailureError::from(Error { key: "pin".to_string(), cause: format!("Not a valid bcm pin: {}", pin).to_string() }))
+        4 | 5 | 6 | 13 | 16 | 17 | 18 | 19 | 20 | 21 | 22 | 23 | 24 | 25 | 26 | 27 => Ok(pin as u8),
+        _ => Err(FailureError::from(Error { key: key.to_string(), cause: format!("Not a valid bcm pin: {}", pin).to_string() }))
     }
 }
 
@@ -103,3 +376,187 @@ fn get_key_as<'a, F, V>(value: &'a Value, key: &str, f: F, scope: &str, expected
         }))
     }
 }
+
+fn get_optional_key_as<'a, F, V>(value: &'a Value, key: &str, f: F, scope: &str, expected_type: &str) -> Result<Option<V>, FailureError>
+    where F: FnOnce(&'a Value) -> Option<V> {
+    match value.get(key) {
+        Some(_) => get_key_as(value, key, f, scope, expected_type).map(Some),
+        None => Ok(None)
+    }
+}
+
+/// Configuration of a single sensor `s1` of `sensor_type`, with the settings in `extra`.
+#[cfg(test)]
+pub fn config_with(sensor_type: &str, extra: &str) -> Result<SensorConfig, FailureError> {
+    let conf = format!(
+        "[sensors.s1]\nsensor_type = '{}'\ninterval = 10\n{}",
+        sensor_type,
+        extra
+    );
+    Ok(from_toml(&conf)?.sensors.remove(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_excitation() {
+        let excitation = |extra| config_with("moist_sensor", extra).unwrap().excitation;
+        assert_eq!(excitation(""), None);
+        assert_eq!(
+            excitation("excitation = { pin = 18, frequency = 500000, duty = 0.25 }"),
+            Some(Excitation { pin: 18, frequency: 500_000.0, duty: 0.25 })
+        );
+        assert_eq!(
+            excitation("excitation = { pin = 4, frequency = 1e6 }"),
+            Some(Excitation { pin: 4, frequency: 1e6, duty: 0.5 })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_excitation() {
+        let error = |extra| config_with("moist_sensor", extra).unwrap_err().to_string();
+        assert!(error("excitation = { pin = 17, frequency = 1000 }").contains("sensors.s1.excitation.pin"));
+        assert!(error("excitation = { pin = -1, frequency = 1000 }").contains("sensors.s1.excitation.pin"));
+        // PWM capable, but not free for sensors.
+        assert!(error("excitation = { pin = 12, frequency = 1000 }").contains("sensors.s1.excitation.pin"));
+        assert!(error("excitation = { pin = 13, frequency = 0 }").contains("excitation.frequency"));
+        assert!(error("excitation = { pin = 13, frequency = 1000, duty = 1.5 }").contains("excitation.duty"));
+        assert!(error("excitation = { pin = 5, frequency = 1000, duty = 0.25 }").contains("excitation.duty"));
+    }
+
+    #[test]
+    fn parses_spi() {
+        let spi = |extra| config_with("moist_sensor", extra).unwrap().spi;
+        assert_eq!(spi(""), None);
+        assert_eq!(
+            spi("spi = { bus = 'bitbang', clk_pin = 21, mosi_pin = 20, miso_pin = 19, cs_pin = 16, mode = 3, clock_hz = 4000000000 }"),
+            Some(SpiConfig {
+                bus: SpiBusConfig::BitBang(SpiPins { clk: 21, mosi: 20, miso: 19, cs: 16 }),
+                mode: SpiMode::Mode3,
+                clock_hz: 4_000_000_000
+            })
+        );
+        assert_eq!(
+            spi("spi = { bus = 'spidev', device = '/dev/spidev0.0' }"),
+            Some(SpiConfig {
+                bus: SpiBusConfig::Spidev { device: "/dev/spidev0.0".to_string() },
+                mode: SpiMode::Mode0,
+                clock_hz: 1_000_000
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_spi() {
+        let error = |extra: &str| config_with("moist_sensor", extra).unwrap_err().to_string();
+        let bitbang = "bus = 'bitbang', mosi_pin = 20, miso_pin = 19, cs_pin = 16";
+        assert!(error(&format!("spi = {{ {}, clk_pin = 277 }}", bitbang)).contains("sensors.s1.spi.clk_pin"));
+        assert!(error(&format!("spi = {{ {}, clk_pin = 2 }}", bitbang)).contains("sensors.s1.spi.clk_pin"));
+        assert!(error("spi = { bus = 'i2c' }").contains("sensors.s1.spi.bus"));
+        assert!(error("spi = { bus = 'mock', mode = 260 }").contains("spi.mode"));
+        assert!(error("spi = { bus = 'mock', clock_hz = 0 }").contains("spi.clock_hz"));
+        assert!(error("spi = { bus = 'mock', clock_hz = 4294967296 }").contains("spi.clock_hz"));
+    }
+
+    #[test]
+    fn parses_i2c() {
+        let i2c = |extra| config_with("moist_sensor", extra).unwrap().i2c;
+        assert_eq!(i2c(""), None);
+        assert_eq!(
+            i2c("i2c = { address = 0x48 }"),
+            Some(I2cConfig { bus: I2cBusConfig::Dev { device: "/dev/i2c-1".to_string() }, address: 0x48 })
+        );
+        assert_eq!(
+            i2c("i2c = { bus = 0, address = 0x48 }"),
+            Some(I2cConfig { bus: I2cBusConfig::Dev { device: "/dev/i2c-0".to_string() }, address: 0x48 })
+        );
+        assert_eq!(
+            i2c("i2c = { bus = 'mock', address = 0x49 }"),
+            Some(I2cConfig { bus: I2cBusConfig::Mock, address: 0x49 })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_i2c() {
+        let error = |extra| config_with("moist_sensor", extra).unwrap_err().to_string();
+        assert!(error("i2c = { bus = 0 }").contains("sensors.s1.i2c.address"));
+        assert!(error("i2c = { address = 0x78 }").contains("sensors.s1.i2c.address"));
+        assert!(error("i2c = { bus = 1.5, address = 0x48 }").contains("sensors.s1.i2c.bus"));
+    }
+
+    #[test]
+    fn requires_pins_free_for_sensors() {
+        let config = config_with("moist_sensor", "pwr_pin = 12\nval_pin = 27").unwrap();
+        assert_eq!(config.require_pin(config.val, "val_pin").unwrap(), 27);
+        assert!(config.require_pin(config.pwr, "pwr_pin").unwrap_err().to_string().contains("sensors.s1.pwr_pin"));
+        assert!(config.require(config.pwr_wait, "pwr_wait").unwrap_err().to_string().contains("sensors.s1.pwr_wait"));
+    }
+
+
+    #[test]
+    fn parses_calibration() {
+        let calibration = |extra| config_with("mcp3008", extra).unwrap().calibration;
+        assert_eq!(calibration(""), None);
+        assert_eq!(
+            calibration("calibration = { type = 'two_point', dry = 820, wet = 410.5 }"),
+            Some(Calibration {
+                curve: Curve::TwoPoint { dry: 820.0, wet: 410.5, dry_value: 0.0, wet_value: 100.0 },
+                channel: "value".to_string(),
+                unit: "%VWC".to_string()
+            })
+        );
+        assert_eq!(
+            calibration("calibration = { type = 'piecewise', points = [[800, 0], [400, 45], [600, 20]], channel = 'soil', unit = 'kPa' }"),
+            Some(Calibration {
+                curve: Curve::Piecewise(vec![(400.0, 45.0), (600.0, 20.0), (800.0, 0.0)]),
+                channel: "soil".to_string(),
+                unit: "kPa".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_calibration() {
+        let error = |extra| config_with("mcp3008", extra).unwrap_err().to_string();
+        assert!(error("calibration = { type = 'cubic' }").contains("sensors.s1.calibration.type"));
+        assert!(error("calibration = { type = 'two_point', dry = 400, wet = 400 }").contains("calibration.wet"));
+        assert!(error("calibration = { type = 'piecewise', points = [[400, 45]] }").contains("calibration.points"));
+        assert!(error("calibration = { type = 'piecewise', points = [[400, 45], [400, 20]] }").contains("calibration.points"));
+        assert!(error("calibration = { type = 'polynomial', coefficients = [] }").contains("calibration.coefficients"));
+    }
+
+    #[test]
+    fn rejects_non_finite_calibration() {
+        let error = |extra| config_with("mcp3008", extra).unwrap_err().to_string();
+        assert!(error("calibration = { type = 'two_point', dry = nan, wet = 410 }").contains("calibration.dry"));
+        assert!(error("calibration = { type = 'piecewise', points = [[nan, 0.0], [1.0, 2.0], [3.0, 4.0]] }").contains("calibration.points"));
+        assert!(error("calibration = { type = 'polynomial', coefficients = [1.0, inf] }").contains("calibration.coefficients"));
+    }
+
+
+    #[test]
+    fn parses_acquisition() {
+        let acquisition = |extra| config_with("moist_sensor", extra).unwrap().acquisition;
+        assert_eq!(acquisition(""), Acquisition::default());
+        assert_eq!(
+            acquisition("acquisition = { samples = 9, spacing = 50, reduce = 'majority' }"),
+            Acquisition { samples: 9, spacing: Duration::from_millis(50), reduction: Reduction::Majority }
+        );
+        assert_eq!(
+            acquisition("acquisition = { samples = 10, reduce = 'trimmed_mean' }"),
+            Acquisition { samples: 10, spacing: Duration::from_millis(0), reduction: Reduction::TrimmedMean(0.1) }
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_acquisition() {
+        let error = |extra| config_with("moist_sensor", extra).unwrap_err().to_string();
+        assert!(error("acquisition = { spacing = 50 }").contains("sensors.s1.acquisition.samples"));
+        assert!(error("acquisition = { samples = 0 }").contains("sensors.s1.acquisition.samples"));
+        assert!(error("acquisition = { samples = 3, spacing = -1 }").contains("sensors.s1.acquisition.spacing"));
+        assert!(error("acquisition = { samples = 3, reduce = 'mode' }").contains("sensors.s1.acquisition.reduce"));
+        assert!(error("acquisition = { samples = 3, reduce = 'trimmed_mean', trim = 0.5 }").contains("sensors.s1.acquisition.trim"));
+    }
+}
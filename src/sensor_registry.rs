@@ -0,0 +1,169 @@
+///
+/// Maps `sensor_type` in the configuration to the factory building that kind of sensor.
+///
+/// Factories parse their own type specific keys from the `SensorConfig`, so a new sensor type
+/// only needs a factory and a `register` call here.
+///
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use failure::Error as FailureError;
+use crate::gpio::GpioBackend;
+use crate::gpio::claims::{ClaimedGpio, ClaimedPin, PinClaims};
+use crate::gpio::soc::Soc;
+use crate::excited_sensor::ExcitedSensor;
+use crate::sensor::Sensor;
+use crate::sensor_config::SensorConfig;
+use crate::sensor_setup::SampleStream;
+use crate::{
+    ads1115_sensor, capacitive_sensor, dht22_sensor, ds18b20_sensor, mcp3008_sensor, moist_sensor,
+    pulse_sensor
+};
+
+/// What factories share while setting up all sensors.
+pub struct SensorContext<G: GpioBackend> {
+    pub gpio: Arc<Mutex<G>>,
+    pub claims: PinClaims,
+    /// SoC whose PWM and clock registers drive excitation outputs.
+    pub soc: Soc,
+    background: RefCell<Vec<SampleStream>>,
+}
+
+impl<G: GpioBackend> SensorContext<ClaimedGpio<G>> {
+    /// Pins are claimed from the registry `gpio` checks changes against.
+    pub fn new(gpio: Arc<Mutex<ClaimedGpio<G>>>, soc: Soc) -> Self {
+        let claims = gpio.lock().unwrap().claims().clone();
+        SensorContext { gpio, claims, soc, background: RefCell::new(Vec::new()) }
+    }
+}
+
+impl<G: GpioBackend> SensorContext<G> {
+    /// Claims `pwr_pin`, shared with other sensors when `pwr_shared` is set.
+    pub fn claim_pwr_pin(&self, config: &SensorConfig) -> Result<ClaimedPin, FailureError> {
+        let pwr = config.require_pin(config.pwr, "pwr_pin")?;
+        Ok(match config.pwr_shared {
+            true => self.claims.claim_shared(pwr, &config.id)?,
+            false => self.claims.claim(pwr, &config.id)?
+        })
+    }
+
+    /// Claims `val_pin` for the sensor alone.
+    pub fn claim_val_pin(&self, config: &SensorConfig) -> Result<ClaimedPin, FailureError> {
+        let val = config.require_pin(config.val, "val_pin")?;
+        Ok(self.claims.claim(val, &config.id)?)
+    }
+
+    /// Publishes `stream` alongside the samples, for sensors working between samples. It is
+    /// first polled once all sensors are initialised.
+    pub fn run_alongside(&self, stream: SampleStream) {
+        self.background.borrow_mut().push(stream);
+    }
+
+    /// Streams passed to `run_alongside` so far.
+    pub fn take_background(&self) -> Vec<SampleStream> {
+        self.background.borrow_mut().drain(..).collect()
+    }
+}
+
+pub type SensorFactory<G> = fn(&SensorConfig, &SensorContext<G>) -> Result<Box<dyn Sensor<G> + Send>, FailureError>;
+
+pub struct SensorRegistry<G: GpioBackend> {
+    factories: BTreeMap<&'static str, SensorFactory<G>>,
+}
+
+impl<G: GpioBackend + Send + 'static> SensorRegistry<G> {
+    pub fn new() -> Self {
+        SensorRegistry { factories: BTreeMap::new() }
+    }
+
+    /// A registry with every sensor type of this crate.
+    pub fn builtin() -> Self {
+        let mut registry = Self::new();
+        registry.register("ads1115", ads1115_sensor::from_config);
+        registry.register("capacitive", capacitive_sensor::from_config);
+        registry.register("dht22", dht22_sensor::from_config);
+        registry.register("ds18b20", ds18b20_sensor::from_config);
+        registry.register("mcp3008", mcp3008_sensor::from_config);
+        registry.register("moist_sensor", moist_sensor::from_config);
+        registry.register("pulse_counter", pulse_sensor::from_config);
+        registry
+    }
+
+    /// Registers `factory` for `sensor_type`, replacing any factory registered before.
+    pub fn register(&mut self, sensor_type: &'static str, factory: SensorFactory<G>) {
+        self.factories.insert(sensor_type, factory);
+    }
+
+    pub fn sensor_types(&self) -> Vec<&'static str> {
+        self.factories.keys().cloned().collect()
+    }
+
+    pub fn create(&self, config: &SensorConfig, context: &SensorContext<G>)
+        -> Result<Box<dyn Sensor<G> + Send>, FailureError> {
+        let sensor = match self.factories.get(config.sensor_type.as_str()) {
+            Some(factory) => factory(config, context)?,
+            None => return Err(config.invalid("sensor_type", format!(
+                "Unknown sensor type '{}' for sensor '{}', expected one of {}",
+                config.sensor_type,
+                config.id,
+                self.sensor_types().join(", ")
+            )))
+        };
+        Ok(match config.excitation {
+            Some(excitation) => {
+                let pin = context.claims.claim(excitation.pin, &config.id)?;
+                Box::new(ExcitedSensor::new(sensor, excitation, pin, context.soc))
+            },
+            None => sensor
+        })
+    }
+}
+
+impl<G: GpioBackend + Send + 'static> Default for SensorRegistry<G> {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+/// A context on a simulated GPIO, for testing factories.
+#[cfg(test)]
+pub fn simulated_context() -> SensorContext<ClaimedGpio<crate::gpio::simulated::SimulatedGpio>> {
+    let gpio = ClaimedGpio::new(crate::gpio::simulated::SimulatedGpio::new());
+    SensorContext::new(Arc::new(Mutex::new(gpio)), Soc::Bcm2835)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::simulated::SimulatedGpio;
+    use crate::sensor_config::config_with;
+
+    #[test]
+    fn registers_every_sensor_type() {
+        assert_eq!(SensorRegistry::<ClaimedGpio<SimulatedGpio>>::builtin().sensor_types(), vec![
+            "ads1115", "capacitive", "dht22", "ds18b20", "mcp3008", "moist_sensor", "pulse_counter"
+        ]);
+    }
+
+    #[test]
+    fn names_sensor_of_unknown_type() {
+        let context = simulated_context();
+        let config = config_with("bme280", "").unwrap();
+        let error = SensorRegistry::builtin().create(&config, &context).err().unwrap().to_string();
+        assert!(error.contains("sensors.s1.sensor_type"), "{}", error);
+        assert!(error.contains("'bme280' for sensor 's1'"), "{}", error);
+        assert!(error.contains("expected one of ads1115, capacitive, dht22, ds18b20, mcp3008, moist_sensor, pulse_counter"), "{}", error);
+    }
+
+    #[test]
+    fn creates_registered_types() {
+        let context = simulated_context();
+        let mut registry = SensorRegistry::new();
+        registry.register("moist_sensor", moist_sensor::from_config);
+        let config = config_with("moist_sensor", "pwr_pin = 17\nval_pin = 27\npwr_wait = 1").unwrap();
+        let sensor = registry.create(&config, &context);
+        assert!(sensor.is_ok());
+        assert_eq!(context.claims.owners(27), vec!["s1"]);
+        assert!(registry.create(&config_with("dht22", "val_pin = 22").unwrap(), &context).is_err());
+    }
+}
@@ -1,47 +1,335 @@
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant, SystemTime};
-use futures::{Async, Poll};
+use futures::{Async, Future, Poll};
 use futures::stream::Stream;
-use tokio_timer::Interval;
-use crate::gpio::{Gpio, Error as GpioError};
-use crate::sensor::Sensor;
+use tokio_timer::{Delay, Interval};
+use crate::acquisition::Acquisition;
+use crate::gpio::GpioBackend;
+use crate::sample::{Channel, Sample};
+use crate::sensor::{Sensor, Error as SensorError};
 
-pub struct SensorSampler<S: Sensor> {
+enum State {
+    /// Waiting for the next interval.
+    Idle,
+    /// Waiting for the sensor to be ready to read, see `Sensor::prepare`. `retry` counts the
+    /// failed attempts at the current sample.
+    Preparing { retry: u32, delay: Delay },
+    /// Waiting between the measurements of a sample, see `Sensor::measure`.
+    Measuring { retry: u32, delay: Delay },
+    /// Waiting to attempt a failed sample again, see `Sensor::retries`.
+    Retrying { retry: u32, delay: Delay },
+    /// Waiting between the reads of a sample, see `Acquisition::spacing`.
+    Spacing { delay: Delay },
+}
+
+pub struct SensorSampler<S: Sensor<G>, G: GpioBackend> {
     sensor: S,
-    gpio: Arc<Mutex<Gpio>>,
+    gpio: Arc<Mutex<G>>,
+    acquisition: Acquisition,
+    /// Reads of the current sample so far.
+    reads: Vec<Result<Vec<Channel>, SensorError>>,
     timer: Interval,
+    state: State,
 }
 
-impl<S: Sensor> std::ops::Drop for SensorSampler<S> {
+impl<S: Sensor<G>, G: GpioBackend> std::ops::Drop for SensorSampler<S, G> {
     fn drop(&mut self) {
         let _ = self.sensor.clear(&mut self.gpio.lock().unwrap());
     }
 }
 
-impl<S: Sensor> SensorSampler<S> {
-    pub fn new(sensor: S, gpio: Arc<Mutex<Gpio>>, interval: u64) -> Self {
-        let timer = Interval::new(Instant::now(), Duration::from_secs(interval));
-        SensorSampler { sensor, gpio, timer }
+impl<S: Sensor<G>, G: GpioBackend> SensorSampler<S, G> {
+    /// Samples every `interval` seconds.
+    pub fn new(sensor: S, gpio: Arc<Mutex<G>>, interval: u64, acquisition: Acquisition) -> Self {
+        Self::with_interval(sensor, gpio, Duration::from_secs(interval), acquisition)
+    }
+
+    /// Samples right away and then every `interval`, which must not be zero.
+    pub fn with_interval(sensor: S, gpio: Arc<Mutex<G>>, interval: Duration, acquisition: Acquisition) -> Self {
+        let timer = Interval::new(Instant::now(), interval);
+        SensorSampler { sensor, gpio, acquisition, reads: Vec::new(), timer, state: State::Idle }
+    }
+
+    /// Starts attempt `retry` at a read, giving the sample unless there is something to wait for.
+    fn start(&mut self, retry: u32) -> Option<Result<Vec<Channel>, SensorError>> {
+        let prepared = self.sensor.prepare(&mut self.gpio.lock().unwrap());
+        match prepared {
+            Ok(Some(wait)) => {
+                self.state = State::Preparing { retry, delay: Delay::new(Instant::now() + wait) };
+                None
+            },
+            Ok(None) => self.measure(retry),
+            Err(err) => self.failed(retry, err)
+        }
+    }
+
+    fn measure(&mut self, retry: u32) -> Option<Result<Vec<Channel>, SensorError>> {
+        let measured = self.sensor.measure(&mut self.gpio.lock().unwrap());
+        match measured {
+            Ok(Some(wait)) => {
+                self.state = State::Measuring { retry, delay: Delay::new(Instant::now() + wait) };
+                None
+            },
+            Ok(None) => self.read(retry),
+            Err(err) => self.failed(retry, err)
+        }
+    }
+
+    fn read(&mut self, retry: u32) -> Option<Result<Vec<Channel>, SensorError>> {
+        let read = self.sensor.read(&mut self.gpio.lock().unwrap());
+        match read {
+            Ok(channels) => self.acquired(Ok(channels)),
+            Err(err) => self.failed(retry, err)
+        }
+    }
+
+    fn failed(&mut self, retry: u32, err: SensorError) -> Option<Result<Vec<Channel>, SensorError>> {
+        if retry < self.sensor.retries() {
+            let delay = Delay::new(Instant::now() + self.sensor.retry_wait());
+            self.state = State::Retrying { retry: retry + 1, delay };
+            return None;
+        }
+        self.acquired(Err(err))
+    }
+
+    /// Adds a read to the sample, giving the sample once all its reads are taken.
+    fn acquired(&mut self, read: Result<Vec<Channel>, SensorError>) -> Option<Result<Vec<Channel>, SensorError>> {
+        self.reads.push(read);
+        if self.reads.len() < self.acquisition.samples as usize {
+            self.state = State::Spacing { delay: Delay::new(Instant::now() + self.acquisition.spacing) };
+            return None;
+        }
+        self.state = State::Idle;
+        Some(self.acquisition.reduce(std::mem::take(&mut self.reads)))
     }
 }
 
-impl<S: Sensor> Stream for SensorSampler<S> {
-    type Item = (SystemTime, u32);
-    type Error = GpioError;
+/// A sample per interval. A failed sample is yielded as an error and sampling goes on at the
+/// next interval, the stream only ends with its timer.
+///
+/// The wait of a sensor preparing its read, the waits between its measurements, the wait
+/// before attempting a failed read again, and the spacing of the reads of an oversampled
+/// sample, are timers rather than sleeping. The GPIO
+/// is not locked meanwhile, so other sensors are sampled.
+impl<S: Sensor<G>, G: GpioBackend> Stream for SensorSampler<S, G> {
+    type Item = Sample;
+    type Error = SensorError;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        // TODO handler errors instead of unwrap
-        match self.timer.poll() {
-            Ok(Async::Ready(Some(_))) => {
-                let sample = { self.sensor.read(&mut self.gpio.lock().unwrap()).unwrap() };
-                Ok(Async::Ready(Some((SystemTime::now(), sample))))
-            },
-            Ok(Async::Ready(None)) => Ok(Async::Ready(None)),
-            Ok(Async::NotReady) => Ok(Async::NotReady),
-            Err(err) => {
-                println!("Error on timer in sampler {}", err);
-                Ok(Async::Ready(None))
+        loop {
+            let sample = match &mut self.state {
+                State::Idle => match self.timer.poll() {
+                    Ok(Async::Ready(Some(_))) => self.start(0),
+                    Ok(Async::Ready(None)) => return Ok(Async::Ready(None)),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(err) => {
+                        println!("Error on timer in sampler {}", err);
+                        return Ok(Async::Ready(None));
+                    }
+                },
+                State::Preparing { retry, delay }
+                | State::Measuring { retry, delay }
+                | State::Retrying { retry, delay } => match delay.poll() {
+                    Ok(Async::Ready(())) => {
+                        let retry = *retry;
+                        match self.state {
+                            State::Retrying { .. } => self.start(retry),
+                            _ => self.measure(retry)
+                        }
+                    },
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(err) => {
+                        println!("Error on timer in sampler {}", err);
+                        return Ok(Async::Ready(None));
+                    }
+                },
+                State::Spacing { delay } => match delay.poll() {
+                    Ok(Async::Ready(())) => self.start(0),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(err) => {
+                        println!("Error on timer in sampler {}", err);
+                        return Ok(Async::Ready(None));
+                    }
+                }
+            };
+            match sample {
+                Some(channels) => return Ok(Async::Ready(Some(Sample::new(SystemTime::now(), channels?)))),
+                None => continue
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acquisition::Reduction;
+    use crate::dht22_sensor::Error as Dht22Error;
+    use crate::gpio::simulated::SimulatedGpio;
+    use crate::sample::{Status, Value};
+
+    /// A sensor method called, and when.
+    type Call = (&'static str, Instant);
+    type Calls = Arc<Mutex<Vec<Call>>>;
+
+    /// Needs `wait` between prepare and read and reads 1, 2, 3 and so on, remembering when
+    /// each call was made. Reads listed in `failing` fail. Takes `measurements` measurements
+    /// `wait` apart before each read.
+    struct Converting {
+        calls: Calls,
+        wait: Option<Duration>,
+        failing: Vec<u32>,
+        retries: u32,
+        measurements: usize,
+    }
+
+    impl Converting {
+        fn new(wait: Option<Duration>, failing: Vec<u32>, retries: u32) -> Self {
+            Converting { calls: Arc::new(Mutex::new(Vec::new())), wait, failing, retries, measurements: 0 }
+        }
+    }
+
+    impl Sensor<SimulatedGpio> for Converting {
+        fn init(&self, _gpio: &mut SimulatedGpio) -> Result<(), SensorError> {
+            Ok(())
+        }
+
+        fn clear(&self, _gpio: &mut SimulatedGpio) -> Result<(), SensorError> {
+            Ok(())
+        }
+
+        fn read(&self, _gpio: &mut SimulatedGpio) -> Result<Vec<Channel>, SensorError> {
+            let mut calls = self.calls.lock().unwrap();
+            calls.push(("read", Instant::now()));
+            let reads = calls.iter().filter(|(call, _)| *call == "read").count() as u32;
+            match self.failing.contains(&reads) {
+                true => Err(SensorError::from(Dht22Error::Incomplete { bits: 0 })),
+                false => Ok(Channel::single(reads))
             }
         }
+
+        fn prepare(&self, _gpio: &mut SimulatedGpio) -> Result<Option<Duration>, SensorError> {
+            self.calls.lock().unwrap().push(("prepare", Instant::now()));
+            Ok(self.wait)
+        }
+
+        fn measure(&self, _gpio: &mut SimulatedGpio) -> Result<Option<Duration>, SensorError> {
+            let mut calls = self.calls.lock().unwrap();
+            let measured = calls.iter()
+                .rev()
+                .take_while(|(call, _)| *call != "read")
+                .filter(|(call, _)| *call == "measure")
+                .count();
+            if measured == self.measurements {
+                return Ok(None);
+            }
+            calls.push(("measure", Instant::now()));
+            Ok(self.wait)
+        }
+
+        fn retries(&self) -> u32 {
+            self.retries
+        }
+
+        fn retry_wait(&self) -> Duration {
+            Duration::from_millis(30)
+        }
+    }
+
+    fn first_sample(sensor: Converting) -> (Result<Vec<Channel>, SensorError>, Vec<Call>) {
+        first_acquired(sensor, Acquisition::default())
+    }
+
+    fn first_acquired(sensor: Converting, acquisition: Acquisition) -> (Result<Vec<Channel>, SensorError>, Vec<Call>) {
+        let calls = sensor.calls.clone();
+        let gpio = Arc::new(Mutex::new(SimulatedGpio::new()));
+        let sampler = SensorSampler::new(sensor, gpio, 1, acquisition);
+        let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+        let sample = runtime.block_on(sampler.into_future())
+            .map(|(sample, _)| sample.unwrap().channels)
+            .map_err(|(err, _)| err);
+        let calls = calls.lock().unwrap().clone();
+        (sample, calls)
+    }
+
+    fn names(calls: &[Call]) -> Vec<&'static str> {
+        calls.iter().map(|(call, _)| *call).collect()
+    }
+
+    #[test]
+    fn waits_for_prepared_read_with_timer() {
+        let (sample, calls) = first_sample(Converting::new(Some(Duration::from_millis(30)), vec![], 0));
+        assert_eq!(sample.unwrap(), Channel::single(1u32));
+        assert_eq!(names(&calls), vec!["prepare", "read"]);
+        assert!(calls[1].1 - calls[0].1 >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn reads_right_away_without_wait() {
+        let (sample, calls) = first_sample(Converting::new(None, vec![], 0));
+        assert_eq!(sample.unwrap(), Channel::single(1u32));
+        assert_eq!(names(&calls), vec!["prepare", "read"]);
+    }
+
+    #[test]
+    fn waits_between_measurements_with_timer() {
+        let sensor = Converting { measurements: 2, ..Converting::new(Some(Duration::from_millis(30)), vec![], 0) };
+        let (sample, calls) = first_sample(sensor);
+        assert_eq!(sample.unwrap(), Channel::single(1u32));
+        assert_eq!(names(&calls), vec!["prepare", "measure", "measure", "read"]);
+        for pair in calls.windows(2) {
+            assert!(pair[1].1 - pair[0].1 >= Duration::from_millis(30));
+        }
+    }
+
+    #[test]
+    fn retries_failed_reads_after_waiting() {
+        let (sample, calls) = first_sample(Converting::new(None, vec![1, 2], 2));
+        assert_eq!(sample.unwrap(), Channel::single(3u32));
+        assert_eq!(names(&calls), vec!["prepare", "read", "prepare", "read", "prepare", "read"]);
+        assert!(calls[2].1 - calls[1].1 >= Duration::from_millis(30));
+        assert!(calls[4].1 - calls[3].1 >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn fails_once_retries_are_used_up() {
+        let (sample, calls) = first_sample(Converting::new(None, vec![1, 2], 1));
+        match sample {
+            Err(SensorError::Dht22(Dht22Error::Incomplete { bits: 0 })) => (),
+            other => panic!("Expected the failed read, got {:?}", other)
+        }
+        assert_eq!(names(&calls).len(), 4);
+    }
+
+    #[test]
+    fn goes_on_sampling_after_failed_sample() {
+        let gpio = Arc::new(Mutex::new(SimulatedGpio::new()));
+        let sampler = SensorSampler::new(Converting::new(None, vec![1], 0), gpio, 1, Acquisition::default());
+        let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+        let samples = runtime.block_on(sampler.then(Ok::<_, ()>).take(2).collect()).unwrap();
+        assert!(samples[0].is_err());
+        assert_eq!(samples[1].as_ref().unwrap().channels, Channel::single(2u32));
+    }
+
+
+    #[test]
+    fn spaces_reads_with_timers() {
+        let acquisition = Acquisition { samples: 3, spacing: Duration::from_millis(30), reduction: Reduction::Median };
+        let (sample, calls) = first_acquired(Converting::new(Some(Duration::from_millis(10)), vec![], 0), acquisition);
+        assert_eq!(sample.unwrap(), vec![Channel::new("value", 2.0), Channel::new("spread", 1.0)]);
+        assert_eq!(names(&calls), vec!["prepare", "read", "prepare", "read", "prepare", "read"]);
+        assert!(calls[2].1 - calls[1].1 >= Duration::from_millis(30));
+        assert!(calls[4].1 - calls[3].1 >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn leaves_out_reads_failing_after_retries() {
+        let acquisition = Acquisition { samples: 3, spacing: Duration::from_millis(0), reduction: Reduction::Mean };
+        // The second read is retried once and the retry fails too.
+        let (sample, calls) = first_acquired(Converting::new(None, vec![2, 3], 1), acquisition);
+        let channels = sample.unwrap();
+        assert_eq!(names(&calls).len(), 8);
+        assert_eq!(channels[0].value, Value::Float(2.5));
+        assert_eq!(channels[0].status, Status::Suspect("1 of 3 reads failed".to_string()));
     }
 }
@@ -1,43 +1,63 @@
 use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 use failure::Error as FailureError;
 use futures::stream::{Stream};
-use crate::gpio::{Gpio};
-use crate::moist_sensor::MoistSensor;
+use crate::gpio::{GpioBackend};
+use crate::gpio::claims::ClaimedGpio;
+use crate::gpio::soc::Soc;
+use crate::sensor::Sensor;
 use crate::sensor_config::{SensorsConfig, SensorConfig};
+use crate::sensor_registry::{SensorContext, SensorRegistry};
 use crate::sensor_sampler::SensorSampler;
 use crate::sample_formatter::SampleFormatter;
 
-pub fn setup(config: &SensorsConfig, gpio: Arc<Mutex<Gpio>>)
-    -> Result<Box<Stream<Item = Vec<u8>, Error = FailureError> + Send>, FailureError> {
-    config.sensors
+/// Published samples of all sensors.
+pub type SampleStream = Box<dyn Stream<Item = Vec<u8>, Error = FailureError> + Send>;
+
+/// Pins are claimed from the registry `gpio` checks changes against.
+/// `soc` decides which PWM and clock registers drive excitation outputs.
+pub fn setup<G: GpioBackend + Send + 'static>(config: &SensorsConfig, gpio: Arc<Mutex<ClaimedGpio<G>>>, soc: Soc)
+    -> Result<SampleStream, FailureError> {
+    let registry = SensorRegistry::builtin();
+    let context = SensorContext::new(gpio, soc);
+    let samples = config.sensors
         .iter()
-        .fold(
-            Ok(Box::new(futures::stream::empty::<Vec<u8>, failure::Error>())),
-            |combined_stream, sc| {
-                match combined_stream {
-                    Ok(c) => match setup_one(sc, gpio.clone()) {
-                        Ok(s) => Ok(Box::new(c.select(s))),
-                        e => e
-                    },
-                    e => e
-                }
+        .try_fold(
+            Box::new(futures::stream::empty()) as SampleStream,
+            |combined_stream, sc| -> Result<SampleStream, FailureError> {
+                Ok(Box::new(combined_stream.select(setup_one(sc, &registry, &context)?)))
             }
-        )
+        )?;
+    // Work between samples, such as counting pulses, starts once every sensor is initialised.
+    Ok(context.take_background()
+        .into_iter()
+        .fold(samples, |combined_stream, background| Box::new(combined_stream.select(background))))
 }
 
-fn setup_one(config: &SensorConfig, gpio: Arc<Mutex<Gpio>>)
-    -> Result<Box<Stream<Item = Vec<u8>, Error = FailureError> + Send>, FailureError> {
-    let sensor = MoistSensor::new(config.pwr as u8, config.val as u8, config.pwr_wait);
-    sensor.init(&mut gpio.lock().unwrap()).unwrap();
+fn setup_one<G: GpioBackend + Send + 'static>(config: &SensorConfig, registry: &SensorRegistry<G>, context: &SensorContext<G>)
+    -> Result<SampleStream, FailureError> {
+    let sensor = registry.create(config, context)?;
+    sensor.init(&mut *context.gpio.lock().unwrap())?;
     let formatter = SampleFormatter::new(config.id.clone(), config.sensor_type.clone());
     let sampler = SensorSampler::new(
         sensor,
-        gpio,
-        config.interval
+        context.gpio.clone(),
+        config.interval,
+        config.acquisition
     );
+    let calibration = config.calibration.clone();
 
+    // A failed sample is published as such instead of ending the stream.
     Ok(Box::new(sampler
-        .map(move |sample| formatter.format(&sample))
-        .map_err(failure::Error::from)
+        .map(move |sample| match &calibration {
+            Some(calibration) => calibration.apply(sample),
+            None => sample
+        })
+        .then(move |sample| -> Result<Vec<u8>, FailureError> {
+            Ok(match sample {
+                Ok(sample) => formatter.format(&sample),
+                Err(err) => formatter.format_error(SystemTime::now(), &err)
+            })
+        })
     ))
 }
@@ -0,0 +1,150 @@
+///
+/// SPI master support for ADCs and other SPI peripherals.
+///
+use std::io;
+use crate::gpio::{self, GpioBackend};
+use self::bitbang::{BitBangSpi, SpiPins};
+use self::mock::MockSpi;
+use self::spidev::Spidev;
+
+pub mod bitbang;
+pub mod mock;
+pub mod spidev;
+
+/// Clock polarity and phase, see https://en.wikipedia.org/wiki/Serial_Peripheral_Interface.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpiMode {
+    /// Clock idles low, data sampled on rising edge.
+    Mode0,
+    /// Clock idles low, data sampled on falling edge.
+    Mode1,
+    /// Clock idles high, data sampled on falling edge.
+    Mode2,
+    /// Clock idles high, data sampled on rising edge.
+    Mode3,
+}
+
+impl SpiMode {
+    pub fn from_number(mode: u8) -> Option<SpiMode> {
+        match mode {
+            0 => Some(SpiMode::Mode0),
+            1 => Some(SpiMode::Mode1),
+            2 => Some(SpiMode::Mode2),
+            3 => Some(SpiMode::Mode3),
+            _ => None
+        }
+    }
+
+    /// Clock polarity, true if the clock idles high.
+    pub fn cpol(self) -> bool {
+        self == SpiMode::Mode2 || self == SpiMode::Mode3
+    }
+
+    /// Clock phase, true if data is sampled on the trailing clock edge.
+    pub fn cpha(self) -> bool {
+        self == SpiMode::Mode1 || self == SpiMode::Mode3
+    }
+}
+
+/// Which bus a sensor talks through, set per sensor in the configuration.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SpiBusConfig {
+    /// Hardware SPI through a spidev device such as `/dev/spidev0.0`.
+    Spidev { device: String },
+    BitBang(SpiPins),
+    /// Answers every transfer with zeros, for running without hardware.
+    Mock,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpiConfig {
+    pub bus: SpiBusConfig,
+    pub mode: SpiMode,
+    pub clock_hz: u32,
+}
+
+/// A full duplex SPI bus. Sensors use this so hardware and bit-banged SPI are interchangeable.
+/// Buses not driven through GPIO ignore the `gpio` argument.
+pub trait SpiBus<G: GpioBackend> {
+    fn init(&self, _gpio: &mut G) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn clear(&self, _gpio: &mut G) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Sends `tx` while receiving into `rx`, both of the same length, with chip select held.
+    fn transfer(&self, gpio: &mut G, tx: &[u8], rx: &mut [u8]) -> Result<(), Error>;
+}
+
+impl<G: GpioBackend> SpiBus<G> for BitBangSpi {
+    fn init(&self, gpio: &mut G) -> Result<(), Error> {
+        BitBangSpi::init(self, gpio)
+    }
+
+    fn clear(&self, gpio: &mut G) -> Result<(), Error> {
+        BitBangSpi::clear(self, gpio)
+    }
+
+    fn transfer(&self, gpio: &mut G, tx: &[u8], rx: &mut [u8]) -> Result<(), Error> {
+        BitBangSpi::transfer(self, gpio, tx, rx)
+    }
+}
+
+impl<G: GpioBackend> SpiBus<G> for Spidev {
+    fn transfer(&self, _gpio: &mut G, tx: &[u8], rx: &mut [u8]) -> Result<(), Error> {
+        Spidev::transfer(self, tx, rx)
+    }
+}
+
+impl<G: GpioBackend> SpiBus<G> for MockSpi {
+    fn transfer(&self, _gpio: &mut G, tx: &[u8], rx: &mut [u8]) -> Result<(), Error> {
+        MockSpi::transfer(self, tx, rx)
+    }
+}
+
+/// Opens the bus described by `config`. Bit-banged pins are configured by `SpiBus::init`.
+pub fn open<G: GpioBackend>(config: &SpiConfig) -> Result<Box<dyn SpiBus<G> + Send>, Error> {
+    Ok(match &config.bus {
+        SpiBusConfig::Spidev { device } =>
+            Box::new(Spidev::open(device, config.mode, config.clock_hz)?),
+        SpiBusConfig::BitBang(pins) =>
+            Box::new(BitBangSpi::new(*pins, config.mode, config.clock_hz)),
+        SpiBusConfig::Mock => Box::new(MockSpi::new()),
+    })
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Gpio(gpio::Error),
+    /// Transmit and receive buffers of a full duplex transfer differ in length.
+    BufferLength { tx: usize, rx: usize },
+    Io(io::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Gpio(err) => write!(fmt, "{}", err),
+            Error::BufferLength { tx, rx } =>
+                write!(fmt, "Spi transfer of {} bytes with {} byte receive buffer", tx, rx),
+            Error::Io(err) => write!(fmt, "Spi device error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+}
+
+impl From<gpio::Error> for Error {
+    fn from(err: gpio::Error) -> Self {
+        Error::Gpio(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
@@ -0,0 +1,174 @@
+///
+/// SPI master implemented by toggling GPIO pins.
+///
+/// Bits are sent most significant first. Half clock periods are busy waited, so the clock
+/// rate is an upper bound, actual rates are limited by the GPIO backend.
+///
+use std::time::{Duration, Instant};
+use crate::gpio::{GpioBackend, Level, Mode};
+use super::{Error, SpiMode};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpiPins {
+    pub clk: u8,
+    pub mosi: u8,
+    pub miso: u8,
+    /// Chip select, active low.
+    pub cs: u8,
+}
+
+pub struct BitBangSpi {
+    pins: SpiPins,
+    mode: SpiMode,
+    half_period: Duration,
+}
+
+fn wait(duration: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < duration {}
+}
+
+impl BitBangSpi {
+    pub fn new(pins: SpiPins, mode: SpiMode, clock_hz: u32) -> Self {
+        let half_period = Duration::from_nanos(500_000_000 / u64::from(clock_hz.max(1)));
+        BitBangSpi { pins, mode, half_period }
+    }
+
+    pub fn pins(&self) -> SpiPins {
+        self.pins
+    }
+
+    /// Sets up the pins with the clock idle and the chip deselected.
+    pub fn init<G: GpioBackend>(&self, gpio: &mut G) -> Result<(), Error> {
+        self.write_clk(gpio, self.mode.cpol())?;
+        gpio.set(self.pins.cs)?;
+        gpio.clear(self.pins.mosi)?;
+        gpio.set_mode(self.pins.clk, Mode::Output)?;
+        gpio.set_mode(self.pins.cs, Mode::Output)?;
+        gpio.set_mode(self.pins.mosi, Mode::Output)?;
+        gpio.set_mode(self.pins.miso, Mode::Input)?;
+        Ok(())
+    }
+
+    /// Returns all pins to inputs.
+    pub fn clear<G: GpioBackend>(&self, gpio: &mut G) -> Result<(), Error> {
+        for pin in &[self.pins.cs, self.pins.clk, self.pins.mosi, self.pins.miso] {
+            gpio.set_mode(*pin, Mode::Input)?;
+        }
+        Ok(())
+    }
+
+    /// Full duplex transfer with the chip selected for the whole transfer. `rx` must be as
+    /// long as `tx`.
+    pub fn transfer<G: GpioBackend>(&self, gpio: &mut G, tx: &[u8], rx: &mut [u8]) -> Result<(), Error> {
+        if tx.len() != rx.len() {
+            return Err(Error::BufferLength { tx: tx.len(), rx: rx.len() });
+        }
+        let idle = self.mode.cpol();
+        gpio.clear(self.pins.cs)?;
+        wait(self.half_period);
+        let result = tx.iter().zip(rx.iter_mut()).try_for_each(|(out, inp)| {
+            *inp = self.transfer_byte(gpio, *out, idle)?;
+            Ok(())
+        });
+        gpio.set(self.pins.cs)?;
+        result
+    }
+
+    fn transfer_byte<G: GpioBackend>(&self, gpio: &mut G, out: u8, idle: bool) -> Result<u8, Error> {
+        let mut inp = 0;
+        for bit in (0..8).rev() {
+            let mosi = out & (1 << bit) != 0;
+            let miso = if self.mode.cpha() {
+                self.write_clk(gpio, !idle)?;
+                self.write_mosi(gpio, mosi)?;
+                wait(self.half_period);
+                self.write_clk(gpio, idle)?;
+                let miso = gpio.read(self.pins.miso)?;
+                wait(self.half_period);
+                miso
+            } else {
+                self.write_mosi(gpio, mosi)?;
+                wait(self.half_period);
+                self.write_clk(gpio, !idle)?;
+                let miso = gpio.read(self.pins.miso)?;
+                wait(self.half_period);
+                self.write_clk(gpio, idle)?;
+                miso
+            };
+            if miso == Level::High {
+                inp |= 1 << bit;
+            }
+        }
+        Ok(inp)
+    }
+
+    fn write_clk<G: GpioBackend>(&self, gpio: &mut G, high: bool) -> Result<(), Error> {
+        match high {
+            true => Ok(gpio.set(self.pins.clk)?),
+            false => Ok(gpio.clear(self.pins.clk)?)
+        }
+    }
+
+    fn write_mosi<G: GpioBackend>(&self, gpio: &mut G, high: bool) -> Result<(), Error> {
+        match high {
+            true => Ok(gpio.set(self.pins.mosi)?),
+            false => Ok(gpio.clear(self.pins.mosi)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::simulated::{Call, SimulatedGpio};
+
+    const PINS: SpiPins = SpiPins { clk: 11, mosi: 10, miso: 9, cs: 8 };
+
+    #[test]
+    fn loopback_in_every_mode() {
+        let tx = [0x00, 0xff, 0xa5, 0x3c, 0x81];
+        for mode in &[SpiMode::Mode0, SpiMode::Mode1, SpiMode::Mode2, SpiMode::Mode3] {
+            let mut gpio = SimulatedGpio::new();
+            gpio.connect(PINS.miso, PINS.mosi).unwrap();
+            let spi = BitBangSpi::new(PINS, *mode, 100_000_000);
+            spi.init(&mut gpio).unwrap();
+            let mut rx = [0; 5];
+            spi.transfer(&mut gpio, &tx, &mut rx).unwrap();
+            assert_eq!(rx, tx, "{:?}", mode);
+            let idle = if mode.cpol() { Level::High } else { Level::Low };
+            assert_eq!(gpio.read(PINS.clk).unwrap(), idle, "{:?}", mode);
+            assert_eq!(gpio.read(PINS.cs).unwrap(), Level::High, "{:?}", mode);
+        }
+    }
+
+    /// Calls for the first bit of a transfer, after the chip is selected.
+    fn first_bit(mode: SpiMode) -> Vec<Call> {
+        let mut gpio = SimulatedGpio::new();
+        let spi = BitBangSpi::new(PINS, mode, 100_000_000);
+        spi.init(&mut gpio).unwrap();
+        gpio.take_calls();
+        spi.transfer(&mut gpio, &[0x80], &mut [0]).unwrap();
+        gpio.take_calls().into_iter().skip(1).take(3).collect()
+    }
+
+    #[test]
+    fn sets_data_for_the_sampling_edge() {
+        // Data is set before the leading edge in mode 0 and 2, on it in mode 1 and 3.
+        assert_eq!(first_bit(SpiMode::Mode0), vec![Call::Set(10), Call::Set(11), Call::Clear(11)]);
+        assert_eq!(first_bit(SpiMode::Mode1), vec![Call::Set(11), Call::Set(10), Call::Clear(11)]);
+        assert_eq!(first_bit(SpiMode::Mode2), vec![Call::Set(10), Call::Clear(11), Call::Set(11)]);
+        assert_eq!(first_bit(SpiMode::Mode3), vec![Call::Clear(11), Call::Set(10), Call::Set(11)]);
+    }
+
+    #[test]
+    fn refuses_unequal_buffers() {
+        let mut gpio = SimulatedGpio::new();
+        let spi = BitBangSpi::new(PINS, SpiMode::Mode0, 100_000_000);
+        let mut rx = [0; 1];
+        assert!(matches!(
+            spi.transfer(&mut gpio, &[1, 2], &mut rx),
+            Err(Error::BufferLength { tx: 2, rx: 1 })
+        ));
+    }
+}
@@ -0,0 +1,85 @@
+///
+/// SPI bus for tests and simulation. Transfers are recorded and answered by a responder.
+///
+use std::sync::Mutex;
+use super::Error;
+
+type Responder = Box<dyn Fn(&[u8]) -> Vec<u8> + Send + Sync>;
+
+pub struct MockSpi {
+    responder: Responder,
+    transfers: Mutex<Vec<Vec<u8>>>,
+}
+
+impl MockSpi {
+    /// A bus answering every transfer with zeros.
+    pub fn new() -> Self {
+        MockSpi::with_responder(|tx| vec![0; tx.len()])
+    }
+
+    /// A bus answering each transfer with what `responder` returns for the sent bytes.
+    /// Missing bytes are received as zeros.
+    pub fn with_responder<F>(responder: F) -> Self
+        where F: Fn(&[u8]) -> Vec<u8> + Send + Sync + 'static {
+        MockSpi { responder: Box::new(responder), transfers: Mutex::new(Vec::new()) }
+    }
+
+    /// Bytes sent by all transfers so far, oldest first.
+    pub fn transfers(&self) -> Vec<Vec<u8>> {
+        self.transfers.lock().unwrap().clone()
+    }
+
+    pub fn transfer(&self, tx: &[u8], rx: &mut [u8]) -> Result<(), Error> {
+        if tx.len() != rx.len() {
+            return Err(Error::BufferLength { tx: tx.len(), rx: rx.len() });
+        }
+        self.transfers.lock().unwrap().push(tx.to_vec());
+        let response = (self.responder)(tx);
+        for (i, byte) in rx.iter_mut().enumerate() {
+            *byte = response.get(i).cloned().unwrap_or(0);
+        }
+        Ok(())
+    }
+}
+
+impl Default for MockSpi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_with_zeros_by_default() {
+        let spi = MockSpi::new();
+        let mut rx = [0xff; 3];
+        spi.transfer(&[1, 2, 3], &mut rx).unwrap();
+        assert_eq!(rx, [0, 0, 0]);
+    }
+
+    #[test]
+    fn records_transfers_and_answers_with_responder() {
+        let spi = MockSpi::with_responder(|tx| tx.iter().rev().cloned().take(2).collect());
+        let mut rx = [0xff; 3];
+        spi.transfer(&[1, 2, 3], &mut rx).unwrap();
+        // Missing bytes of the response are received as zeros.
+        assert_eq!(rx, [3, 2, 0]);
+        let mut rx = [0; 1];
+        spi.transfer(&[9], &mut rx).unwrap();
+        assert_eq!(rx, [9]);
+        assert_eq!(spi.transfers(), vec![vec![1, 2, 3], vec![9]]);
+    }
+
+    #[test]
+    fn refuses_unequal_buffers() {
+        let spi = MockSpi::new();
+        match spi.transfer(&[1, 2], &mut [0; 3]) {
+            Err(Error::BufferLength { tx: 2, rx: 3 }) => (),
+            other => panic!("Expected a buffer length error, got {:?}", other)
+        }
+        assert!(spi.transfers().is_empty());
+    }
+}
@@ -0,0 +1,113 @@
+///
+/// Hardware SPI through the Linux spidev driver (`/dev/spidevB.C`).
+/// https://www.kernel.org/doc/html/latest/spi/spidev.html
+///
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use super::{Error, SpiMode};
+
+const SPI_IOC_MAGIC: u32 = 0x6b;
+
+#[repr(C)]
+struct SpiIocTransfer {
+    tx_buf: u64,
+    rx_buf: u64,
+    len: u32,
+    speed_hz: u32,
+    delay_usecs: u16,
+    bits_per_word: u8,
+    cs_change: u8,
+    tx_nbits: u8,
+    rx_nbits: u8,
+    word_delay_usecs: u8,
+    pad: u8,
+}
+
+const fn iow(nr: u32, size: usize) -> u32 {
+    (1 << 30) | ((size as u32) << 16) | (SPI_IOC_MAGIC << 8) | nr
+}
+
+const SPI_IOC_MESSAGE_1: u32 = iow(0, std::mem::size_of::<SpiIocTransfer>());
+const SPI_IOC_WR_MODE: u32 = iow(1, 1);
+const SPI_IOC_WR_BITS_PER_WORD: u32 = iow(3, 1);
+const SPI_IOC_WR_MAX_SPEED_HZ: u32 = iow(4, 4);
+
+fn ioctl<T>(file: &File, request: u32, arg: *mut T) -> io::Result<()> {
+    match unsafe { libc::ioctl(file.as_raw_fd(), request as _, arg) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(())
+    }
+}
+
+pub struct Spidev {
+    file: File,
+    clock_hz: u32,
+}
+
+impl Spidev {
+    pub fn open(path: &str, mode: SpiMode, clock_hz: u32) -> Result<Spidev, Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)?;
+        // The mode number is CPOL and CPHA as bits, like SPI_CPOL | SPI_CPHA.
+        let mut mode_bits = (mode.cpol() as u8) << 1 | mode.cpha() as u8;
+        let mut bits_per_word = 8u8;
+        let mut speed = clock_hz;
+        ioctl(&file, SPI_IOC_WR_MODE, &mut mode_bits)?;
+        ioctl(&file, SPI_IOC_WR_BITS_PER_WORD, &mut bits_per_word)?;
+        ioctl(&file, SPI_IOC_WR_MAX_SPEED_HZ, &mut speed)?;
+        Ok(Spidev { file, clock_hz })
+    }
+
+    pub fn transfer(&self, tx: &[u8], rx: &mut [u8]) -> Result<(), Error> {
+        if tx.len() != rx.len() {
+            return Err(Error::BufferLength { tx: tx.len(), rx: rx.len() });
+        }
+        let mut transfer = SpiIocTransfer {
+            tx_buf: tx.as_ptr() as u64,
+            rx_buf: rx.as_mut_ptr() as u64,
+            len: tx.len() as u32,
+            speed_hz: self.clock_hz,
+            delay_usecs: 0,
+            bits_per_word: 8,
+            cs_change: 0,
+            tx_nbits: 0,
+            rx_nbits: 0,
+            word_delay_usecs: 0,
+            pad: 0,
+        };
+        Ok(ioctl(&self.file, SPI_IOC_MESSAGE_1, &mut transfer)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::{offset_of, size_of};
+
+    #[test]
+    fn transfer_matches_kernel_layout() {
+        // struct spi_ioc_transfer from include/uapi/linux/spi/spidev.h
+        assert_eq!(size_of::<SpiIocTransfer>(), 32);
+        assert_eq!(offset_of!(SpiIocTransfer, tx_buf), 0);
+        assert_eq!(offset_of!(SpiIocTransfer, rx_buf), 8);
+        assert_eq!(offset_of!(SpiIocTransfer, len), 16);
+        assert_eq!(offset_of!(SpiIocTransfer, speed_hz), 20);
+        assert_eq!(offset_of!(SpiIocTransfer, delay_usecs), 24);
+        assert_eq!(offset_of!(SpiIocTransfer, bits_per_word), 26);
+        assert_eq!(offset_of!(SpiIocTransfer, cs_change), 27);
+        assert_eq!(offset_of!(SpiIocTransfer, tx_nbits), 28);
+        assert_eq!(offset_of!(SpiIocTransfer, rx_nbits), 29);
+        assert_eq!(offset_of!(SpiIocTransfer, word_delay_usecs), 30);
+    }
+
+    #[test]
+    fn encodes_ioctl_requests() {
+        assert_eq!(SPI_IOC_MESSAGE_1, 0x4020_6b00);
+        assert_eq!(SPI_IOC_WR_MODE, 0x4001_6b01);
+        assert_eq!(SPI_IOC_WR_BITS_PER_WORD, 0x4001_6b03);
+        assert_eq!(SPI_IOC_WR_MAX_SPEED_HZ, 0x4004_6b04);
+    }
+}